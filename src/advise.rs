@@ -0,0 +1,190 @@
+//! Grammar optimization advice, see `--advise`.
+//!
+//! peg tries the alternatives of a choice in order, and each one that fails
+//! before the one that matches is wasted work. Choices don't show up in
+//! traces, but their alternatives do: they're consecutive siblings attempted
+//! at the same position, all failing but (maybe) the last.
+
+use crate::{escape_html, input::Trace, spill::SpilledTree, Location, Node, State};
+use std::{collections::HashMap, error::Error, io::Write};
+
+/// Only suggest reordering when at least this many attempts could be saved.
+const MIN_WASTED: usize = 3;
+
+#[derive(Default)]
+struct Choice {
+    /// times the winner matched right after some alternatives failed
+    late_wins: usize,
+    /// times the winner matched with nothing failing before it
+    early_wins: usize,
+    /// failed attempts right before the winner matched, by rule name
+    wasted: HashMap<String, usize>,
+}
+
+/// A suggestion to try `winner` earlier in `parent`.
+pub(crate) struct Advice {
+    pub(crate) parent: String,
+    pub(crate) winner: String,
+    late_wins: usize,
+    early_wins: usize,
+    /// rules that failed before `winner` matched, most wasteful first
+    wasted: Vec<(String, usize)>,
+}
+
+impl Advice {
+    pub(crate) fn total_wasted(&self) -> usize {
+        self.wasted.iter().map(|(_, n)| n).sum()
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        let wasted: Vec<String> = self
+            .wasted
+            .iter()
+            .map(|(name, n)| format!("`{}` ({}×)", name, n))
+            .collect();
+        format!(
+            "in `{}`, `{}` matched {} of {} times after {} failed at the same position, \
+             trying it first would save {} attempts",
+            self.parent,
+            self.winner,
+            self.late_wins,
+            self.late_wins + self.early_wins,
+            wasted.join(", "),
+            self.total_wasted()
+        )
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Advisor {
+    /// keyed by (parent, winner)
+    choices: HashMap<(String, String), Choice>,
+}
+
+impl Advisor {
+    pub(crate) fn add_trace(&mut self, trace: &Trace) -> Result<(), Box<dyn Error>> {
+        match &trace.spilled {
+            Some(tree) => self.add_spilled(trace, tree)?,
+            None => {
+                for child in &trace.root.children {
+                    self.add_node(trace, child);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_node(&mut self, trace: &Trace, node: &Node) {
+        self.add_siblings(trace, &node.rule.name, node.children.iter());
+        for child in &node.children {
+            self.add_node(trace, child);
+        }
+    }
+
+    fn add_spilled(&mut self, trace: &Trace, tree: &SpilledTree) -> Result<(), Box<dyn Error>> {
+        let mut stack = tree.top_level()?;
+        while let Some(loaded) = stack.pop() {
+            let children = tree.children(&loaded)?;
+            self.add_siblings(
+                trace,
+                &loaded.node.rule.name,
+                children.iter().map(|l| &l.node),
+            );
+            stack.extend(children);
+        }
+        Ok(())
+    }
+
+    fn add_siblings<'a>(
+        &mut self,
+        trace: &Trace,
+        parent: &str,
+        siblings: impl Iterator<Item = &'a Node>,
+    ) {
+        // the failures since the last success, if they're all at `loc`
+        let mut failed: Vec<&str> = vec![];
+        let mut loc: Option<Location> = None;
+
+        for node in siblings {
+            if loc != Some(node.rule.loc) {
+                failed.clear();
+                loc = Some(node.rule.loc);
+            }
+            match node.state {
+                State::Failure => failed.push(&node.rule.name),
+                State::Success => {
+                    let choice = self
+                        .choices
+                        .entry((
+                            trace.qualified_rule(parent),
+                            trace.qualified_rule(&node.rule.name),
+                        ))
+                        .or_default();
+                    if failed.is_empty() {
+                        choice.early_wins += 1;
+                    } else {
+                        choice.late_wins += 1;
+                        for name in failed.drain(..) {
+                            *choice.wasted.entry(trace.qualified_rule(name)).or_default() += 1;
+                        }
+                    }
+                    // whatever comes next starts a new choice
+                    loc = None;
+                }
+                State::Unknown => {
+                    failed.clear();
+                    loc = None;
+                }
+            }
+        }
+    }
+
+    /// Choices whose winner usually comes after failing alternatives, the
+    /// most wasteful first.
+    pub(crate) fn advice(&self) -> Vec<Advice> {
+        let mut res: Vec<Advice> = self
+            .choices
+            .iter()
+            .filter(|(_, choice)| choice.late_wins > choice.early_wins)
+            .map(|((parent, winner), choice)| {
+                let mut wasted: Vec<(String, usize)> = choice
+                    .wasted
+                    .iter()
+                    .map(|(name, n)| (name.clone(), *n))
+                    .collect();
+                wasted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                Advice {
+                    parent: parent.clone(),
+                    winner: winner.clone(),
+                    late_wins: choice.late_wins,
+                    early_wins: choice.early_wins,
+                    wasted,
+                }
+            })
+            .filter(|advice| advice.total_wasted() >= MIN_WASTED)
+            .collect();
+        res.sort_by(|a, b| {
+            b.total_wasted()
+                .cmp(&a.total_wasted())
+                .then_with(|| (&a.parent, &a.winner).cmp(&(&b.parent, &b.winner)))
+        });
+        res
+    }
+}
+
+pub(crate) fn write_advice(f: &mut dyn Write, advice: &[Advice]) -> Result<(), Box<dyn Error>> {
+    writeln!(
+        f,
+        r#"<details class="advice"><summary>{} choice ordering suggestions</summary><ul>"#,
+        advice.len()
+    )?;
+    for advice in advice {
+        writeln!(f, "<li>{}</li>", escape_html(&advice.describe()))?;
+    }
+    writeln!(
+        f,
+        "</ul><p>Only reorder alternatives that can't match the same input, \
+         since peg commits to the first one that matches.</p></details>"
+    )?;
+    Ok(())
+}
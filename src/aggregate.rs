@@ -0,0 +1,257 @@
+//! Per-rule statistics over a whole corpus of traces, see `pegviz aggregate`.
+
+use crate::{
+    assets, backfill_next_loc,
+    cluster::{write_clusters, Clusters},
+    escape_html,
+    input::{self, Selection, Source, Trace},
+    mark_partial_matches,
+    run_info::RunInfo,
+    Args, Location, Node, State,
+};
+use argh::FromArgs;
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs::File,
+    io::Write,
+    path::PathBuf,
+};
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "aggregate")]
+/// merge per-rule statistics across many trace logs into a single report.
+/// Options that affect reading (like --spill or --tag-by-input) go before
+/// `aggregate`
+pub(crate) struct AggregateArgs {
+    #[argh(positional)]
+    /// trace files, or directories containing trace files
+    inputs: Vec<PathBuf>,
+
+    #[argh(option, short = 'o')]
+    /// output path, "./aggregate.html" for example
+    output: PathBuf,
+}
+
+#[derive(Default)]
+struct RuleStats {
+    attempts: usize,
+    successes: usize,
+    failures: usize,
+    /// number of traces that attempted the rule
+    traces_attempted: usize,
+    /// number of traces where the rule matched at least once
+    traces_matched: usize,
+    /// the position the rule was attempted at the most times, in any trace
+    hotspot: Option<Hotspot>,
+}
+
+struct Hotspot {
+    attempts: usize,
+    trace: String,
+    loc: Location,
+}
+
+#[derive(Default)]
+struct Corpus {
+    traces: usize,
+    /// keyed by rule name, qualified with the grammar when there's one
+    rules: BTreeMap<String, RuleStats>,
+}
+
+/// Statistics for a single trace, before they're merged into the corpus'.
+#[derive(Default)]
+struct TraceStats {
+    /// (attempts, successes, failures)
+    rules: HashMap<String, (usize, usize, usize)>,
+    positions: HashMap<(String, Location), usize>,
+}
+
+impl TraceStats {
+    fn add(&mut self, name: String, node: &Node) {
+        let counts = self.rules.entry(name.clone()).or_default();
+        counts.0 += 1;
+        match node.state {
+            State::Success => counts.1 += 1,
+            State::Failure => counts.2 += 1,
+            State::Unknown => {}
+        }
+        *self.positions.entry((name, node.rule.loc)).or_default() += 1;
+    }
+}
+
+impl Corpus {
+    fn add_trace(&mut self, args: &Args, trace: &Trace) -> Result<(), Box<dyn Error>> {
+        let mut stats = TraceStats::default();
+        match &trace.spilled {
+            Some(tree) => tree.walk(0, &mut |node, _, _| {
+                stats.add(trace.qualified_rule(&node.rule.name), node)
+            })?,
+            None => {
+                fn walk(node: &Node, f: &mut dyn FnMut(&Node)) {
+                    for child in &node.children {
+                        f(child);
+                        walk(child, f);
+                    }
+                }
+                walk(&trace.root, &mut |node| {
+                    stats.add(trace.qualified_rule(&node.rule.name), node)
+                });
+            }
+        }
+
+        let label = match &trace.source {
+            Some(source) => format!("{} (trace #{})", source, trace.index + 1),
+            None => format!("trace #{}", trace.index + 1),
+        };
+        self.traces += 1;
+        for (name, (attempts, successes, failures)) in stats.rules {
+            let rule = self.rules.entry(name).or_default();
+            rule.attempts += attempts;
+            rule.successes += successes;
+            rule.failures += failures;
+            rule.traces_attempted += 1;
+            if successes > 0 {
+                rule.traces_matched += 1;
+            }
+        }
+        for ((name, loc), attempts) in stats.positions {
+            let rule = self.rules.get_mut(&name).unwrap();
+            if rule.hotspot.as_ref().is_none_or(|h| attempts > h.attempts) {
+                rule.hotspot = Some(Hotspot {
+                    attempts,
+                    trace: label.clone(),
+                    loc: args.display_loc(&trace.input, loc),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn run(args: &Args, agg: &AggregateArgs) -> Result<(), Box<dyn Error>> {
+    let mut corpus = Corpus::default();
+    let mut clusters = Clusters::default();
+    let mut selection = Selection::new(args);
+    let mut traces = vec![];
+    let sources = Source::expand(&agg.inputs)?;
+    let mut hashes = vec![];
+    for source in &sources {
+        if selection.is_exhausted() {
+            break;
+        }
+        hashes.push(input::read_traces(
+            source,
+            args,
+            &mut selection,
+            &mut traces,
+            None,
+        )?);
+        // only one source's traces are kept in memory at a time
+        for mut trace in traces.drain(..) {
+            if trace.spilled.is_none() {
+                backfill_next_loc(&mut trace.root, None);
+                mark_partial_matches(&mut trace.root);
+            }
+            corpus.add_trace(args, &trace)?;
+            clusters.add_trace(args, &trace, false)?;
+        }
+    }
+
+    let mut out = File::create(&agg.output)?;
+    let assets = assets::tags(args.assets_dir.as_deref(), &agg.output, false)?;
+    write_report(
+        &mut out,
+        &assets,
+        &RunInfo::collect(&sources, &hashes)?,
+        &corpus,
+        &clusters,
+    )?;
+    status!(
+        "= pegviz aggregated {} rules over {} traces to {}",
+        corpus.rules.len(),
+        corpus.traces,
+        agg.output.display()
+    );
+    Ok(())
+}
+
+fn write_report(
+    f: &mut dyn Write,
+    // the stylesheet's tag
+    assets: &str,
+    run_info: &RunInfo,
+    corpus: &Corpus,
+    clusters: &Clusters,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(
+        f,
+        r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+    <meta charset="utf-8"/>
+        {assets}
+    </head>
+    <body>
+    <h1>{traces} traces</h1>"#,
+        assets = assets,
+        traces = corpus.traces,
+    )?;
+    run_info.write_html(f)?;
+
+    let never_matched: Vec<&str> = corpus
+        .rules
+        .iter()
+        .filter(|(_, rule)| rule.traces_matched == 0)
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !never_matched.is_empty() {
+        writeln!(
+            f,
+            r#"<p class="never-matched">Attempted but never matched: <code>{}</code></p>"#,
+            escape_html(&never_matched.join(", "))
+        )?;
+    }
+
+    write_clusters(f, &clusters.largest(50))?;
+
+    let mut rules: Vec<(&String, &RuleStats)> = corpus.rules.iter().collect();
+    rules.sort_by(|a, b| b.1.attempts.cmp(&a.1.attempts).then_with(|| a.0.cmp(b.0)));
+
+    writeln!(
+        f,
+        r#"<table class="aggregate">
+<tr><th>rule</th><th>attempts</th><th>successes</th><th>failures</th><th>failure rate</th><th>attempted in</th><th>matched in</th><th>hotspot</th></tr>"#
+    )?;
+    for (name, rule) in rules {
+        let percent = |n: usize, of: usize| (n * 100).checked_div(of).unwrap_or(0);
+        write!(
+            f,
+            "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}%</td><td>{}%</td><td>{}%</td>",
+            escape_html(name),
+            rule.attempts,
+            rule.successes,
+            rule.failures,
+            percent(rule.failures, rule.attempts),
+            percent(rule.traces_attempted, corpus.traces),
+            percent(rule.traces_matched, corpus.traces),
+        )?;
+        match &rule.hotspot {
+            Some(hotspot) if hotspot.attempts > 1 => writeln!(
+                f,
+                "<td>{}× at {} in {}</td></tr>",
+                hotspot.attempts,
+                hotspot.loc,
+                escape_html(&hotspot.trace)
+            )?,
+            _ => writeln!(f, "<td></td></tr>")?,
+        }
+    }
+    writeln!(
+        f,
+        r#"</table>
+    </body>
+</html>"#
+    )?;
+    Ok(())
+}
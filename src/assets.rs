@@ -0,0 +1,95 @@
+//! The stylesheet and script reports need, inlined or, with `--assets-dir`,
+//! written once and linked to.
+//!
+//! Shared assets are named after a hash of their contents, so that reports
+//! made by different versions of pegviz can share a directory.
+
+use crate::expect::input_hash;
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+const STYLE: &str = include_str!("style.css");
+const SCRIPT: &str = include_str!("index.js");
+
+/// The `<style>` (and `<script>`, with `script`) tags for a report written
+/// to `output`.
+pub(crate) fn tags(dir: Option<&Path>, output: &Path, script: bool) -> io::Result<String> {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => {
+            let mut tags = format!("<style>{}</style>", STYLE);
+            if script {
+                tags.push_str(&format!("\n<script>{}</script>", SCRIPT));
+            }
+            return Ok(tags);
+        }
+    };
+
+    let mut tags = format!(
+        r#"<link rel="stylesheet" href="{}">"#,
+        share(dir, output, "css", STYLE)?
+    );
+    if script {
+        tags.push_str(&format!(
+            r#"
+<script src="{}"></script>"#,
+            share(dir, output, "js", SCRIPT)?
+        ));
+    }
+    Ok(tags)
+}
+
+/// Writes `contents` to `dir` unless it's there already, returns its URL
+/// relative to `output`.
+fn share(dir: &Path, output: &Path, extension: &str, contents: &str) -> io::Result<String> {
+    fs::create_dir_all(dir)?;
+    let name = format!("pegviz-{:016x}.{}", input_hash(contents), extension);
+    let path = dir.join(&name);
+    if !path.exists() {
+        fs::write(&path, contents)?;
+        status!("= pegviz wrote {}", path.display());
+    }
+
+    let report_dir = match output.parent() {
+        Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let from = fs::canonicalize(report_dir)?;
+    let to = fs::canonicalize(&path)?;
+    Ok(url(&relative(&from, &to)))
+}
+
+/// `to` relative to the directory `from`, both absolute.
+fn relative(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut res = PathBuf::new();
+    for _ in common..from.len() {
+        res.push("..");
+    }
+    for component in &to[common..] {
+        res.push(component);
+    }
+    res
+}
+
+fn url(path: &Path) -> String {
+    let mut res = String::new();
+    for (i, component) in path.components().enumerate() {
+        if i > 0 {
+            res.push('/');
+        }
+        for b in component.as_os_str().to_string_lossy().bytes() {
+            match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    res.push(b as char)
+                }
+                _ => res.push_str(&format!("%{:02X}", b)),
+            }
+        }
+    }
+    res
+}
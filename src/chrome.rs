@@ -0,0 +1,108 @@
+//! Chrome trace-event output, see `--format chrome`, to look at big parses
+//! in `chrome://tracing` or Perfetto.
+//!
+//! Logs don't say how long anything took, so time is the order of events:
+//! every attempt and every outcome in the log is one microsecond, and a rule
+//! lasts from its attempt to its outcome. Each trace is a process of its own.
+
+use crate::{input::Trace, json, Args, Node, State};
+use std::{error::Error, io::Write};
+
+pub(crate) fn write_chrome(
+    f: &mut dyn Write,
+    args: &Args,
+    traces: &[Trace],
+) -> Result<(), Box<dyn Error>> {
+    let mut events = vec![];
+    for trace in traces {
+        let pid = trace.index + 1;
+        let name = match &trace.source {
+            Some(source) => format!("{} — {}", trace.root.rule.name, source),
+            None => trace.root.rule.name.clone(),
+        };
+        events.push(format!(
+            r#"{{"name": "process_name", "ph": "M", "pid": {}, "tid": 1, "args": {{"name": {}}}}}"#,
+            pid,
+            json::string(&name)
+        ));
+        let mut clock = 0;
+        let loaded;
+        let children = match &trace.spilled {
+            Some(tree) => {
+                loaded = tree.load_all()?;
+                &loaded
+            }
+            None => &trace.root.children,
+        };
+        for child in children {
+            add_events(args, trace, child, pid, &mut clock, &mut events);
+        }
+    }
+
+    writeln!(f, "{{")?;
+    writeln!(
+        f,
+        r#"  "otherData": {{"generator": {}}},"#,
+        json::string(&format!("pegviz {}", env!("CARGO_PKG_VERSION")))
+    )?;
+    writeln!(f, r#"  "traceEvents": ["#)?;
+    writeln!(f, "    {}", events.join(",\n    "))?;
+    writeln!(f, "  ]")?;
+    writeln!(f, "}}")?;
+    Ok(())
+}
+
+/// Adds a complete event for `node` and its children, `--hide` applies.
+fn add_events(
+    args: &Args,
+    trace: &Trace,
+    node: &Node,
+    pid: usize,
+    clock: &mut usize,
+    events: &mut Vec<String>,
+) {
+    if args.should_hide(trace, node) {
+        return;
+    }
+    let input = &trace.input;
+    let ts = *clock;
+    *clock += 1;
+    let index = events.len();
+    events.push(String::new());
+    for child in &node.children {
+        add_events(args, trace, child, pid, clock, events);
+    }
+    *clock += 1;
+
+    let state = match node.state {
+        State::Success => "success",
+        State::Failure if node.partial_match => "partial-match",
+        State::Failure => "failure",
+        State::Unknown => "unknown",
+    };
+    let start = node.rule.loc.pos(input);
+    let end = match (&node.state, node.rule.next_loc) {
+        (State::Success, Some(next)) => next.pos(input).max(start),
+        _ => start,
+    };
+    let at = match node.rule.next_loc {
+        Some(next) => format!(
+            "{}–{}",
+            args.display_loc(input, node.rule.loc),
+            args.display_loc(input, next)
+        ),
+        None => args.display_loc(input, node.rule.loc).to_string(),
+    };
+    let matched: String = input[start..end].chars().take(80).collect();
+    events[index] = format!(
+        r#"{{"name": {}, "cat": "{}", "ph": "X", "ts": {}, "dur": {}, "pid": {}, "tid": 1, "args": {{"at": {}, "matched": {}, "log_line": {}}}}}"#,
+        json::string(&node.rule.name),
+        state,
+        ts,
+        *clock - ts,
+        pid,
+        json::string(&at),
+        json::string(&matched),
+        node.log_line
+    );
+}
@@ -0,0 +1,98 @@
+//! Chunked reports, for traces too big for the browser to take in at once.
+//! With `--chunked`, runs of sibling subtrees under big nodes are written to
+//! scripts next to the report, in `report.html.chunks/`, and the page only
+//! has placeholders for them. Once it's up, its script loads the chunks one
+//! after the other and puts them in place.
+
+use crate::json;
+use std::{
+    cell::Cell,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// How many nodes go in a chunk, at most, unless a single subtree has more.
+pub(crate) const NODES: usize = 5_000;
+
+pub(crate) struct Chunks {
+    dir: PathBuf,
+    /// the directory's name, which is where it is from the report
+    name: String,
+    count: Cell<usize>,
+}
+
+/// Siblings on their way to a chunk, written out when it's full.
+#[derive(Default)]
+pub(crate) struct Run {
+    pub(crate) html: Vec<u8>,
+    nodes: usize,
+}
+
+impl Chunks {
+    /// The chunks of the report written to `output`. Chunks left there by an
+    /// earlier run are removed.
+    pub(crate) fn new(output: &Path) -> io::Result<Chunks> {
+        let mut name = output.file_name().unwrap_or_default().to_os_string();
+        name.push(".chunks");
+        let dir = output.with_file_name(&name);
+        fs::create_dir_all(&dir)?;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "js") {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(Chunks {
+            dir,
+            name: name.to_string_lossy().into_owned(),
+            count: Cell::new(0),
+        })
+    }
+
+    /// Makes room in `run` for a subtree of `nodes` nodes, writing out what
+    /// it has first when that would make it too big.
+    pub(crate) fn add(&self, f: &mut dyn Write, run: &mut Run, nodes: usize) -> io::Result<()> {
+        if run.nodes > 0 && run.nodes + nodes > NODES {
+            self.flush(f, run)?;
+        }
+        run.nodes += nodes;
+        Ok(())
+    }
+
+    /// Writes what `run` has to a chunk, and the chunk's placeholder to `f`.
+    pub(crate) fn flush(&self, f: &mut dyn Write, run: &mut Run) -> io::Result<()> {
+        run.nodes = 0;
+        if run.html.is_empty() {
+            return Ok(());
+        }
+        let n = self.count.get() + 1;
+        self.count.set(n);
+        let html = String::from_utf8_lossy(&run.html);
+        fs::write(
+            self.dir.join(format!("{}.js", n)),
+            format!("pegvizChunk({}, {});\n", n, json::string(&html)),
+        )?;
+        run.html.clear();
+        write!(
+            f,
+            r#"<template class="chunk" data-chunk="{}"></template>"#,
+            n
+        )
+    }
+
+    /// Writes where the chunks are and how many there are, for the script.
+    pub(crate) fn write_index(&self, f: &mut dyn Write) -> io::Result<()> {
+        status!(
+            "= pegviz wrote {} chunks to {}",
+            self.count.get(),
+            self.dir.display()
+        );
+        writeln!(
+            f,
+            r#"<div id="chunks" data-dir="{}" data-count="{}" hidden></div>"#,
+            crate::escape_html(&self.name),
+            self.count.get()
+        )
+    }
+}
@@ -0,0 +1,179 @@
+//! Groups similar failures together, see `--clusters`.
+//!
+//! Rules failing in PEG is business as usual: that's how alternatives get
+//! tried. What's telling is a rule failing after matching part of its input,
+//! or failing where the parser reported its error. Those get grouped by rule
+//! and by the shape of the input they failed at (letters, digits and spaces
+//! collapsed into classes), so a problem that shows up everywhere ("string
+//! escapes never match") stands out from one-off failures.
+
+use crate::{escape_html, input::Trace, is_at_error, Args, Location, Node, State};
+use std::{collections::HashMap, error::Error, io::Write};
+
+/// How many chars of input make up the shape of a failure.
+const SHAPE_LEN: usize = 8;
+/// How many examples to keep for each cluster.
+const EXAMPLES: usize = 5;
+
+pub(crate) struct Example {
+    /// the node's id, when the report has that node
+    pub(crate) id: Option<usize>,
+    pub(crate) trace: String,
+    /// as shown in the report
+    pub(crate) loc: Location,
+}
+
+#[derive(Default)]
+pub(crate) struct Cluster {
+    pub(crate) count: usize,
+    pub(crate) traces: usize,
+    pub(crate) examples: Vec<Example>,
+    /// index of the last trace that added to the cluster
+    last_trace: Option<usize>,
+}
+
+#[derive(Default)]
+pub(crate) struct Clusters {
+    /// keyed by (rule, shape)
+    clusters: HashMap<(String, String), Cluster>,
+}
+
+impl Clusters {
+    /// Adds the notable failures of `trace`. With `ids`, nodes are assumed
+    /// to be numbered already, and examples link to them.
+    pub(crate) fn add_trace(
+        &mut self,
+        args: &Args,
+        trace: &Trace,
+        ids: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        match &trace.spilled {
+            Some(tree) => tree.walk(trace.root.id + 1, &mut |node, id, _| {
+                self.add(args, trace, node, if ids { Some(id) } else { None })
+            })?,
+            None => {
+                fn walk(
+                    clusters: &mut Clusters,
+                    args: &Args,
+                    trace: &Trace,
+                    node: &Node,
+                    ids: bool,
+                ) {
+                    for child in &node.children {
+                        clusters.add(args, trace, child, if ids { Some(child.id) } else { None });
+                        walk(clusters, args, trace, child, ids);
+                    }
+                }
+                walk(self, args, trace, &trace.root, ids);
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, args: &Args, trace: &Trace, node: &Node, id: Option<usize>) {
+        if !matches!(node.state, State::Failure)
+            || !(node.partial_match || is_at_error(trace, node))
+        {
+            return;
+        }
+        let name = trace.qualified_rule(&node.rule.name);
+        let shape = shape(&trace.input[node.rule.loc.pos(&trace.input)..]);
+        let cluster = self.clusters.entry((name, shape)).or_default();
+        cluster.count += 1;
+        if cluster.last_trace != Some(trace.index) {
+            cluster.last_trace = Some(trace.index);
+            cluster.traces += 1;
+        }
+        if cluster.examples.len() < EXAMPLES {
+            cluster.examples.push(Example {
+                id,
+                trace: match &trace.source {
+                    Some(source) => format!("{} (trace #{})", source, trace.index + 1),
+                    None => format!("trace #{}", trace.index + 1),
+                },
+                loc: args.display_loc(&trace.input, node.rule.loc),
+            });
+        }
+    }
+
+    /// The `n` largest clusters, as `(rule, shape, cluster)`.
+    pub(crate) fn largest(&self, n: usize) -> Vec<(&str, &str, &Cluster)> {
+        let mut res: Vec<(&str, &str, &Cluster)> = self
+            .clusters
+            .iter()
+            .map(|((rule, shape), cluster)| (rule.as_str(), shape.as_str(), cluster))
+            .collect();
+        res.sort_by(|a, b| {
+            b.2.count
+                .cmp(&a.2.count)
+                .then_with(|| (a.0, a.1).cmp(&(b.0, b.1)))
+        });
+        res.truncate(n);
+        res
+    }
+}
+
+/// The first few chars of `input` (up to the end of the line), with
+/// letters, digits and whitespace collapsed into `a`, `0` and `␣` so that
+/// `"foo\n` and `"bar\t` look the same. Punctuation is kept, it's usually
+/// what the grammar cares about.
+fn shape(input: &str) -> String {
+    let mut res = String::new();
+    for c in input.chars().take_while(|&c| c != '\n').take(SHAPE_LEN) {
+        let class = if c.is_alphabetic() {
+            'a'
+        } else if c.is_numeric() {
+            '0'
+        } else if c.is_whitespace() {
+            '␣'
+        } else {
+            c
+        };
+        // runs of the same class are one token
+        if "a0␣".contains(class) && res.ends_with(class) {
+            continue;
+        }
+        res.push(class);
+    }
+    if res.is_empty() {
+        res.push_str("end of line");
+    }
+    res
+}
+
+pub(crate) fn write_clusters(
+    f: &mut dyn Write,
+    clusters: &[(&str, &str, &Cluster)],
+) -> Result<(), Box<dyn Error>> {
+    writeln!(
+        f,
+        r#"<details class="clusters"><summary>{} largest failure clusters</summary><table>
+<tr><th>rule</th><th>failing at</th><th>failures</th><th>traces</th><th>examples</th></tr>"#,
+        clusters.len()
+    )?;
+    for (rule, shape, cluster) in clusters {
+        write!(
+            f,
+            "<tr><td><code>{}</code></td><td><code>{}</code></td><td>{}</td><td>{}</td><td>",
+            escape_html(rule),
+            escape_html(shape),
+            cluster.count,
+            cluster.traces
+        )?;
+        for example in &cluster.examples {
+            let label = format!("{} at {}", example.trace, example.loc);
+            match example.id {
+                Some(id) => write!(
+                    f,
+                    r##"<a href="#node-{}">{}</a> "##,
+                    id,
+                    escape_html(&label)
+                )?,
+                None => write!(f, "{}; ", escape_html(&label))?,
+            }
+        }
+        writeln!(f, "</td></tr>")?;
+    }
+    writeln!(f, "</table></details>")?;
+    Ok(())
+}
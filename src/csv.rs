@@ -0,0 +1,93 @@
+//! Per-rule statistics as CSV, see `--format csv`, for charting how a
+//! grammar behaves across runs in a spreadsheet.
+
+use crate::{input::Trace, Node, State};
+use std::{collections::BTreeMap, error::Error, io::Write};
+
+#[derive(Default)]
+pub(crate) struct RuleStats {
+    pub(crate) attempts: usize,
+    pub(crate) successes: usize,
+    pub(crate) failures: usize,
+    pub(crate) cache_hits: usize,
+    /// deepest the rule was attempted at, top-level rules being at 1
+    pub(crate) max_depth: usize,
+    /// input matched by all of the rule's successes, in bytes
+    pub(crate) matched_bytes: usize,
+}
+
+/// Writes one row per rule, over all traces.
+pub(crate) fn write_csv(f: &mut dyn Write, traces: &[Trace]) -> Result<(), Box<dyn Error>> {
+    let rules = rule_stats(traces)?;
+    writeln!(
+        f,
+        "rule,attempts,successes,failures,cache_hits,max_depth,matched_bytes"
+    )?;
+    for (name, rule) in &rules {
+        writeln!(
+            f,
+            "{},{},{},{},{},{},{}",
+            field(name),
+            rule.attempts,
+            rule.successes,
+            rule.failures,
+            rule.cache_hits,
+            rule.max_depth,
+            rule.matched_bytes
+        )?;
+    }
+    Ok(())
+}
+
+/// The statistics of each rule, over all traces. Rules are qualified with
+/// their grammar when there's one, like in `pegviz aggregate`.
+pub(crate) fn rule_stats(traces: &[Trace]) -> Result<BTreeMap<String, RuleStats>, Box<dyn Error>> {
+    let mut rules: BTreeMap<String, RuleStats> = BTreeMap::new();
+    for trace in traces {
+        let mut add = |node: &Node, depth: usize| {
+            let rule = rules
+                .entry(trace.qualified_rule(&node.rule.name))
+                .or_default();
+            rule.attempts += 1;
+            rule.max_depth = rule.max_depth.max(depth);
+            match node.state {
+                State::Success => {
+                    rule.successes += 1;
+                    if let Some(next) = node.rule.next_loc {
+                        let start = node.rule.loc.pos(&trace.input);
+                        rule.matched_bytes += next.pos(&trace.input).saturating_sub(start);
+                    }
+                }
+                State::Failure => rule.failures += 1,
+                State::Unknown => {}
+            }
+        };
+        fn walk(nodes: &[Node], depth: usize, f: &mut dyn FnMut(&Node, usize)) {
+            for node in nodes {
+                f(node, depth);
+                walk(&node.children, depth + 1, f);
+            }
+        }
+        match &trace.spilled {
+            // streamed, so `--spill` keeps memory down with the sidebar too
+            Some(tree) => tree.walk(0, &mut |node, _, depth| add(node, depth))?,
+            None => walk(&trace.root.children, 1, &mut add),
+        }
+        for (name, hits) in &trace.cache_hits {
+            rules
+                .entry(trace.qualified_rule(name))
+                .or_default()
+                .cache_hits += hits;
+        }
+    }
+    Ok(rules)
+}
+
+/// Quotes `s` if it needs to be.
+fn field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
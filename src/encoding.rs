@@ -0,0 +1,324 @@
+//! Encoding detection for trace logs. Redirecting output to a file on
+//! Windows easily produces UTF-16 (PowerShell's `>` does) or UTF-8 with a
+//! byte order mark, neither of which `BufRead::lines` copes with, and
+//! leaves `\r`s around, see `clean_line`.
+
+use std::io::{self, BufRead, BufReader, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// Sniffs the encoding of `reader` from its first bytes, and returns a
+/// reader that yields UTF-8 without a byte order mark.
+pub(crate) fn decode(mut reader: Box<dyn BufRead>) -> io::Result<(Encoding, Box<dyn BufRead>)> {
+    let head = reader.fill_buf()?;
+    let (encoding, bom) = match head {
+        [0xef, 0xbb, 0xbf, ..] => (Encoding::Utf8, 3),
+        [0xff, 0xfe, ..] => (Encoding::Utf16Le, 2),
+        [0xfe, 0xff, ..] => (Encoding::Utf16Be, 2),
+        // no byte order mark, but ASCII text in UTF-16 is hard to miss
+        [a, 0, b, 0, ..] if *a != 0 && *b != 0 => (Encoding::Utf16Le, 0),
+        [0, a, 0, b, ..] if *a != 0 && *b != 0 => (Encoding::Utf16Be, 0),
+        _ => (Encoding::Utf8, 0),
+    };
+    reader.consume(bom);
+    Ok(match encoding {
+        Encoding::Utf8 => (encoding, reader),
+        _ => (
+            encoding,
+            Box::new(BufReader::new(Utf16Reader {
+                inner: reader,
+                big_endian: encoding == Encoding::Utf16Be,
+                pending: vec![],
+                surrogate: None,
+            })),
+        ),
+    })
+}
+
+/// What to do with bytes that aren't valid UTF-8, see `--utf8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Utf8Errors {
+    /// replace them with U+FFFD, the replacement character
+    Lossy,
+    Strict,
+}
+
+impl std::str::FromStr for Utf8Errors {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lossy" => Ok(Utf8Errors::Lossy),
+            "strict" => Ok(Utf8Errors::Strict),
+            _ => Err(format!(
+                "unknown UTF-8 handling {:?}, expected lossy or strict",
+                s
+            )),
+        }
+    }
+}
+
+/// Like `BufRead::lines`, but invalid UTF-8 (binary junk in a parser's
+/// input, say) doesn't have to be an error.
+pub(crate) struct Lines {
+    reader: Box<dyn BufRead>,
+    errors: Utf8Errors,
+    buf: Vec<u8>,
+    line: usize,
+    /// how many lines had invalid UTF-8 replaced
+    pub(crate) replaced: usize,
+}
+
+impl Lines {
+    pub(crate) fn new(reader: Box<dyn BufRead>, errors: Utf8Errors) -> Self {
+        Lines {
+            reader,
+            errors,
+            buf: vec![],
+            line: 0,
+            replaced: 0,
+        }
+    }
+
+    /// The bytes of the line last returned, before they were decoded and
+    /// without their `\n`.
+    pub(crate) fn raw(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub(crate) fn into_inner(self) -> Box<dyn BufRead> {
+        self.reader
+    }
+}
+
+impl Iterator for Lines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buf.clear();
+        match self.reader.read_until(b'\n', &mut self.buf) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        self.line += 1;
+        if self.buf.last() == Some(&b'\n') {
+            self.buf.pop();
+        }
+        match std::str::from_utf8(&self.buf) {
+            Ok(line) => Some(Ok(line.to_string())),
+            Err(_) if self.errors == Utf8Errors::Lossy => {
+                self.replaced += 1;
+                Some(Ok(String::from_utf8_lossy(&self.buf).into_owned()))
+            }
+            Err(e) => Some(Err(invalid(&format!(
+                "invalid UTF-8 on line {} ({}), --utf8 lossy would replace it",
+                self.line, e
+            )))),
+        }
+    }
+}
+
+/// Strips what Windows tools leave around a line read with `BufRead::lines`:
+/// carriage returns, more than one when line endings were converted twice,
+/// and byte order marks, when logs were concatenated.
+pub(crate) fn clean_line(line: &mut String) {
+    line.truncate(line.trim_end_matches('\r').len());
+    if line.starts_with('\u{feff}') {
+        line.drain(..'\u{feff}'.len_utf8());
+    }
+}
+
+/// Converts UTF-16 to UTF-8 as it's read, so large logs don't need to fit in
+/// memory twice.
+struct Utf16Reader {
+    inner: Box<dyn BufRead>,
+    big_endian: bool,
+    /// converted bytes that didn't fit in the caller's buffer
+    pending: Vec<u8>,
+    /// a high surrogate waiting for its other half
+    surrogate: Option<u16>,
+}
+
+impl Utf16Reader {
+    /// Converts the next chunk of input into `pending`, returns false at the
+    /// end of input.
+    fn convert(&mut self) -> io::Result<bool> {
+        let buf = self.inner.fill_buf()?;
+        if buf.is_empty() {
+            if self.surrogate.take().is_some() {
+                return Err(invalid("unpaired surrogate at the end of the input"));
+            }
+            return Ok(false);
+        }
+        if buf.len() == 1 {
+            // the other byte of the unit may come with the next read
+            let first = buf[0];
+            self.inner.consume(1);
+            let second = match self.inner.fill_buf()?.first() {
+                Some(&b) => b,
+                None => return Err(invalid("odd number of bytes in UTF-16 input")),
+            };
+            self.inner.consume(1);
+            self.push_unit([first, second])?;
+            return Ok(true);
+        }
+
+        let len = buf.len() & !1;
+        let units: Vec<[u8; 2]> = buf[..len].chunks(2).map(|c| [c[0], c[1]]).collect();
+        self.inner.consume(len);
+        for unit in units {
+            self.push_unit(unit)?;
+        }
+        Ok(true)
+    }
+
+    fn push_unit(&mut self, bytes: [u8; 2]) -> io::Result<()> {
+        let unit = if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        };
+        let c = match (self.surrogate.take(), unit) {
+            (None, 0xd800..=0xdbff) => {
+                self.surrogate = Some(unit);
+                return Ok(());
+            }
+            (Some(high), 0xdc00..=0xdfff) => {
+                let c = 0x10000 + ((high as u32 - 0xd800) << 10) + (unit as u32 - 0xdc00);
+                char::from_u32(c).unwrap()
+            }
+            (None, 0xdc00..=0xdfff) | (Some(_), _) => {
+                return Err(invalid("unpaired surrogate in UTF-16 input"))
+            }
+            (None, unit) => char::from_u32(unit as u32).unwrap(),
+        };
+        let mut utf8 = [0; 4];
+        self.pending
+            .extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+        Ok(())
+    }
+}
+
+impl Read for Utf16Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            if !self.convert()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(s: &str, big_endian: bool) -> Vec<u8> {
+        s.encode_utf16()
+            .flat_map(|unit| {
+                if big_endian {
+                    unit.to_be_bytes()
+                } else {
+                    unit.to_le_bytes()
+                }
+            })
+            .collect()
+    }
+
+    fn read_all(mut reader: impl Read) -> io::Result<String> {
+        let mut out = String::new();
+        reader.read_to_string(&mut out)?;
+        Ok(out)
+    }
+
+    /// Decodes `data` as pegviz reads a log.
+    fn decoded(data: Vec<u8>) -> io::Result<(Encoding, String)> {
+        let (encoding, reader) = decode(Box::new(io::Cursor::new(data)))?;
+        Ok((encoding, read_all(reader)?))
+    }
+
+    /// Converts `data` from an inner reader that hands out `chunk` bytes at
+    /// a time, so that code units (and surrogate pairs) are split across
+    /// reads.
+    fn chunked(data: Vec<u8>, big_endian: bool, chunk: usize) -> io::Result<String> {
+        read_all(Utf16Reader {
+            inner: Box::new(BufReader::with_capacity(chunk, io::Cursor::new(data))),
+            big_endian,
+            pending: vec![],
+            surrogate: None,
+        })
+    }
+
+    const TEXT: &str = "[PEG_INPUT_START]\r\nnaïve 🦀 input\r\n";
+
+    #[test]
+    fn utf16_with_and_without_bom() {
+        for &(big_endian, encoding) in &[(false, Encoding::Utf16Le), (true, Encoding::Utf16Be)] {
+            let with_bom = encode(&format!("\u{feff}{}", TEXT), big_endian);
+            assert_eq!(decoded(with_bom).unwrap(), (encoding, TEXT.to_string()));
+            let without_bom = encode(TEXT, big_endian);
+            assert_eq!(decoded(without_bom).unwrap(), (encoding, TEXT.to_string()));
+        }
+        assert_eq!(
+            decoded(TEXT.as_bytes().to_vec()).unwrap(),
+            (Encoding::Utf8, TEXT.to_string())
+        );
+        let mut utf8_bom = "\u{feff}".as_bytes().to_vec();
+        utf8_bom.extend_from_slice(TEXT.as_bytes());
+        assert_eq!(
+            decoded(utf8_bom).unwrap(),
+            (Encoding::Utf8, TEXT.to_string())
+        );
+    }
+
+    #[test]
+    fn utf16_split_across_reads() {
+        for &big_endian in &[false, true] {
+            let data = encode(TEXT, big_endian);
+            // chunks of 1 take the one-byte path for every unit, odd ones
+            // split units across reads
+            for chunk in 1..=5 {
+                assert_eq!(
+                    chunked(data.clone(), big_endian, chunk).unwrap(),
+                    TEXT,
+                    "big endian {}, chunks of {}",
+                    big_endian,
+                    chunk
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_utf16_is_an_error() {
+        for chunk in 1..=4 {
+            // an odd trailing byte
+            let mut data = encode("ab", false);
+            data.push(b'c');
+            assert!(chunked(data, false, chunk).is_err(), "chunks of {}", chunk);
+
+            // a high surrogate, cut off or followed by something else
+            let crab = encode("🦀", false);
+            assert!(chunked(crab[..2].to_vec(), false, chunk).is_err());
+            let mut data = crab[..2].to_vec();
+            data.extend(encode("a", false));
+            assert!(chunked(data, false, chunk).is_err());
+            // a low surrogate on its own
+            assert!(chunked(crab[2..].to_vec(), false, chunk).is_err());
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Expected outcomes, see `--expect`.
+//!
+//! An expectations file has one trace per line: either its number (as in
+//! "Trace #3") or the hash of its input (`hash:…`, shown when hovering the
+//! trace's header), then `success` or `failure`. Empty lines and lines
+//! starting with `#` are ignored:
+//!
+//! ```text
+//! # the first two cases are valid, the third one isn't
+//! 1 success
+//! 2 success
+//! hash:6c62272e07bb0142 failure
+//! ```
+
+use crate::input::Trace;
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+#[derive(Default)]
+pub(crate) struct Expectations {
+    /// keyed by trace index, starting at 0
+    by_index: HashMap<usize, bool>,
+    by_hash: HashMap<u64, bool>,
+}
+
+impl Expectations {
+    pub(crate) fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut res = Self::default();
+        let text = fs::read_to_string(path)?;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = || {
+                format!(
+                    "pegviz: {}:{}: expected `<trace number or hash:…> <success|failure>`, got {:?}",
+                    path.display(),
+                    i + 1,
+                    line
+                )
+            };
+
+            let mut words = line.split_whitespace();
+            let (key, outcome) = match (words.next(), words.next(), words.next()) {
+                (Some(key), Some(outcome), None) => (key, outcome),
+                _ => return Err(bad_line().into()),
+            };
+            let success = match outcome {
+                "success" | "pass" => true,
+                "failure" | "fail" => false,
+                _ => return Err(bad_line().into()),
+            };
+            if let Some(hash) = key.strip_prefix("hash:") {
+                let hash = u64::from_str_radix(hash, 16).map_err(|_| bad_line())?;
+                res.by_hash.insert(hash, success);
+            } else {
+                let number: usize = key
+                    .trim_start_matches('#')
+                    .parse()
+                    .map_err(|_| bad_line())?;
+                if number == 0 {
+                    return Err(bad_line().into());
+                }
+                res.by_index.insert(number - 1, success);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Whether `trace` is expected to succeed, if the file says anything
+    /// about it. Hashes win over numbers, as they survive traces being
+    /// added or removed.
+    pub(crate) fn get(&self, trace: &Trace) -> Option<bool> {
+        self.by_hash
+            .get(&trace.input_hash)
+            .or_else(|| self.by_index.get(&trace.index))
+            .copied()
+    }
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is guaranteed to stay the
+/// same across Rust versions, so hashes can be written down.
+pub(crate) fn input_hash(input: &str) -> u64 {
+    fnv1a(FNV_OFFSET, input.as_bytes())
+}
+
+/// The FNV-1a hash of nothing, to start from.
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Continues the FNV-1a `hash` with `bytes`, for input that comes in
+/// pieces.
+pub(crate) fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
@@ -0,0 +1,138 @@
+//! Exports traces as Rust source, see `--export-rust`, so other crates can
+//! embed known-good trees in their tests without parsing logs.
+//!
+//! The generated file is self-contained: it declares its own `Node` and
+//! `State` types, then one `TRACE_N` const per trace, along with its input.
+//! Positions are byte offsets into the input.
+
+use crate::{
+    input::Trace,
+    spill::{Loaded, SpilledTree},
+    Node, State,
+};
+use std::{error::Error, io::Write};
+
+const PRELUDE: &str = r#"#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Success,
+    Failure,
+    /// the rule never finished, e.g. because the parser panicked
+    Unknown,
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub rule: &'static str,
+    pub state: State,
+    /// byte offset where the rule was attempted
+    pub start: usize,
+    /// byte offset the rule matched up to, for successes that say so
+    pub end: Option<usize>,
+    pub children: &'static [Node],
+}
+"#;
+
+pub(crate) fn write_fixtures(f: &mut dyn Write, traces: &[Trace]) -> Result<(), Box<dyn Error>> {
+    writeln!(f, "// Generated by pegviz, do not edit.")?;
+    writeln!(f)?;
+    write!(f, "{}", PRELUDE)?;
+
+    for trace in traces {
+        let n = trace.index + 1;
+        writeln!(f)?;
+        if let Some(source) = &trace.source {
+            writeln!(f, "// from {}", source)?;
+        }
+        writeln!(f, "pub const TRACE_{}_INPUT: &str = {:?};", n, trace.input)?;
+        writeln!(f)?;
+        write!(f, "pub const TRACE_{}: &[Node] = ", n)?;
+        match &trace.spilled {
+            Some(tree) => write_spilled(f, trace, tree, tree.top_level()?, 0)?,
+            None => write_nodes(f, trace, &trace.root.children, 0)?,
+        }
+        writeln!(f, ";")?;
+    }
+    Ok(())
+}
+
+fn write_nodes(
+    f: &mut dyn Write,
+    trace: &Trace,
+    nodes: &[Node],
+    depth: usize,
+) -> Result<(), Box<dyn Error>> {
+    if nodes.is_empty() {
+        write!(f, "&[]")?;
+        return Ok(());
+    }
+    writeln!(f, "&[")?;
+    for node in nodes {
+        write_head(f, trace, node, depth + 1)?;
+        write_nodes(f, trace, &node.children, depth + 2)?;
+        write_tail(f, depth + 1)?;
+    }
+    write!(f, "{}]", indent(depth))?;
+    Ok(())
+}
+
+fn write_spilled(
+    f: &mut dyn Write,
+    trace: &Trace,
+    tree: &SpilledTree,
+    nodes: Vec<Loaded>,
+    depth: usize,
+) -> Result<(), Box<dyn Error>> {
+    if nodes.is_empty() {
+        write!(f, "&[]")?;
+        return Ok(());
+    }
+    writeln!(f, "&[")?;
+    for loaded in nodes {
+        write_head(f, trace, &loaded.node, depth + 1)?;
+        let children = tree.children(&loaded)?;
+        write_spilled(f, trace, tree, children, depth + 2)?;
+        write_tail(f, depth + 1)?;
+    }
+    write!(f, "{}]", indent(depth))?;
+    Ok(())
+}
+
+/// Everything in a node's literal up to its children.
+fn write_head(
+    f: &mut dyn Write,
+    trace: &Trace,
+    node: &Node,
+    depth: usize,
+) -> Result<(), Box<dyn Error>> {
+    let input = &trace.input;
+    let (i, j) = (indent(depth), indent(depth + 1));
+    writeln!(f, "{}Node {{", i)?;
+    writeln!(f, "{}rule: {:?},", j, node.rule.name)?;
+    writeln!(
+        f,
+        "{}state: State::{},",
+        j,
+        match node.state {
+            State::Success => "Success",
+            State::Failure => "Failure",
+            State::Unknown => "Unknown",
+        }
+    )?;
+    writeln!(f, "{}start: {},", j, node.rule.loc.pos(input))?;
+    match node.rule.next_loc {
+        Some(loc) => writeln!(f, "{}end: Some({}),", j, loc.pos(input))?,
+        None => writeln!(f, "{}end: None,", j)?,
+    }
+    write!(f, "{}children: ", j)?;
+    Ok(())
+}
+
+fn write_tail(f: &mut dyn Write, depth: usize) -> Result<(), Box<dyn Error>> {
+    writeln!(f, ",")?;
+    writeln!(f, "{}}},", indent(depth))?;
+    Ok(())
+}
+
+fn indent(depth: usize) -> String {
+    "    ".repeat(depth)
+}
@@ -0,0 +1,187 @@
+//! Flame graphs of rule attempts, see `--format folded` and `--format
+//! speedscope`, to spot the rules where backtracking goes.
+//!
+//! The "time" is input: a rule weighs as many bytes as it went over, what
+//! it matched or, for a failure, as far as its children got. Children that
+//! went over the same input again add up, so a rule whose alternatives kept
+//! starting over comes out wider than what it matched. Attempts that went
+//! nowhere weigh 1, so that rules failing right away still show.
+
+use crate::{input::Trace, json, Args, Node, State};
+use std::{collections::HashMap, error::Error, io::Write};
+
+/// Rule names, deduplicated across traces.
+#[derive(Default)]
+struct Frames {
+    names: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl Frames {
+    fn get(&mut self, name: &str) -> usize {
+        if let Some(&index) = self.index.get(name) {
+            return index;
+        }
+        // `;` separates frames in folded stacks
+        self.names.push(name.replace(';', ","));
+        self.index.insert(name.to_string(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+}
+
+/// Input a stack went over on its own, not counting its children.
+struct Sample {
+    stack: Vec<usize>,
+    weight: usize,
+}
+
+/// One line per stack, `Trace #1;item;term 3`, for `flamegraph.pl`,
+/// `inferno` and speedscope.
+pub(crate) fn write_folded(
+    f: &mut dyn Write,
+    args: &Args,
+    traces: &[Trace],
+) -> Result<(), Box<dyn Error>> {
+    let mut frames = Frames::default();
+    let mut order = vec![];
+    let mut totals: HashMap<Vec<usize>, usize> = HashMap::new();
+    for trace in traces {
+        for sample in samples(args, trace, &mut frames)? {
+            let total = totals.entry(sample.stack.clone()).or_insert_with(|| {
+                order.push(sample.stack.clone());
+                0
+            });
+            *total += sample.weight;
+        }
+    }
+    for stack in order {
+        let names: Vec<&str> = stack.iter().map(|&i| frames.names[i].as_str()).collect();
+        writeln!(f, "{} {}", names.join(";"), totals[&stack])?;
+    }
+    Ok(())
+}
+
+/// speedscope's own format, with a profile per trace and samples in parse
+/// order, for its time order view.
+pub(crate) fn write_speedscope(
+    f: &mut dyn Write,
+    args: &Args,
+    traces: &[Trace],
+) -> Result<(), Box<dyn Error>> {
+    let mut frames = Frames::default();
+    let mut profiles = vec![];
+    for trace in traces {
+        let samples = samples(args, trace, &mut frames)?;
+        let total: usize = samples.iter().map(|s| s.weight).sum();
+        let stacks: Vec<String> = samples
+            .iter()
+            .map(|s| {
+                let stack: Vec<String> = s.stack.iter().map(|i| i.to_string()).collect();
+                format!("[{}]", stack.join(","))
+            })
+            .collect();
+        let weights: Vec<String> = samples.iter().map(|s| s.weight.to_string()).collect();
+        let name = match &trace.source {
+            Some(source) => format!("{} — {}", trace.root.rule.name, source),
+            None => trace.root.rule.name.clone(),
+        };
+        profiles.push(format!(
+            r#"    {{
+      "type": "sampled",
+      "name": {},
+      "unit": "bytes",
+      "startValue": 0,
+      "endValue": {},
+      "samples": [{}],
+      "weights": [{}]
+    }}"#,
+            json::string(&name),
+            total,
+            stacks.join(", "),
+            weights.join(", ")
+        ));
+    }
+    let names: Vec<String> = frames
+        .names
+        .iter()
+        .map(|name| format!(r#"{{"name": {}}}"#, json::string(name)))
+        .collect();
+    writeln!(
+        f,
+        r#"{{
+  "$schema": "https://www.speedscope.app/file-format-schema.json",
+  "exporter": {},
+  "name": "pegviz",
+  "activeProfileIndex": 0,
+  "shared": {{ "frames": [{}] }},
+  "profiles": [
+{}
+  ]
+}}"#,
+        json::string(&format!("pegviz {}", env!("CARGO_PKG_VERSION"))),
+        names.join(", "),
+        profiles.join(",\n")
+    )?;
+    Ok(())
+}
+
+fn samples(args: &Args, trace: &Trace, frames: &mut Frames) -> Result<Vec<Sample>, Box<dyn Error>> {
+    let loaded;
+    let children = match &trace.spilled {
+        Some(tree) => {
+            loaded = tree.load_all()?;
+            &loaded
+        }
+        None => &trace.root.children,
+    };
+    let mut samples = vec![];
+    let stack = vec![frames.get(&trace.root.rule.name)];
+    for child in children {
+        walk(args, trace, child, &stack, frames, &mut samples);
+    }
+    // nodes whose children cover them
+    samples.retain(|sample| sample.weight > 0);
+    Ok(samples)
+}
+
+/// Adds samples for `node`'s subtree, returns its weight and how far into the
+/// input it got.
+fn walk(
+    args: &Args,
+    trace: &Trace,
+    node: &Node,
+    stack: &[usize],
+    frames: &mut Frames,
+    samples: &mut Vec<Sample>,
+) -> (usize, usize) {
+    let input = &trace.input;
+    let start = node.rule.loc.pos(input);
+    if args.should_hide(trace, node) {
+        return (0, start);
+    }
+    if args.should_flatten(trace, node, node.children.len()) {
+        return walk(args, trace, &node.children[0], stack, frames, samples);
+    }
+
+    let mut stack = stack.to_vec();
+    stack.push(frames.get(&node.rule.name));
+    // the node's own sample goes before its children's, in parse order
+    let index = samples.len();
+    samples.push(Sample {
+        stack: stack.clone(),
+        weight: 0,
+    });
+    let mut children = 0;
+    let mut end = start;
+    for child in &node.children {
+        let (weight, child_end) = walk(args, trace, child, &stack, frames, samples);
+        children += weight;
+        end = end.max(child_end);
+    }
+    if let (State::Success, Some(next)) = (&node.state, node.rule.next_loc) {
+        end = next.pos(input).max(start);
+    }
+    let span = (end - start).max(1);
+    samples[index].weight = span.saturating_sub(children);
+    (span.max(children), end)
+}
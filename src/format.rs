@@ -0,0 +1,346 @@
+//! Trace formats, and telling them apart, see `--trace-format`.
+//!
+//! Whatever the format, traces sit between pegviz's markers, after their
+//! input: formats only differ in their trace lines, which are all turned
+//! into peg's. Logs in formats pegviz knows of but can't read are
+//! recognized from their first lines, so pointing pegviz at one fails with
+//! a clear message rather than with a parse error on some line in the
+//! middle.
+//!
+//! Each format is a `Format`, listed in `formats`. Adding one is adding a
+//! module with a type implementing it, and a feature to go with it.
+
+pub(crate) use spec::Spec;
+
+use crate::{timestamp, tracer, Line};
+use std::{fmt, str::FromStr};
+
+#[cfg(feature = "chumsky")]
+mod chumsky;
+#[cfg(feature = "combine")]
+mod combine;
+#[cfg(feature = "json")]
+mod json_lines;
+#[cfg(feature = "lalrpop")]
+mod lalrpop;
+#[cfg(any(feature = "lalrpop", feature = "tree-sitter"))]
+mod lr;
+#[cfg(feature = "nom")]
+mod nom;
+#[cfg(feature = "pest")]
+mod pest;
+mod spec;
+#[cfg(feature = "tree-sitter")]
+mod tree_sitter;
+
+/// How many lines to look at before giving up on recognizing a format.
+const SNIFF_LINES: usize = 50;
+
+/// A trace format pegviz can read, see `formats`.
+pub(crate) trait Format: Sync {
+    /// What `--trace-format` calls it.
+    fn name(&self) -> &'static str;
+
+    /// Whether `line`, the first one after `[PEG_TRACE_START]` or one of
+    /// those that follow, looks like one of this format's.
+    fn sniff(&self, line: &str) -> bool;
+
+    /// A reader for the lines of one trace, of `input`.
+    fn reader(&self, input: &str) -> Box<dyn Reader>;
+}
+
+/// Reads the lines between `[PEG_TRACE_START]` and `[PEG_TRACE_STOP]`,
+/// remembering what earlier lines said for formats that need it.
+pub(crate) trait Reader {
+    /// Parses the line at `log_line`, into the peg lines it stands for, along
+    /// with the log lines they come from. That's often none, for the formats
+    /// that only know what happened at the end of the trace.
+    fn line(&mut self, log_line: usize, line: &str) -> Result<Vec<(usize, Line)>, String>;
+
+    /// The lines held back until `[PEG_TRACE_STOP]`.
+    fn finish(&mut self) -> Vec<(usize, Line)> {
+        vec![]
+    }
+}
+
+/// The formats built in, peg first. Others are behind features of the same
+/// name, all on by default.
+fn formats() -> Vec<&'static dyn Format> {
+    #[allow(unused_mut)]
+    let mut formats: Vec<&'static dyn Format> = vec![&Peg];
+    #[cfg(feature = "pest")]
+    formats.push(&pest::Pest);
+    #[cfg(feature = "nom")]
+    formats.push(&nom::Nom);
+    #[cfg(feature = "lalrpop")]
+    formats.push(&lalrpop::Lalrpop);
+    #[cfg(feature = "tree-sitter")]
+    formats.push(&tree_sitter::TreeSitter);
+    #[cfg(feature = "chumsky")]
+    formats.push(&chumsky::Chumsky);
+    #[cfg(feature = "combine")]
+    formats.push(&combine::Combine);
+    #[cfg(feature = "json")]
+    formats.push(&json_lines::JsonLines);
+    formats
+}
+
+/// One of `formats`, as given to `--trace-format`.
+#[derive(Clone, Copy)]
+pub(crate) struct TraceFormat(&'static dyn Format);
+
+impl TraceFormat {
+    pub(crate) const PEG: TraceFormat = TraceFormat(&Peg);
+
+    pub(crate) fn reader(self, input: &str) -> Box<dyn Reader> {
+        self.0.reader(input)
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let formats = formats();
+        match formats.iter().find(|format| format.name() == s) {
+            Some(&format) => Ok(TraceFormat(format)),
+            None => {
+                let names: Vec<&str> = formats.iter().map(|format| format.name()).collect();
+                Err(format!(
+                    "unknown trace format {:?}, expected one of: {}",
+                    s,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.name())
+    }
+}
+
+/// A reader for formats whose lines all stand on their own.
+struct Lines(fn(&str) -> Result<Vec<Line>, String>);
+
+impl Reader for Lines {
+    fn line(&mut self, log_line: usize, line: &str) -> Result<Vec<(usize, Line)>, String> {
+        let lines = (self.0)(line)?;
+        Ok(lines.into_iter().map(|line| (log_line, line)).collect())
+    }
+}
+
+/// rust-peg's `trace` feature, with pegviz's markers around it.
+struct Peg;
+
+impl Format for Peg {
+    fn name(&self) -> &'static str {
+        "peg"
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        line.starts_with("[PEG_TRACE] ")
+    }
+
+    fn reader(&self, _input: &str) -> Box<dyn Reader> {
+        Box::new(Lines(|line| {
+            let parsed = tracer::line(line).map_err(|e| match peg_release(line) {
+                Some(release) => format!(
+                    "worded like the trace lines of peg {}, but not quite:\n{:#?}",
+                    release, e
+                ),
+                None => format!(
+                    "not worded like the trace lines of any peg release pegviz reads (0.4 \
+                     to 0.8), it may come from a newer one, which isn't supported yet:\n{:#?}",
+                    e
+                ),
+            })?;
+            Ok(vec![parsed])
+        }))
+    }
+}
+
+/// Which rust-peg releases word their trace lines like `line`, for errors
+/// on lines pegviz can't read.
+fn peg_release(line: &str) -> Option<&'static str> {
+    let rest = line.trim().strip_prefix("[PEG_TRACE] ")?;
+    let rule = [
+        "Attempting to match rule ",
+        "Matched rule ",
+        "Failed to match rule ",
+    ]
+    .iter()
+    .find_map(|verb| rest.strip_prefix(verb));
+    match rule {
+        Some(rule) if rule.starts_with('`') => Some("0.6 to 0.8"),
+        Some(rule) if rule.contains(" (pos ") => Some("0.5"),
+        Some(_) => Some("0.4 and earlier"),
+        None if ["Cached ", "Entering level ", "Leaving level "]
+            .iter()
+            .any(|verb| rest.starts_with(verb)) =>
+        {
+            Some("0.6 to 0.8")
+        }
+        None => None,
+    }
+}
+
+/// What the first lines of a log look like.
+#[derive(Clone, Copy)]
+pub(crate) enum Sniffed {
+    Format(TraceFormat),
+    /// a format pegviz knows about but can't read, named
+    Unsupported(&'static str),
+    /// pegviz's markers, but no trace lines to tell the format from, see
+    /// `detect`
+    Markers,
+    Unknown,
+}
+
+/// Guesses the format of a log from `head`, its first bytes. The markers
+/// are the same for all formats: trace lines tell them apart.
+pub(crate) fn sniff(head: &[u8]) -> Sniffed {
+    let head = String::from_utf8_lossy(head);
+    let mut markers = false;
+    for line in head.lines().take(SNIFF_LINES) {
+        let (_, line) = timestamp::split(line.trim_start_matches('\u{feff}'));
+        let line = line.trim();
+        if line.starts_with("[PEG_INPUT_START") || line.starts_with("[PEG_TRACE_START") {
+            markers = true;
+        } else if markers {
+            if let Some(format) = detect(line) {
+                return Sniffed::Format(format);
+            }
+        }
+        if !markers && line.starts_with('{') && line.contains("\"event\"") {
+            return Sniffed::Unsupported("JSON trace events without pegviz's markers");
+        }
+    }
+    if markers {
+        return Sniffed::Markers;
+    }
+    Sniffed::Unknown
+}
+
+/// The format `line`, a trace line, is in, if it looks like any.
+pub(crate) fn detect(line: &str) -> Option<TraceFormat> {
+    let line = line.trim();
+    formats()
+        .into_iter()
+        .find(|format| format.sniff(line))
+        .map(TraceFormat)
+}
+
+/// Removes ANSI color codes, for formats printed in color when they can be.
+#[cfg(any(feature = "nom", feature = "combine"))]
+fn strip_colors(line: &str) -> String {
+    let mut res = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // `ESC [ params letter`
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Location, Rule};
+
+    fn parse(line: &str) -> Result<Line, String> {
+        let mut lines = Peg.reader("").line(1, line)?;
+        assert_eq!(lines.len(), 1);
+        Ok(lines.remove(0).1)
+    }
+
+    fn rule(line: &str) -> (&'static str, Rule) {
+        match parse(line).unwrap() {
+            Line::Attempt(rule) => ("attempt", rule),
+            Line::Success(rule) => ("success", rule),
+            Line::Failure(rule) => ("failure", rule),
+            Line::Cache(Some(rule)) => ("cache", rule),
+            other => panic!("expected a rule line, got {:?}", other),
+        }
+    }
+
+    fn at(line: usize, column: usize) -> Location {
+        Location::LineCol { line, column }
+    }
+
+    #[test]
+    fn peg_0_4_lines() {
+        let (kind, r) = rule("[PEG_TRACE] Attempting to match rule item at 1:1");
+        assert_eq!(
+            (kind, r.name.as_str(), r.loc, r.next_loc),
+            ("attempt", "item", at(1, 1), None)
+        );
+        let (kind, r) = rule("[PEG_TRACE] Failed to match rule atom at 2:7");
+        assert_eq!(
+            (kind, r.name.as_str(), r.loc),
+            ("failure", "atom", at(2, 7))
+        );
+    }
+
+    #[test]
+    fn peg_0_5_lines() {
+        let (kind, r) = rule("[PEG_TRACE] Matched rule term at 1:3 (pos 2)");
+        assert_eq!(
+            (kind, r.name.as_str(), r.loc, r.next_loc),
+            ("success", "term", at(1, 3), None)
+        );
+    }
+
+    #[test]
+    fn peg_0_6_to_0_8_lines() {
+        let (kind, r) = rule("[PEG_TRACE] Matched rule `expr` at 1:1 to 1:4");
+        assert_eq!(
+            (kind, r.name.as_str(), r.loc, r.next_loc),
+            ("success", "expr", at(1, 1), Some(at(1, 4)))
+        );
+        // `[u8]` and `[T]` inputs have bare offsets
+        let (_, r) = rule("[PEG_TRACE] Matched rule `tok` at 3 to 5");
+        assert_eq!(
+            (r.loc, r.next_loc),
+            (Location::Offset(3), Some(Location::Offset(5)))
+        );
+        let (kind, r) = rule("[PEG_TRACE] Cached match of rule atom at 4");
+        assert_eq!((kind, r.name.as_str()), ("cache", "atom"));
+        assert!(matches!(
+            parse("[PEG_TRACE] Entering level 2"),
+            Ok(Line::EnterLevel)
+        ));
+        assert!(matches!(
+            parse("[PEG_TRACE] Leaving level 2"),
+            Ok(Line::LeaveLevel)
+        ));
+    }
+
+    #[test]
+    fn unknown_wording_names_the_release() {
+        let err = parse("[PEG_TRACE] Matched rule `expr` at one to four").unwrap_err();
+        assert!(
+            err.starts_with("worded like the trace lines of peg 0.6 to 0.8"),
+            "{}",
+            err
+        );
+        let err = parse("[PEG_TRACE] Matched rule term at 1:3 (pos two)").unwrap_err();
+        assert!(
+            err.starts_with("worded like the trace lines of peg 0.5,"),
+            "{}",
+            err
+        );
+        let err = parse("[PEG_TRACE] Rule `expr` matched at 1:1").unwrap_err();
+        assert!(err.contains("may come from a newer one"), "{}", err);
+    }
+}
@@ -0,0 +1,66 @@
+//! chumsky's debug output, for parsers wrapped in `.debug(label)`: where a
+//! parser was entered, and where it matched up to or why it failed.
+//!
+//! ```text
+//! > expr @ 0
+//!   > term @ 0
+//!   < term @ 0..1
+//!   > term @ 2
+//!   ! term @ 2: found 'x', expected digit
+//! < expr @ 0..1
+//! ```
+//!
+//! Indentation is ignored, nesting comes from the order of the lines. The
+//! reason a parser failed is kept as a note on it.
+
+use super::{Format, Lines, Reader};
+use crate::{Line, Location, Rule};
+
+pub(crate) struct Chumsky;
+
+impl Format for Chumsky {
+    fn name(&self) -> &'static str {
+        "chumsky"
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        line.starts_with("> ") && parse(line).is_some()
+    }
+
+    fn reader(&self, _input: &str) -> Box<dyn Reader> {
+        Box::new(Lines(line))
+    }
+}
+
+fn line(line: &str) -> Result<Vec<Line>, String> {
+    parse(line.trim())
+        .ok_or_else(|| "expected `>`, `<` or `!`, a label, `@` and where, like `> expr @ 0`".into())
+}
+
+fn parse(line: &str) -> Option<Vec<Line>> {
+    let (mark, rest) = line.split_once(' ')?;
+    let (name, at) = rest.split_once(" @ ")?;
+    let (span, reason) = match at.split_once(": ") {
+        Some((span, reason)) => (span, Some(reason.trim())),
+        None => (at, None),
+    };
+    let (start, end) = match span.split_once("..") {
+        Some((start, end)) => (start.parse().ok()?, Some(end.parse().ok()?)),
+        None => (span.parse().ok()?, None),
+    };
+    let rule = Rule {
+        name: name.trim().into(),
+        loc: Location::Offset(start),
+        next_loc: end.map(Location::Offset),
+    };
+    match mark {
+        ">" => Some(vec![Line::Attempt(rule)]),
+        "<" if end.is_some() => Some(vec![Line::Success(rule)]),
+        "!" => {
+            let mut lines: Vec<Line> = reason.map(|r| Line::Note(r.into())).into_iter().collect();
+            lines.push(Line::Failure(rule));
+            Some(lines)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,120 @@
+//! combine parsers traced with `tracing`, as `tracing-subscriber`'s fmt
+//! layer prints them: a span per parser, with where it started, and events
+//! saying it was entered, matched up to somewhere, or failed.
+//!
+//! ```text
+//! TRACE expr{pos=0}: grammar: enter
+//! TRACE expr{pos=0}:term{pos=0}: grammar: enter
+//! TRACE expr{pos=0}:term{pos=0}: grammar: ok end=1
+//! TRACE expr{pos=0}:term{pos=2}: grammar: enter
+//! TRACE expr{pos=0}:term{pos=2}: grammar: err expected digit
+//! TRACE expr{pos=0}: grammar: ok end=1
+//! ```
+//!
+//! The parser is the innermost span, named after its `parser` field when it
+//! has one (`parse{parser="term" pos=2}`). Positions are byte offsets,
+//! colors and the level are stripped, and other events are left out. Why a
+//! parser failed is kept as a note on it.
+
+use super::{strip_colors, Format, Lines, Reader};
+use crate::{Line, Location, Rule};
+
+pub(crate) struct Combine;
+
+impl Format for Combine {
+    fn name(&self) -> &'static str {
+        "combine"
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        let line = strip_colors(line);
+        match parts(line.trim()) {
+            Some((span, message)) => message == "enter" && parser(span).is_some(),
+            None => false,
+        }
+    }
+
+    fn reader(&self, _input: &str) -> Box<dyn Reader> {
+        Box::new(Lines(line))
+    }
+}
+
+fn line(line: &str) -> Result<Vec<Line>, String> {
+    let line = strip_colors(line);
+    let (span, message) = parts(line.trim())
+        .ok_or_else(|| "expected spans and an event, like `expr{pos=0}: enter`".to_string())?;
+    let (name, pos) = parser(span)
+        .ok_or_else(|| format!("expected a parser span with a `pos`, got {:?}", span))?;
+    let rule = |next_loc| Rule {
+        name: name.into(),
+        loc: Location::Offset(pos),
+        next_loc,
+    };
+
+    let (verb, rest) = message.split_once(' ').unwrap_or((message, ""));
+    Ok(match verb {
+        "enter" => vec![Line::Attempt(rule(None))],
+        "ok" => {
+            let end = match rest.trim().strip_prefix("end=") {
+                Some(end) => Some(
+                    end.parse()
+                        .map_err(|_| format!("expected a number for `end=`, got {:?}", end))?,
+                ),
+                None => None,
+            };
+            vec![Line::Success(rule(end.map(Location::Offset)))]
+        }
+        "err" => {
+            let mut lines = vec![];
+            if !rest.trim().is_empty() {
+                lines.push(Line::Note(rest.trim().into()));
+            }
+            lines.push(Line::Failure(rule(None)));
+            lines
+        }
+        _ => vec![],
+    })
+}
+
+/// The innermost span and the event's message, without the level and target.
+fn parts(line: &str) -> Option<(&str, &str)> {
+    let line = match line.split_once(' ') {
+        Some((level, rest)) if LEVELS.contains(&level) => rest.trim_start(),
+        _ => line,
+    };
+    // the spans end at the first `}: `, fields may have `: ` in them but
+    // not `}`
+    let end = line.find("}: ")?;
+    let spans = &line[..=end];
+    let mut message = &line[end + 3..];
+    if let Some((target, rest)) = message.split_once(": ") {
+        if target
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == ':')
+        {
+            message = rest;
+        }
+    }
+    let innermost = match spans.rfind("}:") {
+        Some(i) => &spans[i + 2..],
+        None => spans,
+    };
+    Some((innermost, message.trim()))
+}
+
+const LEVELS: &[&str] = &["TRACE", "DEBUG", "INFO", "WARN", "ERROR"];
+
+/// A span's parser name and position, from `name{pos=0}` or
+/// `name{parser="term" pos=0}`.
+fn parser(span: &str) -> Option<(&str, usize)> {
+    let (mut name, fields) = span.strip_suffix('}')?.split_once('{')?;
+    let mut pos = None;
+    for field in fields.split_whitespace() {
+        match field.split_once('=') {
+            Some(("pos", value)) => pos = value.parse().ok(),
+            Some(("parser", value)) => name = value.trim_matches('"'),
+            _ => {}
+        }
+    }
+    Some((name, pos?))
+}
@@ -0,0 +1,215 @@
+//! Traces as JSON lines, one event per line, for parsers that would rather
+//! not worry about how rule names and positions are printed:
+//!
+//! ```text
+//! {"event":"attempt","rule":"expr","line":1,"col":1}
+//! {"event":"success","rule":"expr","line":1,"col":1,"end_line":1,"end_col":4}
+//! {"event":"failure","rule":"term","pos":3}
+//! {"event":"note","text":"trying the fallback"}
+//! ```
+//!
+//! Positions are `line` and `col` (1-based, like peg's) or `pos`, a byte
+//! offset, and successes say where they ended with `end_line` and `end_col`
+//! or `end`. `cache` events, with a rule and a position, are cache hits.
+//! Fields pegviz doesn't know of are left alone.
+
+use super::{Format, Lines, Reader};
+use crate::{Line, Location, Rule};
+use std::{collections::HashMap, iter::Peekable, str::Chars};
+
+pub(crate) struct JsonLines;
+
+impl Format for JsonLines {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        line.starts_with('{') && self::line(line).is_ok()
+    }
+
+    fn reader(&self, _input: &str) -> Box<dyn Reader> {
+        Box::new(Lines(|line| Ok(vec![self::line(line)?])))
+    }
+}
+
+enum Value {
+    Number(f64),
+    String(String),
+    Object(HashMap<String, Value>),
+    /// arrays, booleans and null, which events have no use for
+    Other,
+}
+
+fn line(line: &str) -> Result<Line, String> {
+    let mut chars = line.trim().chars().peekable();
+    let event = match value(&mut chars)? {
+        Value::Object(event) => event,
+        _ => return Err("expected an object".into()),
+    };
+    if let Some(c) = skip_whitespace(&mut chars) {
+        return Err(format!("unexpected {:?} after the object", c));
+    }
+
+    let string = |name: &str| match event.get(name) {
+        Some(Value::String(s)) => Ok(s.as_str()),
+        _ => Err(format!("expected a string `{}`", name)),
+    };
+    let number = |name: &str| match event.get(name) {
+        Some(Value::Number(n)) if *n >= 0.0 && n.fract() == 0.0 => Ok(Some(*n as usize)),
+        Some(_) => Err(format!("expected `{}` to be a position", name)),
+        None => Ok(None),
+    };
+    let location = |pos: &str, line: &str, col: &str| -> Result<Option<Location>, String> {
+        Ok(match (number(pos)?, number(line)?, number(col)?) {
+            (Some(pos), _, _) => Some(Location::Offset(pos)),
+            (None, Some(line), Some(column)) => Some(Location::LineCol { line, column }),
+            _ => None,
+        })
+    };
+    let rule = || -> Result<Rule, String> {
+        Ok(Rule {
+            name: string("rule")?.into(),
+            loc: location("pos", "line", "col")?.ok_or("expected `line` and `col`, or `pos`")?,
+            next_loc: location("end", "end_line", "end_col")?,
+        })
+    };
+
+    Ok(match string("event")? {
+        "attempt" => Line::Attempt(rule()?),
+        "success" => Line::Success(rule()?),
+        "failure" => Line::Failure(rule()?),
+        "cache" => Line::Cache(Some(rule()?)),
+        "note" => Line::Note(string("text")?.into()),
+        event => {
+            return Err(format!(
+                "unknown event {:?}, expected attempt, success, failure, cache or note",
+                event
+            ))
+        }
+    })
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> Option<char> {
+    while let Some(c) = chars.peek() {
+        if !c.is_whitespace() {
+            return Some(*c);
+        }
+        chars.next();
+    }
+    None
+}
+
+fn value(chars: &mut Peekable<Chars>) -> Result<Value, String> {
+    match skip_whitespace(chars) {
+        Some('{') => {
+            chars.next();
+            let mut object = HashMap::new();
+            if skip_whitespace(chars) == Some('}') {
+                chars.next();
+                return Ok(Value::Object(object));
+            }
+            loop {
+                if skip_whitespace(chars) != Some('"') {
+                    return Err("expected a key".into());
+                }
+                let key = string(chars)?;
+                if skip_whitespace(chars) != Some(':') {
+                    return Err(format!("expected `:` after {:?}", key));
+                }
+                chars.next();
+                object.insert(key, value(chars)?);
+                match skip_whitespace(chars) {
+                    Some(',') => chars.next(),
+                    Some('}') => {
+                        chars.next();
+                        return Ok(Value::Object(object));
+                    }
+                    _ => return Err("expected `,` or `}`".into()),
+                };
+            }
+        }
+        Some('[') => {
+            chars.next();
+            if skip_whitespace(chars) == Some(']') {
+                chars.next();
+                return Ok(Value::Other);
+            }
+            loop {
+                value(chars)?;
+                match skip_whitespace(chars) {
+                    Some(',') => chars.next(),
+                    Some(']') => {
+                        chars.next();
+                        return Ok(Value::Other);
+                    }
+                    _ => return Err("expected `,` or `]`".into()),
+                };
+            }
+        }
+        Some('"') => Ok(Value::String(string(chars)?)),
+        Some(c) if c == '-' || c.is_ascii_digit() => {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            number
+                .parse()
+                .map(Value::Number)
+                .map_err(|_| format!("invalid number {:?}", number))
+        }
+        Some(_) => {
+            let word: String =
+                std::iter::from_fn(|| chars.next_if(char::is_ascii_alphabetic)).collect();
+            match word.as_str() {
+                "true" | "false" | "null" => Ok(Value::Other),
+                _ => Err("expected a value".into()),
+            }
+        }
+        None => Err("expected a value".into()),
+    }
+}
+
+/// A string, starting at its opening quote.
+fn string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    chars.next();
+    let mut res = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(res),
+            Some('\\') => match chars.next() {
+                Some('n') => res.push('\n'),
+                Some('t') => res.push('\t'),
+                Some('r') => res.push('\r'),
+                Some('b') => res.push('\u{8}'),
+                Some('f') => res.push('\u{c}'),
+                Some('u') => {
+                    let mut code = hex4(chars)?;
+                    // surrogate pairs, for characters outside the BMP
+                    if (0xd800..0xdc00).contains(&code) {
+                        if chars.next() != Some('\\') || chars.next() != Some('u') {
+                            return Err("expected the rest of a surrogate pair".into());
+                        }
+                        let low = hex4(chars)?;
+                        code =
+                            0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                    }
+                    res.push(char::from_u32(code).ok_or("invalid \\u escape")?);
+                }
+                Some(c @ ('"' | '\\' | '/')) => res.push(c),
+                _ => return Err("invalid escape".into()),
+            },
+            Some(c) => res.push(c),
+            None => return Err("unterminated string".into()),
+        }
+    }
+}
+
+fn hex4(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+    let hex: String = chars.by_ref().take(4).collect();
+    u32::from_str_radix(&hex, 16).map_err(|_| format!("invalid \\u escape {:?}", hex))
+}
@@ -0,0 +1,85 @@
+//! LALRPOP parse logs: the tokens shifted, with their byte positions, and
+//! the reductions, with how many symbols they take off the stack:
+//!
+//! ```text
+//! shift Num 0..1
+//! reduce Term 1
+//! shift "+" 1..2
+//! shift Num 2..3
+//! reduce Term 1
+//! reduce Expr 3
+//! accept
+//! ```
+//!
+//! Reductions become nodes, with the symbols they took as children, tokens
+//! included. An LR parser only knows what it parsed once it's reduced, so
+//! nothing comes out until the trace is over. An `error` line, with the
+//! token that couldn't be shifted (`error "+" 3..4`, or `error EOF 5` at
+//! the end of the input), ends up as a failed node after what was parsed.
+
+use super::{lr::Stack, Format, Reader};
+use crate::{Line, Location};
+
+pub(crate) struct Lalrpop;
+
+impl Format for Lalrpop {
+    fn name(&self) -> &'static str {
+        "lalrpop"
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        line.starts_with("shift ") && LalrpopReader::default().line(0, line).is_ok()
+    }
+
+    fn reader(&self, _input: &str) -> Box<dyn Reader> {
+        Box::new(LalrpopReader::default())
+    }
+}
+
+#[derive(Default)]
+struct LalrpopReader {
+    stack: Stack,
+}
+
+impl Reader for LalrpopReader {
+    fn line(&mut self, log_line: usize, line: &str) -> Result<Vec<(usize, Line)>, String> {
+        let line = line.trim();
+        let (verb, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match verb {
+            "" | "accept" => {}
+            "shift" | "error" => {
+                let (name, span) = rest
+                    .rsplit_once(' ')
+                    .ok_or_else(|| format!("expected a token and where it is after `{}`", verb))?;
+                let span = span_of(span)
+                    .ok_or_else(|| format!("expected `start..end` or an offset, got {:?}", span))?;
+                self.stack.shift(name, span, verb == "shift", log_line);
+            }
+            "reduce" => {
+                let (name, count) = rest
+                    .rsplit_once(' ')
+                    .and_then(|(name, count)| Some((name, count.parse().ok()?)))
+                    .ok_or_else(|| "expected a nonterminal and a symbol count".to_string())?;
+                self.stack.reduce(name, count, true, log_line)?;
+            }
+            _ => return Err("expected `shift`, `reduce`, `accept` or `error`".into()),
+        }
+        Ok(vec![])
+    }
+
+    fn finish(&mut self) -> Vec<(usize, Line)> {
+        self.stack.finish()
+    }
+}
+
+/// `start..end`, or a single offset for an empty span.
+fn span_of(s: &str) -> Option<(Location, Location)> {
+    let (start, end) = match s.split_once("..") {
+        Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+        None => {
+            let at = s.parse().ok()?;
+            (at, at)
+        }
+    };
+    Some((Location::Offset(start), Location::Offset(end)))
+}
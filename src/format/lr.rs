@@ -0,0 +1,106 @@
+//! What LR parsers' logs have in common: the symbols on the parser's stack,
+//! shifted as tokens and reduced into nonterminals, which only make a tree
+//! once the parse is over.
+
+use crate::{Line, Location, Rule};
+
+struct Symbol {
+    name: String,
+    start: Location,
+    end: Location,
+    matched: bool,
+    log_line: usize,
+    children: Vec<Symbol>,
+}
+
+#[derive(Default)]
+pub(crate) struct Stack {
+    symbols: Vec<Symbol>,
+}
+
+impl Stack {
+    #[cfg(feature = "tree-sitter")]
+    pub(crate) fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Pushes a token, or with `matched` false, one that couldn't be shifted.
+    pub(crate) fn shift(
+        &mut self,
+        name: &str,
+        (start, end): (Location, Location),
+        matched: bool,
+        log_line: usize,
+    ) {
+        self.symbols.push(Symbol {
+            name: name.into(),
+            start,
+            end,
+            matched,
+            log_line,
+            children: vec![],
+        });
+    }
+
+    /// Replaces the top `count` symbols with `name`, which has them as
+    /// children.
+    pub(crate) fn reduce(
+        &mut self,
+        name: &str,
+        count: usize,
+        matched: bool,
+        log_line: usize,
+    ) -> Result<(), String> {
+        if count > self.symbols.len() {
+            return Err(format!(
+                "{} reduces {} symbols, but only {} are on the stack",
+                name,
+                count,
+                self.symbols.len()
+            ));
+        }
+        let children = self.symbols.split_off(self.symbols.len() - count);
+        // empty reductions sit right after what came before
+        let at = self
+            .symbols
+            .last()
+            .map(|s| s.end)
+            .unwrap_or(Location::Offset(0));
+        self.symbols.push(Symbol {
+            name: name.into(),
+            start: children.first().map(|s| s.start).unwrap_or(at),
+            end: children.last().map(|s| s.end).unwrap_or(at),
+            matched,
+            log_line,
+            children,
+        });
+        Ok(())
+    }
+
+    /// The whole trace, as peg lines with the log line each comes from.
+    pub(crate) fn finish(&mut self) -> Vec<(usize, Line)> {
+        let mut lines = vec![];
+        for symbol in std::mem::take(&mut self.symbols) {
+            emit(symbol, &mut lines);
+        }
+        lines
+    }
+}
+
+fn emit(symbol: Symbol, lines: &mut Vec<(usize, Line)>) {
+    let rule = |next_loc| Rule {
+        name: symbol.name.clone(),
+        loc: symbol.start,
+        next_loc,
+    };
+    lines.push((symbol.log_line, Line::Attempt(rule(None))));
+    let done = if symbol.matched {
+        Line::Success(rule(Some(symbol.end)))
+    } else {
+        Line::Failure(rule(None))
+    };
+    for child in symbol.children {
+        emit(child, lines);
+    }
+    lines.push((symbol.log_line, done));
+}
@@ -0,0 +1,103 @@
+//! nom-tracable's trace lines, indented by depth, with the byte offset and
+//! the input left where each parser went forward into or backward out of:
+//!
+//! ```text
+//! expr  0 : 1+x : forward
+//!  term 0 : 1+x : forward
+//!  term 1 : +x  : backward
+//!  term 2 : x   : forward
+//!  term 2 : x   : backward
+//! expr  2 : x   : backward
+//! ```
+//!
+//! Colors are stripped, and so is indentation: nesting comes from the order
+//! of the lines. A parser going backward is taken to have matched when it
+//! says `ok`, failed when it says `error`, and otherwise when it got further
+//! than where it started, so parsers that match nothing show as failures.
+
+use super::{strip_colors, Format, Reader};
+use crate::{Line, Location, Rule};
+
+pub(crate) struct Nom;
+
+impl Format for Nom {
+    fn name(&self) -> &'static str {
+        "nom"
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        let line = strip_colors(line);
+        let line = line.trim();
+        line.ends_with(": forward") && fields(line).is_some()
+    }
+
+    fn reader(&self, _input: &str) -> Box<dyn Reader> {
+        Box::new(NomReader::default())
+    }
+}
+
+/// Where the parsers that went forward and not backward yet started.
+#[derive(Default)]
+struct NomReader {
+    starts: Vec<usize>,
+}
+
+impl Reader for NomReader {
+    fn line(&mut self, log_line: usize, line: &str) -> Result<Vec<(usize, Line)>, String> {
+        let line = self.parse(line)?;
+        Ok(line.into_iter().map(|line| (log_line, line)).collect())
+    }
+}
+
+impl NomReader {
+    fn parse(&mut self, line: &str) -> Result<Option<Line>, String> {
+        let line = strip_colors(line);
+        let line = line.trim();
+        // the header and blank lines
+        if !line.contains(" : ") {
+            return Ok(None);
+        }
+        let (name, offset, direction) = fields(line).ok_or_else(|| {
+            "expected a parser, an offset, the input left and `forward` or `backward`".to_string()
+        })?;
+        let rule = |loc, next_loc: Option<usize>| Rule {
+            name: name.into(),
+            loc: Location::Offset(loc),
+            next_loc: next_loc.map(Location::Offset),
+        };
+
+        let mut direction = direction.split_whitespace();
+        match (direction.next(), direction.next()) {
+            (Some("forward"), _) => {
+                self.starts.push(offset);
+                Ok(Some(Line::Attempt(rule(offset, None))))
+            }
+            (Some("backward"), result) => {
+                let start = self
+                    .starts
+                    .pop()
+                    .ok_or_else(|| format!("{} goes backward, but never went forward", name))?;
+                let matched = match result {
+                    Some("ok") => true,
+                    Some("error") | Some("err") => false,
+                    _ => offset > start,
+                };
+                Ok(Some(if matched {
+                    Line::Success(rule(start, Some(offset)))
+                } else {
+                    Line::Failure(rule(start, None))
+                }))
+            }
+            _ => Err("expected `forward` or `backward` at the end of the line".into()),
+        }
+    }
+}
+
+/// The parser's name, its offset and which way it went.
+fn fields(line: &str) -> Option<(&str, usize, &str)> {
+    // the input left may have ` : ` in it
+    let (head, _) = line.split_once(" : ")?;
+    let (_, direction) = line.rsplit_once(" : ")?;
+    let (name, offset) = head.trim().rsplit_once(char::is_whitespace)?;
+    Some((name.trim(), offset.parse().ok()?, direction.trim()))
+}
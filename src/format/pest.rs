@@ -0,0 +1,65 @@
+//! pest parse attempts, one per line, with byte positions:
+//!
+//! ```text
+//! attempt expr at 0
+//!   attempt term at 0
+//!   matched term at 0..1
+//!   attempt term at 2
+//!   failed term at 2
+//! matched expr at 0..1
+//! ```
+//!
+//! Indentation is ignored, nesting comes from the order of the lines.
+
+use super::{Format, Lines, Reader};
+use crate::{Line, Location, Rule};
+
+pub(crate) struct Pest;
+
+impl Format for Pest {
+    fn name(&self) -> &'static str {
+        "pest"
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        line.starts_with("attempt ") && self::line(line).is_ok()
+    }
+
+    fn reader(&self, _input: &str) -> Box<dyn Reader> {
+        Box::new(Lines(|line| Ok(vec![self::line(line)?])))
+    }
+}
+
+fn line(line: &str) -> Result<Line, String> {
+    let line = line.trim();
+    let parsed = line.split_once(' ').and_then(|(verb, rest)| {
+        let (name, at) = rest.rsplit_once(" at ")?;
+        let (loc, next_loc) = match at.split_once("..") {
+            Some((start, end)) => (location(start)?, Some(location(end)?)),
+            None => (location(at)?, None),
+        };
+        let rule = Rule {
+            name: name.into(),
+            loc,
+            next_loc,
+        };
+        match (verb, next_loc) {
+            ("attempt", None) => Some(Line::Attempt(rule)),
+            ("matched", Some(_)) => Some(Line::Success(rule)),
+            ("failed", None) => Some(Line::Failure(rule)),
+            _ => None,
+        }
+    });
+    parsed.ok_or_else(|| "expected `attempt`, `matched` or `failed`, a rule, and where".into())
+}
+
+/// A byte offset, or `line:col` for wrappers that print those.
+fn location(s: &str) -> Option<Location> {
+    match s.split_once(':') {
+        Some((line, column)) => Some(Location::LineCol {
+            line: line.parse().ok()?,
+            column: column.parse().ok()?,
+        }),
+        None => Some(Location::Offset(s.parse().ok()?)),
+    }
+}
@@ -0,0 +1,296 @@
+//! Trace formats described in a file, see `--format-spec`, for parsers that
+//! print their own kind of lines. A spec is a small TOML file giving what
+//! lines look like, with placeholders where the rule and positions go:
+//!
+//! ```toml
+//! # lines from our in-house parser
+//! attempt = "enter {rule} at {line}:{col}"
+//! success = "leave {rule} ok, {pos} to {end}"
+//! failure = ["leave {rule} failed at {pos}", "give up on {rule} at {pos}"]
+//! note = "note: {text}"
+//! unmatched = "skip"
+//! ```
+//!
+//! Positions are `{pos}` (a byte offset) or `{line}` and `{col}` (1-based),
+//! and where a success ended is `{end}`, or `{end_line}` and `{end_col}`.
+//! `{_}` matches anything. Lines that match nothing are an error, unless
+//! `unmatched` is `"skip"`.
+
+use super::Reader;
+use crate::{Line, Location, Rule};
+use std::{error::Error, fs, path::Path};
+
+#[derive(Clone)]
+enum Part {
+    Literal(String),
+    Placeholder(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Attempt,
+    Success,
+    Failure,
+    Note,
+}
+
+#[derive(Clone)]
+pub(crate) struct Spec {
+    patterns: Vec<(Kind, Vec<Part>)>,
+    skip_unmatched: bool,
+}
+
+const PLACEHOLDERS: &[&str] = &[
+    "rule", "pos", "line", "col", "end", "end_line", "end_col", "text", "_",
+];
+
+impl Spec {
+    pub(crate) fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let mut spec = Spec {
+            patterns: vec![],
+            skip_unmatched: false,
+        };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = |what: String| format!("pegviz: {}:{}: {}", path.display(), i + 1, what);
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| bad(format!("expected `key = \"value\"`, got {:?}", line)))?;
+            let values = strings(value.trim()).map_err(bad)?;
+            let kind = match key.trim() {
+                "attempt" => Kind::Attempt,
+                "success" => Kind::Success,
+                "failure" => Kind::Failure,
+                "note" => Kind::Note,
+                "unmatched" => {
+                    spec.skip_unmatched = match values.as_slice() {
+                        [value] if value == "skip" => true,
+                        [value] if value == "error" => false,
+                        _ => return Err(bad("expected `\"skip\"` or `\"error\"`".into()).into()),
+                    };
+                    continue;
+                }
+                key => {
+                    return Err(bad(format!(
+                        "unknown key {:?}, expected attempt, success, failure, note or unmatched",
+                        key
+                    ))
+                    .into())
+                }
+            };
+            for value in values {
+                let pattern = pattern(&value).map_err(bad)?;
+                check(kind, &pattern).map_err(bad)?;
+                spec.patterns.push((kind, pattern));
+            }
+        }
+        if !spec.patterns.iter().any(|(kind, _)| *kind == Kind::Attempt) {
+            return Err(format!("pegviz: {}: no `attempt` lines", path.display()).into());
+        }
+        Ok(spec)
+    }
+
+    fn parse(&self, line: &str) -> Result<Option<Line>, String> {
+        for (kind, pattern) in &self.patterns {
+            let mut captures = vec![];
+            if !matches(pattern, line.trim(), &mut captures) {
+                continue;
+            }
+            let get = |name: &str| {
+                captures
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, value)| value.as_str())
+            };
+            let rule = || {
+                Some(Rule {
+                    name: get("rule")?.into(),
+                    loc: location(get("pos"), get("line"), get("col"))?,
+                    next_loc: location(get("end"), get("end_line"), get("end_col")),
+                })
+            };
+            let parsed = match kind {
+                Kind::Attempt => rule().map(Line::Attempt),
+                Kind::Success => rule().map(Line::Success),
+                Kind::Failure => rule().map(Line::Failure),
+                Kind::Note => get("text").map(|text| Line::Note(text.into())),
+            };
+            // otherwise its numbers didn't parse, another pattern may fit
+            if let Some(parsed) = parsed {
+                return Ok(Some(parsed));
+            }
+        }
+        if self.skip_unmatched {
+            Ok(None)
+        } else {
+            Err("matches none of the spec's lines".into())
+        }
+    }
+}
+
+impl Reader for Spec {
+    fn line(&mut self, log_line: usize, line: &str) -> Result<Vec<(usize, Line)>, String> {
+        let line = self.parse(line)?;
+        Ok(line.into_iter().map(|line| (log_line, line)).collect())
+    }
+}
+
+/// A quoted string, or an array of them.
+fn strings(value: &str) -> Result<Vec<String>, String> {
+    let mut rest = value;
+    let array = match rest.strip_prefix('[') {
+        Some(inner) => {
+            rest = inner
+                .strip_suffix(']')
+                .ok_or_else(|| "expected `]` at the end of the array".to_string())?;
+            true
+        }
+        None => false,
+    };
+    let mut res = vec![];
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let (string, after) = string(rest)?;
+        res.push(string);
+        rest = after.trim_start();
+        if rest.starts_with('#') {
+            break;
+        }
+        if !array && !rest.is_empty() {
+            return Err(format!("unexpected {:?} after the string", rest));
+        }
+        rest = rest.strip_prefix(',').unwrap_or(rest);
+    }
+    if res.is_empty() {
+        return Err("expected a string".into());
+    }
+    Ok(res)
+}
+
+/// A TOML basic (`"…"`, with escapes) or literal (`'…'`) string at the start
+/// of `s`, and what's after it.
+fn string(s: &str) -> Result<(String, &str), String> {
+    let quote = s
+        .chars()
+        .next()
+        .filter(|&c| c == '"' || c == '\'')
+        .ok_or_else(|| format!("expected a quoted string, got {:?}", s))?;
+    let mut res = String::new();
+    let mut chars = s[1..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((res, &s[i + 2..])),
+            '\\' if quote == '"' => match chars.next().map(|(_, c)| c) {
+                Some('n') => res.push('\n'),
+                Some('t') => res.push('\t'),
+                Some('"') => res.push('"'),
+                Some('\\') => res.push('\\'),
+                other => return Err(format!("unsupported escape \\{}", other.unwrap_or(' '))),
+            },
+            c => res.push(c),
+        }
+    }
+    Err("unterminated string".into())
+}
+
+fn pattern(s: &str) -> Result<Vec<Part>, String> {
+    let mut parts = vec![];
+    let mut rest = s;
+    while let Some(open) = rest.find('{') {
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed `{{` in {:?}", s))?;
+        let name = &rest[open + 1..open + close];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "unknown placeholder {{{}}}, expected one of {{{}}}",
+                name,
+                PLACEHOLDERS.join("}, {")
+            ));
+        }
+        if open > 0 {
+            parts.push(Part::Literal(rest[..open].into()));
+        } else if let Some(Part::Placeholder(_)) = parts.last() {
+            return Err(format!(
+                "placeholders in {:?} need something between them",
+                s
+            ));
+        }
+        parts.push(Part::Placeholder(name.into()));
+        rest = &rest[open + close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.into()));
+    }
+    Ok(parts)
+}
+
+/// Makes sure a pattern has what lines of its kind need.
+fn check(kind: Kind, pattern: &[Part]) -> Result<(), String> {
+    let has = |name: &str| {
+        pattern
+            .iter()
+            .any(|part| matches!(part, Part::Placeholder(n) if n == name))
+    };
+    let missing = match kind {
+        Kind::Note if !has("text") => Some("{text}"),
+        Kind::Note => None,
+        _ if !has("rule") => Some("{rule}"),
+        _ if !(has("pos") || has("line") && has("col")) => Some("{pos}, or {line} and {col}"),
+        _ => None,
+    };
+    match missing {
+        Some(missing) => Err(format!("this pattern needs {}", missing)),
+        None => Ok(()),
+    }
+}
+
+/// Matches `line` against `pattern`, placeholders taking as little as they
+/// can, except for the last one.
+fn matches(pattern: &[Part], line: &str, captures: &mut Vec<(String, String)>) -> bool {
+    match pattern.split_first() {
+        None => line.is_empty(),
+        Some((Part::Literal(literal), rest)) => match line.strip_prefix(literal.as_str()) {
+            Some(line) => matches(rest, line, captures),
+            None => false,
+        },
+        Some((Part::Placeholder(name), rest)) => {
+            let ends: Vec<usize> = match rest.first() {
+                Some(Part::Literal(next)) => {
+                    line.match_indices(next.as_str()).map(|(i, _)| i).collect()
+                }
+                _ => vec![line.len()],
+            };
+            for end in ends {
+                if end == 0 {
+                    continue;
+                }
+                captures.push((name.clone(), line[..end].into()));
+                if matches(rest, &line[end..], captures) {
+                    return true;
+                }
+                captures.pop();
+            }
+            false
+        }
+    }
+}
+
+fn location(pos: Option<&str>, line: Option<&str>, col: Option<&str>) -> Option<Location> {
+    match (pos, line, col) {
+        (Some(pos), _, _) => Some(Location::Offset(pos.parse().ok()?)),
+        (None, Some(line), Some(col)) => Some(Location::LineCol {
+            line: line.parse().ok()?,
+            column: col.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
@@ -0,0 +1,152 @@
+//! tree-sitter's parse logs, as printed by `tree-sitter parse --debug`:
+//!
+//! ```text
+//! new_parse
+//! process version:0, version_count:1, state:1, row:0, col:0
+//! lex_internal state:0, row:0, column:0
+//!   consume character:'1'
+//! lexed_lookahead sym:number, size:1
+//! shift state:3
+//! process version:0, version_count:1, state:3, row:0, col:1
+//! lex_internal state:0, row:0, column:1
+//!   consume character:'+'
+//! lexed_lookahead sym:+, size:1
+//! reduce sym:expression, child_count:1
+//! ```
+//!
+//! Like LALRPOP's, reductions become nodes, and tokens shifted their
+//! children. Tokens skipped and missing ones, during error recovery, are
+//! failures, and so are `ERROR` reductions. Only the first version of the
+//! parse is followed, when there are several.
+
+use super::{lr::Stack, Format, Reader};
+use crate::{Line, Location};
+
+pub(crate) struct TreeSitter;
+
+impl Format for TreeSitter {
+    fn name(&self) -> &'static str {
+        "tree-sitter"
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        line == "new_parse" || line.starts_with("process version:")
+    }
+
+    fn reader(&self, input: &str) -> Box<dyn Reader> {
+        Box::new(TreeSitterReader::new(input))
+    }
+}
+
+struct TreeSitterReader {
+    stack: Stack,
+    /// where each of the input's rows start
+    rows: Vec<usize>,
+    version: usize,
+    /// where the lexer started, and how much whitespace it skipped since
+    lex_start: usize,
+    skipped: usize,
+    lookahead: Option<(String, (Location, Location))>,
+}
+
+impl TreeSitterReader {
+    fn new(input: &str) -> Self {
+        let mut rows = vec![0];
+        rows.extend(input.match_indices('\n').map(|(i, _)| i + 1));
+        Self {
+            stack: Stack::default(),
+            rows,
+            version: 0,
+            lex_start: 0,
+            skipped: 0,
+            lookahead: None,
+        }
+    }
+}
+
+impl Reader for TreeSitterReader {
+    fn line(&mut self, log_line: usize, line: &str) -> Result<Vec<(usize, Line)>, String> {
+        let line = line.trim();
+        let (verb, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let field = |name: &str| -> Result<&str, String> {
+            rest.split(", ")
+                .find_map(|field| field.strip_prefix(name)?.strip_prefix(':'))
+                .ok_or_else(|| format!("expected `{}:` after `{}`", name, verb))
+        };
+        let number = |name: &str| -> Result<usize, String> {
+            field(name)?
+                .parse()
+                .map_err(|_| format!("expected a number for `{}:`", name))
+        };
+
+        if verb == "process" || verb == "resume" {
+            self.version = number("version")?;
+        }
+        if self.version != 0 {
+            return Ok(vec![]);
+        }
+        match verb {
+            "lex_internal" | "lex_external" => {
+                let row = number("row")?;
+                let column = number("column")?;
+                let row_start = self
+                    .rows
+                    .get(row)
+                    .copied()
+                    .ok_or_else(|| format!("row {} is past the end of the input", row))?;
+                self.lex_start = row_start + column;
+                self.skipped = 0;
+            }
+            "skip" => self.skipped += character(field("character")?)?.len_utf8(),
+            "lexed_lookahead" => {
+                let start = self.lex_start + self.skipped;
+                // the size includes the whitespace before the token
+                let end = (self.lex_start + number("size")?).max(start);
+                self.lookahead = Some((
+                    field("sym")?.into(),
+                    (Location::Offset(start), Location::Offset(end)),
+                ));
+            }
+            "shift" | "shift_extra" | "skip_token" => {
+                let (name, span) = self
+                    .lookahead
+                    .take()
+                    .ok_or_else(|| format!("`{}` before any token was lexed", verb))?;
+                self.stack
+                    .shift(&name, span, verb != "skip_token", log_line);
+            }
+            "recover_with_missing" => {
+                let at = match &self.lookahead {
+                    Some((_, (start, _))) => *start,
+                    None => Location::Offset(self.lex_start),
+                };
+                let name = format!("MISSING {}", field("symbol")?);
+                self.stack.shift(&name, (at, at), false, log_line);
+            }
+            "reduce" => {
+                let name = field("sym")?;
+                // error recovery rearranges the stack in ways the log
+                // doesn't show, reductions can't take more than there is
+                let count = number("child_count")?.min(self.stack.len());
+                self.stack.reduce(name, count, name != "ERROR", log_line)?;
+            }
+            _ => {}
+        }
+        Ok(vec![])
+    }
+
+    fn finish(&mut self) -> Vec<(usize, Line)> {
+        self.stack.finish()
+    }
+}
+
+/// A character as tree-sitter logs it: quoted when printable, its code
+/// otherwise.
+fn character(s: &str) -> Result<char, String> {
+    let quoted = s.strip_prefix('\'').and_then(|s| s.strip_suffix('\''));
+    let c = match quoted {
+        Some(quoted) => quoted.chars().next(),
+        None => s.parse().ok().and_then(char::from_u32),
+    };
+    c.ok_or_else(|| format!("expected a character, got {:?}", s))
+}
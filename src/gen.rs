@@ -0,0 +1,326 @@
+//! Synthetic trace logs, for testing pegviz itself, see `pegviz gen`.
+//!
+//! Generated traces are well-formed unless `--malformed` is given, in which
+//! case some lines get corrupted the way real-world logs do: cut short,
+//! duplicated, dropped, or interleaved with unrelated output.
+
+use crate::Location;
+use argh::FromArgs;
+use std::io::{self, Write};
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "gen")]
+/// generate a synthetic trace log on stdout, for testing pegviz itself
+pub(crate) struct GenArgs {
+    #[argh(option, default = "100")]
+    /// number of rule attempts in each trace
+    pub(crate) nodes: usize,
+
+    #[argh(option, default = "8")]
+    /// maximum nesting depth of rule attempts
+    pub(crate) depth: usize,
+
+    #[argh(option, default = "0")]
+    /// random seed: the same seed always generates the same log
+    pub(crate) seed: u64,
+
+    #[argh(option, default = "1")]
+    /// number of traces to generate
+    pub(crate) traces: usize,
+
+    #[argh(switch)]
+    /// corrupt some of the lines
+    pub(crate) malformed: bool,
+}
+
+pub(crate) fn run(args: &GenArgs) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    out.write_all(generate(args).as_bytes())
+}
+
+/// Generates the whole log.
+pub(crate) fn generate(args: &GenArgs) -> String {
+    let mut rng = Rng::new(args.seed);
+    let mut lines = vec![];
+    for i in 0..args.traces {
+        if rng.chance(30) {
+            lines.push(format!("running test case {}", i));
+        }
+        Generator::new(args, &mut rng, &mut lines).trace();
+    }
+    if args.malformed {
+        lines = corrupt(&mut rng, lines);
+    }
+
+    let mut res = String::new();
+    for line in lines {
+        res.push_str(&line);
+        res.push('\n');
+    }
+    res
+}
+
+const RULES: &[&str] = &[
+    "expr", "term", "factor", "atom", "number", "ident", "string", "ws", "list", "item",
+];
+
+struct Generator<'a> {
+    args: &'a GenArgs,
+    rng: &'a mut Rng,
+    lines: &'a mut Vec<String>,
+    input: String,
+    /// attempts left to generate
+    budget: usize,
+    /// peg 0.5 printed `name at 1:1 (pos 0)`, later versions use backquotes
+    old_style: bool,
+}
+
+impl<'a> Generator<'a> {
+    fn new(args: &'a GenArgs, rng: &'a mut Rng, lines: &'a mut Vec<String>) -> Self {
+        let len = args.nodes * 2 + 1;
+        let input = (0..len)
+            .map(|_| match rng.below(10) {
+                0 => '\n',
+                1 => ' ',
+                2 => 'é',
+                n => (b'a' + n as u8) as char,
+            })
+            .collect();
+        let old_style = rng.chance(20);
+        Generator {
+            args,
+            rng,
+            lines,
+            input,
+            budget: args.nodes,
+            old_style,
+        }
+    }
+
+    fn trace(&mut self) {
+        self.lines.push("[PEG_INPUT_START]".into());
+        let input = self.input.clone();
+        self.lines.extend(input.lines().map(String::from));
+        self.lines.push("[PEG_TRACE_START]".into());
+        while self.budget > 0 {
+            self.node(0, 0);
+        }
+        self.lines.push("[PEG_TRACE_STOP]".into());
+        if self.rng.chance(30) {
+            // the last char is the one the parser choked on
+            let last = self.input.char_indices().last().map_or(0, |(i, _)| i);
+            let loc = self.loc(last);
+            self.lines.push(format!(
+                "Error: error at {}: expected one of \"a\", \"b\"",
+                loc
+            ));
+        }
+    }
+
+    /// Generates a rule attempt at byte offset `pos`, returns where it
+    /// stopped matching, if it matched.
+    fn node(&mut self, pos: usize, depth: usize) -> Option<usize> {
+        self.budget -= 1;
+        let name = RULES[self.rng.below(RULES.len())];
+        self.line("Attempting to match", name, pos, None);
+
+        let mut end = pos;
+        if depth < self.args.depth {
+            for _ in 0..self.rng.below(4) {
+                if self.budget == 0 {
+                    break;
+                }
+                if self.rng.chance(10) {
+                    self.line("Cached match of", name, end, None);
+                }
+                if self.rng.chance(3) {
+                    self.lines.push("[PEG_NOTE] entering recovery mode".into());
+                }
+                if let Some(child_end) = self.node(end, depth + 1) {
+                    end = child_end;
+                }
+            }
+        }
+
+        if self.rng.chance(60) {
+            if self.rng.chance(50) {
+                end = self.next_char(end);
+            }
+            self.line("Matched", name, pos, Some(end));
+            Some(end)
+        } else {
+            self.line("Failed to match", name, pos, None);
+            None
+        }
+    }
+
+    fn line(&mut self, what: &str, name: &str, pos: usize, end: Option<usize>) {
+        let loc = self.loc(pos);
+        let mut line = if self.old_style {
+            format!(
+                "[PEG_TRACE] {} rule {} at {} (pos {})",
+                what, name, loc, pos
+            )
+        } else {
+            format!("[PEG_TRACE] {} rule `{}` at {}", what, name, loc)
+        };
+        // only newer versions of peg say where successes stopped
+        if let (Some(end), false) = (end, self.old_style) {
+            line.push_str(&format!(" to {}", self.loc(end)));
+        }
+        self.lines.push(line);
+    }
+
+    fn loc(&self, pos: usize) -> Location {
+        crate::line_col(&self.input, pos)
+    }
+
+    fn next_char(&self, pos: usize) -> usize {
+        match self.input[pos..].chars().next() {
+            Some(c) => pos + c.len_utf8(),
+            None => pos,
+        }
+    }
+}
+
+/// Applies the kinds of damage real-world logs suffer from to a few lines.
+fn corrupt(rng: &mut Rng, lines: Vec<String>) -> Vec<String> {
+    let mut res = vec![];
+    for line in lines {
+        if !rng.chance(3) {
+            res.push(line);
+            continue;
+        }
+        match rng.below(5) {
+            // cut short, e.g. by a crash or a full disk
+            0 => {
+                let mut cut = rng.below(line.len() + 1);
+                while !line.is_char_boundary(cut) {
+                    cut -= 1;
+                }
+                res.push(line[..cut].into());
+            }
+            // lost
+            1 => {}
+            // logged twice
+            2 => {
+                res.push(line.clone());
+                res.push(line);
+            }
+            // interleaved with output from another thread
+            3 => {
+                res.push("thread 'main' panicked at 'oh no', src/main.rs:1:1".into());
+                res.push(line);
+            }
+            // garbled
+            _ => res.push(line.replace('`', "").replace("rule", "rul")),
+        }
+    }
+    res
+}
+
+/// xorshift64*, good enough to generate test data, and stable across
+/// platforms and releases so seeds can be shared.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// True `percent`% of the time.
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::tests::{read, TestDir},
+        Node,
+    };
+
+    fn gen(seed: u64, malformed: bool) -> GenArgs {
+        GenArgs {
+            nodes: 60,
+            depth: 6,
+            seed,
+            traces: 3,
+            malformed,
+        }
+    }
+
+    fn count(node: &Node) -> usize {
+        1 + node.children.iter().map(count).sum::<usize>()
+    }
+
+    #[test]
+    fn same_seed_same_log() {
+        assert_eq!(generate(&gen(7, true)), generate(&gen(7, true)));
+        assert_ne!(generate(&gen(7, false)), generate(&gen(8, false)));
+    }
+
+    #[test]
+    fn well_formed_logs_parse() {
+        let dir = TestDir::new("well_formed_logs_parse");
+        for seed in 0..100 {
+            let args = gen(seed, false);
+            let traces = read(&dir, &generate(&args), &[]).unwrap();
+            assert_eq!(traces.len(), args.traces, "seed {}", seed);
+            for trace in &traces {
+                // the root isn't an attempt
+                assert_eq!(count(&trace.root) - 1, args.nodes, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn spilling_keeps_every_node() {
+        let dir = TestDir::new("spilling_keeps_every_node");
+        for seed in 0..20 {
+            let args = gen(seed, false);
+            for trace in read(&dir, &generate(&args), &["--spill"]).unwrap() {
+                assert_eq!(trace.spilled.unwrap().nodes, args.nodes, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn spilled_rule_stats_match() {
+        let dir = TestDir::new("spilled_rule_stats_match");
+        let log = generate(&gen(4, false));
+        let csv = |extra: &[&str]| {
+            let mut out = vec![];
+            crate::csv::write_csv(&mut out, &read(&dir, &log, extra).unwrap()).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(csv(&["--spill"]), csv(&[]));
+    }
+
+    #[test]
+    fn malformed_logs_dont_panic() {
+        let dir = TestDir::new("malformed_logs_dont_panic");
+        for seed in 0..300 {
+            let log = generate(&gen(seed, true));
+            // errors are fine, panics are not
+            read(&dir, &log, &[]).ok();
+            read(&dir, &log, &["--spill", "--dedupe"]).ok();
+        }
+    }
+}
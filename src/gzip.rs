@@ -0,0 +1,137 @@
+//! gzip, through `flate2`, for the logs embedded in reports (see
+//! `--embed-log`) and gzipped logs, along with the base64 that embedded
+//! logs are stored as.
+
+use flate2::{bufread::MultiGzDecoder, write::GzEncoder, Compression};
+use std::io::{self, BufRead, Read, Write};
+
+/// Compresses `data` into a gzip member.
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    // writing to a `Vec` can't fail
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Decompresses gzip data as it's read, which may hold several members one
+/// after the other, as `cat a.gz b.gz` produces.
+pub(crate) fn decoder<R: BufRead>(source: R) -> MultiGzDecoder<R> {
+    MultiGzDecoder::new(source)
+}
+
+/// Decompresses gzip data, see `decoder`.
+pub(crate) fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    decoder(data).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Standard base64, with padding.
+pub(crate) fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut res = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let v = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for k in 0..4 {
+            if k <= chunk.len() {
+                res.push(ALPHABET[(v >> (18 - 6 * k) & 0x3f) as usize] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+/// Decodes standard base64, padded or not.
+pub(crate) fn unbase64(text: &str) -> io::Result<Vec<u8>> {
+    let mut res = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut acc, mut n) = (0u32, 0);
+    for c in text.bytes().take_while(|&c| c != b'=') {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(invalid("invalid base64")),
+        };
+        acc = acc << 6 | v as u32;
+        n += 6;
+        if n >= 8 {
+            n -= 8;
+            res.push((acc >> n) as u8);
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log() -> Vec<u8> {
+        let mut log = vec![];
+        for i in 0..5000 {
+            log.extend_from_slice(
+                format!("[PEG_TRACE] Matched rule `expr` at 1:1 to 1:{}\n", i).as_bytes(),
+            );
+        }
+        log
+    }
+
+    #[test]
+    fn round_trip() {
+        let log = log();
+        let compressed = compress(&log);
+        assert!(compressed.len() < log.len() / 4);
+        assert_eq!(decompress(&compressed).unwrap(), log);
+        assert_eq!(decompress(&compress(b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn concatenated_members() {
+        let mut data = compress(b"first\n");
+        data.extend(compress(b"second\n"));
+        assert_eq!(decompress(&data).unwrap(), b"first\nsecond\n");
+    }
+
+    #[test]
+    fn corrupt_input_is_an_error() {
+        let compressed = compress(&log());
+        for len in [1, 9, 10, compressed.len() / 2, compressed.len() - 1] {
+            assert!(decompress(&compressed[..len]).is_err(), "length {}", len);
+        }
+        assert!(decompress(b"[PEG_INPUT_START]\n").is_err());
+
+        // a corrupt body fails the checksum if nothing else
+        let mut corrupt = compressed.clone();
+        corrupt[compressed.len() / 2] ^= 0x5a;
+        assert!(decompress(&corrupt).is_err());
+        // as does a corrupt checksum
+        let mut corrupt = compressed;
+        let crc = corrupt.len() - 8;
+        corrupt[crc] ^= 1;
+        assert!(decompress(&corrupt).is_err());
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        for len in 0..8 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 97) as u8).collect();
+            assert_eq!(unbase64(&base64(&data)).unwrap(), data);
+        }
+        assert_eq!(base64(b"pegviz"), "cGVndml6");
+        assert_eq!(unbase64("cGVndg").unwrap(), b"pegv");
+        assert!(unbase64("cGV!").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! Binary inputs, for grammars over `[u8]`. Their bytes are logged as hex
+//! after a `[PEG_INPUT_HEX]` marker (or raw, when they aren't text), and
+//! the trace's input stands in for them with one ASCII char per byte, so
+//! byte offsets stay where they were and snippets still line up.
+
+use std::io::{self, Write};
+
+/// Bytes written as hex: runs of digit pairs, or bytes apart from each
+/// other, as `{:x?}` prints a slice (`[89, 50, 4e, 47]`).
+pub(crate) fn decode(hex: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    for run in hex.split(|c: char| c.is_whitespace() || ",[]".contains(c)) {
+        let run = run.strip_prefix("0x").unwrap_or(run);
+        if run.len() > 2 && !run.len().is_multiple_of(2) {
+            return Err(format!("odd number of hex digits in {:?}", run));
+        }
+        for i in (0..run.len()).step_by(2) {
+            let byte = run.get(i..(i + 2).min(run.len())).unwrap_or("");
+            bytes.push(
+                u8::from_str_radix(byte, 16)
+                    .map_err(|_| format!("invalid hex byte {:?} in the input", byte))?,
+            );
+        }
+    }
+    Ok(bytes)
+}
+
+/// Whether `input`, logged as is, holds control characters no text has (or
+/// bytes that weren't UTF-8).
+pub(crate) fn looks_binary(input: &str) -> bool {
+    input
+        .chars()
+        .any(|c| c == '\u{fffd}' || c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+}
+
+/// What stands in for `bytes` as text: printable ASCII and newlines as they
+/// are, everything else as `.`.
+pub(crate) fn text(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| {
+            if b == b'\n' || b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// `bytes` as space-separated hex, `de ad be ef`.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Writes a dump of `bytes`, sixteen to a line with their offset and ASCII,
+/// marking the bytes at the positions in `marks`, each mark having its
+/// attributes.
+pub(crate) fn write_dump(
+    f: &mut dyn Write,
+    bytes: &[u8],
+    marks: &[(usize, &str)],
+) -> io::Result<()> {
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let start = line * 16;
+        write!(f, r#"<span class="offset">{:08x}</span>  "#, start)?;
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(b) => {
+                    let here: Vec<_> = marks.iter().filter(|(pos, _)| *pos == start + i).collect();
+                    for (_, attrs) in &here {
+                        write!(f, "<mark {}>", attrs)?;
+                    }
+                    write!(f, "{:02x}{} ", b, "</mark>".repeat(here.len()))?;
+                }
+                None => write!(f, "   ")?,
+            }
+            if i == 7 {
+                write!(f, " ")?;
+            }
+        }
+        let ascii = crate::escape_html(&text(chunk).replace('\n', "."));
+        writeln!(f, r#" <span class="ascii">|{}|</span>"#, ascii)?;
+    }
+    // a mark at the very end has no byte to go on
+    let end: Vec<_> = marks
+        .iter()
+        .filter(|(pos, _)| *pos >= bytes.len())
+        .collect();
+    if !end.is_empty() {
+        for (_, attrs) in &end {
+            write!(f, "<mark {}>", attrs)?;
+        }
+        writeln!(f, "end of input{}", "</mark>".repeat(end.len()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_hex() {
+        let png = [0x89, 0x50, 0x4e, 0x47];
+        assert_eq!(decode("89504e47").unwrap(), png);
+        assert_eq!(decode("89 50 4e 47").unwrap(), png);
+        assert_eq!(decode(&format!("{:x?}", png)).unwrap(), png);
+        assert_eq!(decode("0x89, 0x50, 0x4e, 0x47").unwrap(), png);
+        // `{:x?}` leaves out leading zeroes
+        assert_eq!(decode("[0, a, ff]").unwrap(), [0, 10, 255]);
+        assert_eq!(decode("").unwrap(), []);
+
+        assert!(decode("89504").is_err());
+        assert!(decode("zz").is_err());
+    }
+
+    #[test]
+    fn stand_ins_line_up() {
+        let bytes = b"GIF\x00\n\xff ok";
+        assert_eq!(text(bytes), "GIF.\n. ok");
+        assert_eq!(text(bytes).len(), bytes.len());
+        assert!(!looks_binary("a\tb\r\n"));
+        assert!(looks_binary("a\u{0}b"));
+        assert!(looks_binary(&String::from_utf8_lossy(b"a\xffb")));
+        assert_eq!(hex(&[0xde, 0xad, 0xbe, 0xef]), "de ad be ef");
+    }
+
+    #[test]
+    fn dumps_mark_bytes() {
+        let mut out = vec![];
+        write_dump(&mut out, &[0x41; 17], &[(1, "a"), (17, "b")]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0]
+            .starts_with(r#"<span class="offset">00000000</span>  41 <mark a>41</mark> 41"#));
+        assert!(lines[0].ends_with(r#"<span class="ascii">|AAAAAAAAAAAAAAAA|</span>"#));
+        assert!(lines[1].starts_with(r#"<span class="offset">00000010</span>  41 "#));
+        assert_eq!(lines[2], "<mark b>end of input</mark>");
+    }
+}
@@ -0,0 +1,247 @@
+//! Syntax highlighting of the input, see `--input-lang`. It's nothing like
+//! a real parser for the language: strings, numbers, comments and keywords
+//! are picked out with a few rules, which is enough to make spans easier to
+//! read, and never fails on input that doesn't parse.
+
+use crate::escape_html;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lang {
+    Json,
+    Sql,
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Lang::Json),
+            "sql" => Ok(Lang::Sql),
+            _ => Err(format!(
+                "unknown input language {:?}, expected one of: json, sql",
+                s
+            )),
+        }
+    }
+}
+
+const SQL_KEYWORDS: &[&str] = &[
+    "add",
+    "all",
+    "alter",
+    "and",
+    "as",
+    "asc",
+    "begin",
+    "between",
+    "by",
+    "case",
+    "cast",
+    "check",
+    "column",
+    "commit",
+    "constraint",
+    "create",
+    "cross",
+    "default",
+    "delete",
+    "desc",
+    "distinct",
+    "drop",
+    "else",
+    "end",
+    "exists",
+    "false",
+    "foreign",
+    "from",
+    "full",
+    "group",
+    "having",
+    "if",
+    "in",
+    "index",
+    "inner",
+    "insert",
+    "into",
+    "is",
+    "join",
+    "key",
+    "left",
+    "like",
+    "limit",
+    "not",
+    "null",
+    "offset",
+    "on",
+    "or",
+    "order",
+    "outer",
+    "primary",
+    "references",
+    "right",
+    "rollback",
+    "select",
+    "set",
+    "table",
+    "then",
+    "true",
+    "union",
+    "unique",
+    "update",
+    "using",
+    "values",
+    "view",
+    "when",
+    "where",
+    "with",
+];
+
+/// A highlighted stretch of the text, as byte offsets, and what it is.
+struct Span {
+    start: usize,
+    end: usize,
+    kind: &'static str,
+}
+
+pub(crate) struct Highlight {
+    /// in order, not overlapping
+    spans: Vec<Span>,
+}
+
+impl Lang {
+    /// Picks out what's worth highlighting in `text`.
+    pub(crate) fn highlight(self, text: &str) -> Highlight {
+        let bytes = text.as_bytes();
+        let mut spans = vec![];
+        let mut i = 0;
+        while i < bytes.len() {
+            let start = i;
+            let rest = &bytes[i..];
+            let kind = match bytes[i] {
+                b'-' if self == Lang::Sql && rest.starts_with(b"--") => {
+                    i += rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+                    "comment"
+                }
+                b'/' if self == Lang::Sql && rest.starts_with(b"/*") => {
+                    i += find(&rest[2..], b"*/").map_or(rest.len(), |end| end + 4);
+                    "comment"
+                }
+                quote @ (b'"' | b'\'') if quote == b'"' || self == Lang::Sql => {
+                    i = string_end(bytes, i, self);
+                    // object keys are strings too, but they read better apart
+                    let after = bytes[i..].iter().find(|b| !b.is_ascii_whitespace());
+                    if self == Lang::Json && after == Some(&b':') {
+                        "key"
+                    } else {
+                        "string"
+                    }
+                }
+                b'0'..=b'9' => {
+                    i = number_end(bytes, i);
+                    "number"
+                }
+                b'-' if self == Lang::Json && rest.get(1).is_some_and(u8::is_ascii_digit) => {
+                    i = number_end(bytes, i + 1);
+                    "number"
+                }
+                b if b.is_ascii_alphabetic() || b == b'_' => {
+                    i += rest
+                        .iter()
+                        .position(|&b| !(b.is_ascii_alphanumeric() || b == b'_'))
+                        .unwrap_or(rest.len());
+                    let word = &text[start..i];
+                    let keyword = match self {
+                        Lang::Json => matches!(word, "true" | "false" | "null"),
+                        Lang::Sql => SQL_KEYWORDS.contains(&word.to_ascii_lowercase().as_str()),
+                    };
+                    if !keyword {
+                        continue;
+                    }
+                    "keyword"
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            spans.push(Span {
+                start,
+                end: i,
+                kind,
+            });
+        }
+        Highlight { spans }
+    }
+}
+
+/// Where `needle` first is in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Just past the string starting with the quote at `start`, or the end of
+/// the text when it isn't closed. JSON escapes quotes with a backslash, SQL
+/// doubles them.
+fn string_end(bytes: &[u8], start: usize, lang: Lang) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if lang == Lang::Json => i += 1,
+            b if b == quote && lang == Lang::Sql && bytes.get(i + 1) == Some(&quote) => i += 1,
+            b if b == quote => return i + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Just past the number made of the digit at `start` and what follows it.
+fn number_end(bytes: &[u8], start: usize) -> usize {
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'0'..=b'9' | b'.' | b'e' | b'E' => {}
+            b'+' | b'-' if matches!(bytes[i - 1], b'e' | b'E') => {}
+            _ => break,
+        }
+        i += 1;
+    }
+    i
+}
+
+impl Highlight {
+    /// `text[start..end]` as HTML, with what's highlighted in it wrapped in
+    /// `hl-` spans. Spans stop at newlines, so the result can be cut into
+    /// lines.
+    pub(crate) fn html(&self, text: &str, start: usize, end: usize) -> String {
+        let mut res = String::new();
+        let mut written = start;
+        let first = self.spans.partition_point(|span| span.end <= start);
+        for span in &self.spans[first..] {
+            if span.start >= end {
+                break;
+            }
+            let (from, to) = (span.start.max(start), span.end.min(end));
+            res.push_str(&escape_html(&text[written..from]));
+            for (i, line) in text[from..to].split('\n').enumerate() {
+                if i > 0 {
+                    res.push('\n');
+                }
+                if !line.is_empty() {
+                    res.push_str(&format!(
+                        r#"<span class="hl-{}">{}</span>"#,
+                        span.kind,
+                        escape_html(line)
+                    ));
+                }
+            }
+            written = to;
+        }
+        res.push_str(&escape_html(&text[written..end]));
+        res
+    }
+}
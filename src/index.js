@@ -1,20 +1,1537 @@
+function notify(text) {
+  let notifs = document.getElementById("notifications");
+  let child = document.createElement("div");
+  child.classList.add("notification");
+  child.textContent = text;
+  notifs.appendChild(child);
+
+  setTimeout(() => {
+      child.classList.add("dead");
+      setTimeout(() => {
+          child.remove();
+      }, 1000);
+  }, 1000);
+}
+
+// Saves `blob` as `name`. The URL is only revoked once the download had a
+// chance to start: some browsers cancel it when that happens right away.
+function download(name, blob) {
+  let link = document.createElement("a");
+  link.href = URL.createObjectURL(blob);
+  link.download = name;
+  link.click();
+  setTimeout(() => URL.revokeObjectURL(link.href), 60 * 1000);
+}
+
 document.addEventListener("click", (ev) => {
   if (ev.ctrlKey && ev.target.classList.contains("rule")) {
     ev.preventDefault();
     let text = ev.target.innerText;
     navigator.clipboard.writeText(text);
+    notify(`Copied "${text}" to clipboard!`);
+  }
+});
+
+// A node's copy button copies its whole match, which the snippet has in
+// full (only the context around it is cut), without toggling the node.
+document.addEventListener("click", (ev) => {
+  let button = ev.target.closest("summary > button.copy");
+  if (button) {
+    ev.preventDefault();
+    let text = button.parentElement.querySelector(":scope > code > strong").textContent;
+    navigator.clipboard.writeText(text);
+    notify(`Copied ${[...text].length} chars to clipboard!`);
+  }
+});
+
+// shift-click prunes a node (and everything under it), alt-click flattens
+// it (keeps its children but not the node itself). Both only take effect in
+// the exported copy, clicking again undoes them.
+document.addEventListener("click", (ev) => {
+  if ((ev.shiftKey || ev.altKey) && ev.target.classList.contains("rule")) {
+    ev.preventDefault();
+    let details = ev.target.closest("details");
+    details.classList.toggle(ev.shiftKey ? "pruned" : "flattened");
+    let pruned = document.querySelectorAll("details.pruned, details.flattened").length;
+    document.querySelector("#toolbar .count").textContent = pruned;
+  }
+});
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.id === "export-pruned") {
+    let root = document.documentElement.cloneNode(true);
+    for (let el of root.querySelectorAll("details.pruned")) {
+      el.remove();
+    }
+    for (let el of root.querySelectorAll("details.flattened")) {
+      for (let child of [...el.children]) {
+        if (child.tagName !== "SUMMARY") {
+          el.before(child);
+        }
+      }
+      el.remove();
+    }
+    root.querySelector("#notifications").replaceChildren();
+    root.querySelector("#toolbar .count").textContent = "0";
+
+    let html = "<!DOCTYPE html>\n" + root.outerHTML;
+    download("pegviz-pruned.html", new Blob([html], { type: "text/html" }));
+    notify(`Exported ${Math.round(html.length / 1024)} KB`);
+  }
+});
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.classList.contains("raw-toggle")) {
+    // don't let the click toggle the surrounding <details>
+    ev.preventDefault();
+    let details = ev.target.closest("details");
+    let raw = details.querySelector(":scope > pre.raw");
+    raw.classList.toggle("visible");
+  }
+});
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.closest("span.loc")) {
+    // switch which position convention comes first, for every node at once
+    ev.preventDefault();
+    document.body.classList.toggle("byte-primary");
+  }
+});
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.id == "toggle-compact") {
+    document.body.classList.toggle("compact");
+  }
+});
+
+// With --lazy, a collapsed node's children are kept in a template until it's
+// expanded. What goes over the whole tree (searching, filtering, expanding
+// everything) builds it all first, links build the way to their node.
+function hydrate(details) {
+  let template = details.querySelector(":scope > template.children");
+  if (template) {
+    template.replaceWith(template.content);
+    sourceRoot = null;
+  }
+}
+
+function hydrateAll() {
+  for (let templates; (templates = document.querySelectorAll("template.children")).length > 0; ) {
+    for (let template of templates) {
+      template.replaceWith(template.content);
+    }
+    sourceRoot = null;
+  }
+}
+
+function holds(fragment, id) {
+  return fragment.getElementById(id) || [...fragment.querySelectorAll("template.children")].some((t) => holds(t.content, id));
+}
+
+function materialize(id) {
+  let el;
+  while (!(el = document.getElementById(id))) {
+    let template = [...document.querySelectorAll("template.children")].find((t) => holds(t.content, id));
+    if (!template) {
+      return null;
+    }
+    hydrate(template.parentElement);
+  }
+  return el;
+}
+
+// the child nodes of `details`, even while they're in its template
+function childDetails(details) {
+  let template = details.querySelector(":scope > template.children");
+  return [...(template ? template.content : details).children].filter((el) => el.matches("details[id^='node-']"));
+}
+
+document.addEventListener("toggle", (ev) => {
+  if (ev.target.open) {
+    hydrate(ev.target);
+  }
+}, true);
+
+// With --chunked, big subtrees are in scripts next to the report, loaded one
+// after the other once the page is up. Each puts its nodes where its
+// placeholder is, which may still be in a --lazy template.
+function findChunk(root, n) {
+  let found = root.querySelector(`template.chunk[data-chunk="${n}"]`);
+  for (let template of found ? [] : root.querySelectorAll("template.children")) {
+    found = found || findChunk(template.content, n);
+  }
+  return found;
+}
+
+function loadChunk(n, index) {
+  let script = document.createElement("script");
+  script.src = `${index.dataset.dir}/${n}.js`;
+  script.onerror = () => notify(`Couldn't load chunk ${n} from ${index.dataset.dir}`);
+  document.body.appendChild(script);
+}
+
+window.pegvizChunk = (n, html) => {
+  let index = document.getElementById("chunks");
+  let placeholder = findChunk(document, n);
+  if (placeholder) {
+    let template = document.createElement("template");
+    template.innerHTML = html;
+    placeholder.replaceWith(template.content);
+  }
+  sourceRoot = null;
+  if (cleanMarked) {
+    markClean();
+  }
+  if (location.hash && !document.getElementById(decodeURIComponent(location.hash.slice(1)))) {
+    revealHash();
+  }
+  if (n < Number(index.dataset.count)) {
+    loadChunk(n + 1, index);
+  } else {
+    notify(`Loaded all ${n} chunks`);
+  }
+};
+
+document.addEventListener("DOMContentLoaded", () => {
+  let index = document.getElementById("chunks");
+  if (index && Number(index.dataset.count) > 0) {
+    loadChunk(1, index);
+  }
+});
+
+// Expands or collapses the whole tree at once, from the toolbar or with "+"
+// and "-". Collapsing leaves each trace's top-level rules showing.
+function setAllOpen(open) {
+  if (open) {
+    hydrateAll();
+  }
+  for (let node of document.querySelectorAll("details[id^='node-']")) {
+    node.open = open || !node.parentElement.closest("details[id^='node-']");
+  }
+}
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.id == "expand-all" || ev.target.id == "collapse-all") {
+    setAllOpen(ev.target.id == "expand-all");
+  }
+});
+
+document.addEventListener("keydown", (ev) => {
+  if ((ev.key == "+" || ev.key == "-") && !ev.ctrlKey && !ev.metaKey && !ev.target.closest("input, textarea")) {
+    setAllOpen(ev.key == "+");
+  }
+});
+
+// Opens the tree down to a depth, from the toolbar's number: nodes above it
+// are open, so the rules at that depth show, and everything deeper is
+// closed. Top-level rules are at depth 1, like in `--format csv`.
+function openToDepth(max) {
+  let stack = [...document.querySelectorAll(ROOTS)].map((node) => [node, 0]);
+  while (stack.length > 0) {
+    let [node, depth] = stack.pop();
+    node.open = depth < max;
+    if (node.open) {
+      hydrate(node);
+    }
+    for (let child of node.querySelectorAll(":scope > details[id^='node-']")) {
+      stack.push([child, depth + 1]);
+    }
+  }
+}
+
+document.addEventListener("change", (ev) => {
+  if (ev.target.id == "open-depth" && ev.target.value !== "") {
+    openToDepth(Number(ev.target.value));
+  }
+});
+
+// "Failures only" hides the successful subtrees with no failure (or rule
+// that never finished) anywhere in them, leaving the paths the parser
+// backtracked on. Which subtrees those are is worked out the first time.
+let cleanMarked = false;
+
+function markClean() {
+  hydrateAll();
+  for (let node of document.querySelectorAll("details.clean")) {
+    node.classList.remove("clean");
+  }
+  let dirty = new Set();
+  // children come after their parents, going backwards they come first
+  let nodes = [...document.querySelectorAll("details[id^='node-']")].reverse();
+  for (let node of nodes) {
+    let rule = node.querySelector(":scope > summary > span.rule");
+    let parent = node.parentElement.closest("details[id^='node-']");
+    if (!rule.classList.contains("success") || dirty.has(node)) {
+      if (parent) {
+        dirty.add(parent);
+      }
+    } else if (parent) {
+      node.classList.add("clean");
+    }
+  }
+  cleanMarked = true;
+}
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.id == "toggle-failures-only") {
+    if (!cleanMarked) {
+      markClean();
+    }
+    document.body.classList.toggle("failures-only");
+  }
+});
+
+// "f", or the toolbar's button, shows the farthest failure of the trace
+// around the focused or hovered node, or of the first trace that failed, in
+// its input: the parse error's position, or where a rule failed farthest
+// when that's further along.
+function jumpToFarthest() {
+  let node = (document.activeElement && document.activeElement.closest(NODE)) || hovered;
+  let marks = [];
+  if (node) {
+    while (parentNode(node)) {
+      node = parentNode(node);
+    }
+    let id = node.id.slice("node-".length);
+    marks = [document.getElementById(`farthest-${id}`), document.getElementById(`error-${id}`)];
+  } else {
+    marks = [document.querySelector("mark.farthest-failure, mark.error-pos")];
+  }
+  let mark = marks.find((mark) => mark);
+  if (mark) {
+    reveal(mark);
+  } else {
+    notify("No failure to show");
+  }
+}
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.id == "jump-farthest") {
+    jumpToFarthest();
+  }
+});
+
+document.addEventListener("keydown", (ev) => {
+  if (ev.key == "f" && !ev.ctrlKey && !ev.metaKey && !ev.altKey && !ev.target.closest("input, textarea")) {
+    jumpToFarthest();
+  }
+});
+
+// Dark unless the browser prefers light, or whichever was last picked with
+// the toolbar's button, for every report.
+const SCHEME_KEY = "pegviz-color-scheme";
+const prefersLight = window.matchMedia("(prefers-color-scheme: light)");
+
+function applyScheme() {
+  let scheme = localStorage.getItem(SCHEME_KEY) || (prefersLight.matches ? "light" : "dark");
+  document.documentElement.classList.toggle("light", scheme == "light");
+}
+
+applyScheme();
+prefersLight.addEventListener("change", applyScheme);
+
+// Paper is white: printing is in the light scheme, whichever is picked.
+window.addEventListener("beforeprint", () => document.documentElement.classList.add("light"));
+window.addEventListener("afterprint", applyScheme);
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.id == "toggle-scheme") {
+    let light = document.documentElement.classList.contains("light");
+    localStorage.setItem(SCHEME_KEY, light ? "dark" : "light");
+    applyScheme();
+  }
+});
+
+// Opens all the <details> around `el` so it's visible, then scrolls to it.
+// With several traces, the index's selector shows one at a time, the first
+// to begin with ("all traces" is the long page). Going to something in
+// another trace switches to it.
+function showTrace(id) {
+  document.getElementById("trace-select").value = id;
+  document.body.classList.toggle("one-trace", id != "");
+  for (let section of document.querySelectorAll("section.trace")) {
+    section.classList.toggle("selected", section.id == id);
+  }
+}
+
+function showTraceOf(el) {
+  let section = el.closest("section.trace");
+  if (section && document.body.classList.contains("one-trace") && !section.classList.contains("selected")) {
+    showTrace(section.id);
+  }
+}
+
+document.addEventListener("DOMContentLoaded", () => {
+  let select = document.getElementById("trace-select");
+  if (select) {
+    showTrace(select.options[1].value);
+  }
+});
+
+document.addEventListener("change", (ev) => {
+  if (ev.target.id == "trace-select") {
+    showTrace(ev.target.value);
+  }
+});
+
+function reveal(el) {
+  showTraceOf(el);
+  for (let d = el.closest("details"); d; d = d.parentElement.closest("details")) {
+    d.open = true;
+  }
+  for (let prev of document.querySelectorAll(".targeted")) {
+    prev.classList.remove("targeted");
+  }
+  el.classList.add("targeted");
+  el.scrollIntoView({ block: "center" });
+}
+
+document.addEventListener("click", (ev) => {
+  let link = ev.target.closest("a[href^='#']");
+  if (link) {
+    let el = materialize(link.getAttribute("href").slice(1));
+    if (el) {
+      ev.preventDefault();
+      history.replaceState(null, "", link.getAttribute("href"));
+      reveal(el);
+    }
+  }
+});
+
+// Deep links: opening a report at `#node-1234` (or changing the hash) opens
+// the nodes around that one and scrolls to it, and clicking or focusing a
+// node puts its link in the address bar, ready to be shared.
+function revealHash() {
+  let el = location.hash && materialize(decodeURIComponent(location.hash.slice(1)));
+  if (el) {
+    reveal(el);
+  }
+}
+
+document.addEventListener("DOMContentLoaded", revealHash);
+window.addEventListener("hashchange", revealHash);
+
+// some browsers don't focus a summary on click
+for (let type of ["focusin", "click"]) {
+  document.addEventListener(type, (ev) => {
+    let summary = ev.target.closest("details[id^='node-'] > summary");
+    if (summary) {
+      history.replaceState(null, "", `#${summary.parentElement.id}`);
+    }
+  });
+}
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.classList.contains("copy")) {
+    // copies the <pre> of the surrounding <details>
+    ev.preventDefault();
+    let pre = ev.target.closest("details").querySelector(":scope > pre");
+    navigator.clipboard.writeText(pre.textContent);
+  }
+});
+
+// Hovering the summary of a collapsed node previews its first few levels of
+// descendants, without having to expand (and later collapse) it.
+const PREVIEW_DEPTH = 3;
+const PREVIEW_MAX = 40;
+let previewTimer = null;
+
+function buildPreview(details, depth, list) {
+  for (let child of childDetails(details)) {
+    if (list.childElementCount >= PREVIEW_MAX) {
+      return;
+    }
+    let rule = child.querySelector(":scope > summary > span.rule");
+    if (!rule) {
+      continue;
+    }
+    let item = document.createElement("div");
+    item.style.paddingLeft = `${(PREVIEW_DEPTH - depth) * 16}px`;
+    item.appendChild(rule.cloneNode(true));
+    list.appendChild(item);
+    if (depth > 1) {
+      buildPreview(child, depth - 1, list);
+    }
+  }
+}
+
+document.addEventListener("mouseover", (ev) => {
+  let summary = ev.target.closest("summary");
+  if (!summary || summary.parentElement.open || summary.dataset.previewed) {
+    return;
+  }
+  summary.dataset.previewed = "yes";
+  clearTimeout(previewTimer);
+  previewTimer = setTimeout(() => {
+    let details = summary.parentElement;
+    let list = document.createElement("div");
+    buildPreview(details, PREVIEW_DEPTH, list);
+    if (list.childElementCount == 0) {
+      return;
+    }
+    let preview = document.getElementById("preview") || document.createElement("div");
+    preview.id = "preview";
+    preview.replaceChildren(list);
+    if (list.childElementCount >= PREVIEW_MAX) {
+      let more = document.createElement("div");
+      more.textContent = "…";
+      preview.appendChild(more);
+    }
+    let rect = summary.getBoundingClientRect();
+    preview.style.left = `${rect.left + window.scrollX + 30}px`;
+    preview.style.top = `${rect.bottom + window.scrollY}px`;
+    document.body.appendChild(preview);
+  }, 400);
+});
+
+document.addEventListener("mouseout", (ev) => {
+  let summary = ev.target.closest("summary");
+  if (summary && !summary.contains(ev.relatedTarget)) {
+    delete summary.dataset.previewed;
+    clearTimeout(previewTimer);
+    let preview = document.getElementById("preview");
+    if (preview) {
+      preview.remove();
+    }
+  }
+});
+
+document.addEventListener("toggle", (ev) => {
+  // expanding makes the preview redundant
+  let preview = document.getElementById("preview");
+  if (preview && ev.target.open) {
+    preview.remove();
+  }
+}, true);
+
+// Bookmarks and notes, kept in localStorage per report. While hovering a
+// node, "b" toggles its bookmark and "a" edits its note.
+const ANNOTATIONS_KEY = `pegviz-annotations:${location.pathname}`;
+let hovered = null;
+
+function loadAnnotations() {
+  return JSON.parse(localStorage.getItem(ANNOTATIONS_KEY) || "{}");
+}
+
+function saveAnnotations(annotations) {
+  localStorage.setItem(ANNOTATIONS_KEY, JSON.stringify(annotations));
+  renderAnnotations(annotations);
+}
+
+function renderAnnotations(annotations) {
+  for (let el of document.querySelectorAll("details.bookmarked")) {
+    el.classList.remove("bookmarked");
+  }
+  for (let el of document.querySelectorAll("summary > span.note")) {
+    el.remove();
+  }
+  let list = document.querySelector("#annotations ul");
+  list.replaceChildren();
+
+  for (let [id, annotation] of Object.entries(annotations)) {
+    let details = materialize(id);
+    if (!details) {
+      continue;
+    }
+    if (annotation.bookmark) {
+      details.classList.add("bookmarked");
+    }
+    if (annotation.note) {
+      let note = document.createElement("span");
+      note.classList.add("note");
+      note.textContent = annotation.note;
+      details.querySelector(":scope > summary").appendChild(note);
+    }
+
+    let item = document.createElement("li");
+    let link = document.createElement("a");
+    link.href = `#${id}`;
+    link.textContent = details.querySelector(":scope > summary > span.rule").textContent;
+    item.appendChild(link);
+    if (annotation.note) {
+      item.append(` — ${annotation.note}`);
+    }
+    list.appendChild(item);
+  }
+  document.querySelector("#annotations .count").textContent = list.childElementCount;
+}
+
+document.addEventListener("mouseover", (ev) => {
+  let details = ev.target.closest("details[id^='node-']");
+  if (details) {
+    hovered = details;
+  }
+});
+
+document.addEventListener("keydown", (ev) => {
+  if (!hovered || ev.ctrlKey || ev.metaKey || ev.altKey || ev.target.closest("input, textarea")) {
+    return;
+  }
+  let annotations = loadAnnotations();
+  let annotation = annotations[hovered.id] || {};
+  if (ev.key == "b") {
+    annotation.bookmark = !annotation.bookmark;
+  } else if (ev.key == "a") {
+    let note = prompt("Note for this node (empty to remove it):", annotation.note || "");
+    if (note === null) {
+      return;
+    }
+    annotation.note = note.trim();
+  } else {
+    return;
+  }
+  if (annotation.bookmark || annotation.note) {
+    annotations[hovered.id] = annotation;
+  } else {
+    delete annotations[hovered.id];
+  }
+  saveAnnotations(annotations);
+});
+
+// While hovering a node, "e" downloads it and its descendants as JSON, with
+// the same fields as the nodes of `--format json`.
+function jsonLocation(text) {
+  if (text === undefined) {
+    return null;
+  }
+  if (text.startsWith("@")) {
+    return { offset: Number(text.slice(1)) };
+  }
+  let [line, column] = text.split(":").map(Number);
+  return { line, column };
+}
+
+// The notes logged among a node's children, `before` being the index of the
+// child they come before.
+function childNotes(details) {
+  let template = details.querySelector(":scope > template.children");
+  let notes = [];
+  let before = 0;
+  for (let el of (template ? template.content : details).children) {
+    if (el.matches("details[id^='node-']")) {
+      before++;
+    } else if (el.matches("div.peg-note")) {
+      notes.push({ before, text: el.textContent, log_line: Number(el.title.match(/^line (\d+)/)[1]) });
+    }
+  }
+  return notes;
+}
+
+function nodeJson(details) {
+  let summary = details.querySelector(":scope > summary");
+  let rule = summary.querySelector(":scope > span.rule");
+  let logLine = summary.querySelector(":scope > span.log-line");
+  let { start, end } = details.dataset;
+  return {
+    id: Number(details.id.slice("node-".length)),
+    rule: rule.textContent,
+    state: ["success", "failure", "unknown"].find((c) => rule.classList.contains(c)),
+    partial_match: rule.classList.contains("partial-match"),
+    loc: jsonLocation(details.dataset.loc),
+    next_loc: jsonLocation(details.dataset.nextLoc),
+    start: start === undefined ? null : Number(start),
+    end: end === undefined ? null : Number(end),
+    log_line: logLine ? Number(logLine.textContent.slice(1)) : 0,
+    notes: childNotes(details),
+    children: childDetails(details).map(nodeJson),
+  };
+}
+
+document.addEventListener("keydown", (ev) => {
+  if (!hovered || ev.key != "e" || ev.ctrlKey || ev.metaKey || ev.altKey || ev.target.closest("input, textarea")) {
+    return;
+  }
+  let root = hovered;
+  while (root.parentElement.closest("details[id^='node-']")) {
+    root = root.parentElement.closest("details[id^='node-']");
+  }
+  let json = JSON.stringify({ trace: traceLabel(root), node: nodeJson(hovered) }, null, 2);
+  download(`pegviz-${hovered.id}.json`, new Blob([json], { type: "application/json" }));
+  notify(`Exported ${hovered.querySelectorAll("details[id^='node-']").length + 1} nodes`);
+});
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.id == "export-annotations") {
+    let json = JSON.stringify(loadAnnotations(), null, 2);
+    download("pegviz-annotations.json", new Blob([json], { type: "application/json" }));
+  } else if (ev.target.id == "import-annotations") {
+    document.getElementById("import-annotations-file").click();
+  }
+});
+
+document.addEventListener("change", async (ev) => {
+  if (ev.target.id == "import-annotations-file") {
+    let file = ev.target.files[0];
+    if (!file) {
+      return;
+    }
+    try {
+      let imported = JSON.parse(await file.text());
+      saveAnnotations(Object.assign(loadAnnotations(), imported));
+      notify(`Imported ${Object.keys(imported).length} annotations`);
+    } catch (e) {
+      notify(`Could not import annotations: ${e}`);
+    }
+    ev.target.value = "";
+  }
+});
+
+document.addEventListener("DOMContentLoaded", () => {
+  renderAnnotations(loadAnnotations());
+});
+
+// logs embedded with `--embed-log`, decompressed by the browser
+document.addEventListener("click", async (ev) => {
+  if (ev.target.classList.contains("download-log")) {
+    let bytes = Uint8Array.from(atob(ev.target.dataset.log), (c) => c.charCodeAt(0));
+    let blob = new Blob([bytes]);
+    let name = ev.target.dataset.name;
+    if (window.DecompressionStream) {
+      blob = await new Response(blob.stream().pipeThrough(new DecompressionStream("gzip"))).blob();
+    } else {
+      name += ".gz";
+    }
+    download(name, blob);
+  }
+});
+
+// Searches every trace in the report at once, for when it's not clear which
+// one is relevant. Rule names and matched text both count, results link to
+// the nodes.
+const SEARCH_MAX = 200;
+let searchTimer = null;
+
+function traceLabel(root) {
+  let source = root.previousElementSibling;
+  while (source && !source.classList.contains("trace-source")) {
+    source = source.previousElementSibling;
+  }
+  return source && source.textContent.trim() ? source.textContent.trim() : "trace";
+}
+
+function globalSearch(query) {
+  let results = document.querySelector("#global-search .results");
+  results.replaceChildren();
+  if (!query) {
+    return;
+  }
+  hydrateAll();
+  let groups = new Map();
+  let total = 0;
+  for (let summary of document.querySelectorAll("details[id^='node-'] > summary")) {
+    let rule = summary.querySelector(":scope > span.rule");
+    let matched = summary.querySelector(":scope > code > strong");
+    let text = matched ? matched.textContent : "";
+    if (!rule || !(rule.textContent.includes(query) || text.includes(query))) {
+      continue;
+    }
+    total += 1;
+    if (total > SEARCH_MAX) {
+      continue;
+    }
+    let root = summary.parentElement;
+    while (root.parentElement.closest("details[id^='node-']")) {
+      root = root.parentElement.closest("details[id^='node-']");
+    }
+    if (!groups.has(root)) {
+      groups.set(root, []);
+    }
+    groups.get(root).push({ id: summary.parentElement.id, rule: rule.textContent, text });
+  }
+
+  let heading = document.createElement("div");
+  heading.textContent = total > SEARCH_MAX
+    ? `${total} matches, showing the first ${SEARCH_MAX}`
+    : `${total} matches`;
+  results.appendChild(heading);
+  for (let [root, hits] of groups) {
+    let group = document.createElement("details");
+    group.open = groups.size == 1;
+    let summary = document.createElement("summary");
+    summary.textContent = `${traceLabel(root)} (${hits.length})`;
+    group.appendChild(summary);
+    for (let hit of hits) {
+      let link = document.createElement("a");
+      link.href = `#${hit.id}`;
+      link.textContent = hit.rule;
+      let item = document.createElement("div");
+      item.appendChild(link);
+      if (hit.text) {
+        let code = document.createElement("code");
+        code.textContent = hit.text.length > 40 ? hit.text.slice(0, 40) + "…" : hit.text;
+        item.append(" ", code);
+      }
+      group.appendChild(item);
+    }
+    results.appendChild(group);
+  }
+}
+
+document.addEventListener("input", (ev) => {
+  if (ev.target.closest("#global-search")) {
+    clearTimeout(searchTimer);
+    searchTimer = setTimeout(() => globalSearch(ev.target.value), 200);
+  }
+});
+
+// Filters the tree by rule name (or, with `exact`, a whole one): matching
+// rules are highlighted, the nodes around them opened, and with "only
+// matches" checked the others are hidden. Enter in the box goes to the next
+// match.
+let filterTimer = null;
+let filterIndex = -1;
+
+function filterTree(query, exact) {
+  for (let el of document.querySelectorAll(".filter-hit, .filter-path")) {
+    el.classList.remove("filter-hit", "filter-path");
+  }
+  filterIndex = -1;
+  document.body.classList.toggle("filtering", query != "");
+  let count = document.querySelector("#tree-search .count");
+  if (!query) {
+    count.textContent = "";
+    return;
+  }
+  hydrateAll();
+  let hits = 0;
+  for (let rule of document.querySelectorAll("details[id^='node-'] > summary > span.rule")) {
+    if (exact ? rule.textContent != query : !rule.textContent.includes(query)) {
+      continue;
+    }
+    hits += 1;
+    rule.classList.add("filter-hit");
+    let node = rule.closest("details");
+    node.classList.add("filter-path");
+    for (let d = node.parentElement.closest("details"); d; d = d.parentElement.closest("details")) {
+      if (d.open && d.classList.contains("filter-path")) {
+        break;
+      }
+      d.open = true;
+      d.classList.add("filter-path");
+    }
+  }
+  count.textContent = `${hits} matches`;
+}
+
+document.addEventListener("input", (ev) => {
+  if (ev.target.closest("#tree-search") && ev.target.type == "search") {
+    clearTimeout(filterTimer);
+    filterTimer = setTimeout(() => filterTree(ev.target.value), 200);
+  }
+});
+
+document.addEventListener("change", (ev) => {
+  if (ev.target.closest("#tree-search") && ev.target.type == "checkbox") {
+    document.body.classList.toggle("filter-only", ev.target.checked);
+  }
+});
+
+document.addEventListener("keydown", (ev) => {
+  if (ev.key == "Enter" && ev.target.closest("#tree-search")) {
+    let hits = document.querySelectorAll("span.rule.filter-hit");
+    if (hits.length > 0) {
+      filterIndex = (filterIndex + 1) % hits.length;
+      reveal(hits[filterIndex]);
+    }
+  }
+});
+
+// Keyboard navigation: j/k or the arrows move between the visible nodes,
+// Enter toggles the focused one, and h/l (or left/right) collapse and expand
+// it, going to its parent or first child when there's nothing to do. The
+// focused node is also the one b, n and e apply to.
+const NODE = "details[id^='node-']";
+const ROOTS = `${NODE}:not(${NODE} ${NODE})`;
+
+function shown(el) {
+  return el.getClientRects().length > 0;
+}
+
+function parentNode(node) {
+  return node.parentElement.closest(NODE);
+}
+
+function childNodes(node) {
+  return [...node.querySelectorAll(`:scope > ${NODE}`)].filter(shown);
+}
+
+function siblingNodes(node) {
+  let parent = parentNode(node);
+  return parent ? childNodes(parent) : [...document.querySelectorAll(ROOTS)].filter(shown);
+}
+
+function nextNode(node) {
+  let children = node.open ? childNodes(node) : [];
+  if (children.length > 0) {
+    return children[0];
+  }
+  for (let n = node; n; n = parentNode(n)) {
+    let siblings = siblingNodes(n);
+    let i = siblings.indexOf(n);
+    if (i + 1 < siblings.length) {
+      return siblings[i + 1];
+    }
+  }
+  return null;
+}
+
+function previousNode(node) {
+  let siblings = siblingNodes(node);
+  let i = siblings.indexOf(node);
+  if (i <= 0) {
+    return parentNode(node);
+  }
+  let n = siblings[i - 1];
+  for (let children; n.open && (children = childNodes(n)).length > 0; ) {
+    n = children[children.length - 1];
+  }
+  return n;
+}
+
+function focusNode(node) {
+  let summary = node.querySelector(":scope > summary");
+  summary.focus({ preventScroll: true });
+  summary.scrollIntoView({ block: "nearest" });
+  hovered = node;
+  showInSource(node);
+}
+
+// "n" and "p", or the toolbar's buttons, go to the next or previous failed
+// node in document order, opening the nodes around it. Failures hidden by a
+// filter are skipped, collapsed ones aren't.
+function hiddenByFilter(node) {
+  for (let n = node; n; n = parentNode(n)) {
+    if (getComputedStyle(n).display == "none") {
+      return true;
+    }
+  }
+  return false;
+}
+
+function goToFailure(forward) {
+  hydrateAll();
+  let current = document.activeElement && document.activeElement.closest(NODE);
+  let failed = [...document.querySelectorAll(`${NODE} > summary > span.rule.failure`)].map(
+    (rule) => rule.parentElement.parentElement
+  );
+  if (!forward) {
+    failed.reverse();
+  }
+  let direction = forward ? Node.DOCUMENT_POSITION_FOLLOWING : Node.DOCUMENT_POSITION_PRECEDING;
+  let after = (node) => !current || current.compareDocumentPosition(node) & direction;
+  let target = failed.find((node) => after(node) && !hiddenByFilter(node));
+  if (!target) {
+    notify(`No ${forward ? "next" : "previous"} failure`);
+    return;
+  }
+  showTraceOf(target);
+  for (let d = parentNode(target); d; d = parentNode(d)) {
+    d.open = true;
+  }
+  focusNode(target);
+}
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.id == "next-failure" || ev.target.id == "previous-failure") {
+    goToFailure(ev.target.id == "next-failure");
+  }
+});
+
+document.addEventListener("keydown", (ev) => {
+  // arrow keys in the trace selector pick a trace, and space and enter press
+  // buttons
+  if (ev.ctrlKey || ev.metaKey || ev.altKey || ev.target.closest("input, textarea, select, button")) {
+    return;
+  }
+  let current = document.activeElement && document.activeElement.closest(NODE);
+  let target = null;
+  switch (ev.key) {
+    case "n":
+    case "p":
+      ev.preventDefault();
+      goToFailure(ev.key == "n");
+      return;
+    case "j":
+    case "ArrowDown":
+      target = current ? nextNode(current) : [...document.querySelectorAll(ROOTS)].find(shown);
+      break;
+    case "k":
+    case "ArrowUp":
+      target = current && previousNode(current);
+      break;
+    case "l":
+    case "ArrowRight":
+      if (current && !current.open) {
+        current.open = true;
+      } else {
+        target = current && childNodes(current)[0];
+      }
+      break;
+    case "h":
+    case "ArrowLeft":
+      if (current && current.open) {
+        current.open = false;
+      } else {
+        target = current && parentNode(current);
+      }
+      break;
+    case "Enter":
+      if (current) {
+        current.open = !current.open;
+      }
+      break;
+    default:
+      return;
+  }
+  if (!current && !target) {
+    return;
+  }
+  ev.preventDefault();
+  if (target) {
+    focusNode(target);
+  }
+});
+
+// The source panel, beside the tree: hovering a node shows its trace's input
+// there, with what the node matched marked, and hovering the input outlines
+// the innermost visible node that matched there (clicking goes to it). Nodes
+// have byte offsets, the input's string counts UTF-16 units, hence `toIndex`
+// and `toByte`.
+let sourceRoot = null;
+let source = null;
+let sourceNode = null;
+let sourceHovered = null;
+
+function loadSource(root) {
+  let template = document.querySelector(`template.source[data-root="${root.id}"]`);
+  if (!template) {
+    return null;
+  }
+  let text = template.content.textContent;
+  let toIndex = [];
+  let toByte = [];
+  let [index, byte] = [0, 0];
+  for (let c of text) {
+    let code = c.codePointAt(0);
+    let len = code < 0x80 ? 1 : code < 0x800 ? 2 : code < 0x10000 ? 3 : 4;
+    for (let i = 0; i < len; i++) {
+      toIndex.push(index);
+    }
+    for (let i = 0; i < c.length; i++) {
+      toByte.push(byte);
+    }
+    index += c.length;
+    byte += len;
+  }
+  toIndex.push(index);
+  toByte.push(byte);
+  let nodes = [...root.querySelectorAll("details[data-start]")].map((el) => ({
+    el,
+    start: Number(el.dataset.start),
+    end: Math.max(Number(el.dataset.start), Number(el.dataset.end ?? el.dataset.start)),
+  }));
+  return { text, toIndex, toByte, nodes };
+}
+
+function showInSource(node) {
+  let panel = document.getElementById("source-panel");
+  if (panel.hidden) {
+    return;
+  }
+  let root = node;
+  for (let parent = parentNode(root); parent; parent = parentNode(parent)) {
+    root = parent;
+  }
+  if (root !== sourceRoot) {
+    sourceRoot = root;
+    source = loadSource(root);
+    if (source) {
+      source.hits = inputHits(source.text);
+    }
+    panel.querySelector(".title").textContent = traceLabel(root);
+  }
+  sourceNode = node;
+  let pre = panel.querySelector("pre");
+  if (!source) {
+    pre.replaceChildren();
+    return;
+  }
+  let length = source.text.length;
+  if (node.dataset.start === undefined) {
+    pre.replaceChildren(...withHits(0, length));
+    return;
+  }
+  let at = (byte) => source.toIndex[Math.min(Number(byte), source.toIndex.length - 1)];
+  let start = at(node.dataset.start);
+  let end = Math.max(start, at(node.dataset.end ?? node.dataset.start));
+  let mark = document.createElement("mark");
+  mark.append(...withHits(start, end));
+  mark.classList.toggle("empty", start == end);
+  pre.replaceChildren(...withHits(0, start), mark, ...withHits(end, length));
+  // the panel scrolls, not the page
+  pre.scrollTop = mark.offsetTop - pre.offsetTop - pre.clientHeight / 3;
+}
+
+// The source text from `from` to `to`, with the input search's matches in it
+// marked.
+function withHits(from, to) {
+  let parts = [];
+  for (let [i, [start, end]] of source.hits.entries()) {
+    if (end <= from || start >= to) {
+      continue;
+    }
+    let [s, e] = [Math.max(start, from), Math.min(end, to)];
+    if (s > from) {
+      parts.push(source.text.slice(from, s));
+    }
+    let hit = document.createElement("mark");
+    hit.classList.add("input-hit");
+    hit.dataset.hit = i;
+    hit.textContent = source.text.slice(s, e);
+    parts.push(hit);
+    from = e;
+  }
+  if (from < to) {
+    parts.push(source.text.slice(from, to));
+  }
+  return parts;
+}
 
-    let notifs = document.getElementById("notifications");
-    let child = document.createElement("div");
-    child.classList.add("notification");
-    child.innerHTML = `Copied "${text}" to clipboard!`;
-    notifs.appendChild(child);
-
-    setTimeout(() => {
-        child.classList.add("dead");
-        setTimeout(() => {
-            child.remove();
-        }, 1000);
-    }, 1000);
+// The byte offset of the input under the mouse, in the source panel.
+function sourceOffset(ev, pre) {
+  let [container, offset] = [null, 0];
+  if (document.caretPositionFromPoint) {
+    let pos = document.caretPositionFromPoint(ev.clientX, ev.clientY);
+    [container, offset] = pos ? [pos.offsetNode, pos.offset] : [null, 0];
+  } else if (document.caretRangeFromPoint) {
+    let range = document.caretRangeFromPoint(ev.clientX, ev.clientY);
+    [container, offset] = range ? [range.startContainer, range.startOffset] : [null, 0];
+  }
+  if (!container || container.nodeType != Node.TEXT_NODE || !pre.contains(container)) {
+    return null;
+  }
+  let walker = document.createTreeWalker(pre, NodeFilter.SHOW_TEXT);
+  let index = offset;
+  for (let text = walker.nextNode(); text && text !== container; text = walker.nextNode()) {
+    index += text.length;
+  }
+  return source.toByte[Math.min(index, source.toByte.length - 1)];
+}
+
+function innermostAt(byte) {
+  let best = null;
+  for (let node of source.nodes) {
+    if (node.start <= byte && byte < node.end
+        && (!best || node.end - node.start <= best.end - best.start)
+        && shown(node.el)) {
+      best = node;
+    }
+  }
+  return best && best.el;
+}
+
+function setSourceHovered(node) {
+  if (sourceHovered) {
+    sourceHovered.classList.remove("source-hovered");
+  }
+  sourceHovered = node;
+  if (node) {
+    node.classList.add("source-hovered");
+  }
+}
+
+document.addEventListener("click", (ev) => {
+  if (ev.target.id == "toggle-source-panel") {
+    let panel = document.getElementById("source-panel");
+    panel.hidden = !panel.hidden;
+    document.body.classList.toggle("with-source-panel", !panel.hidden);
+    if (!sourceRoot) {
+      panel.querySelector(".title").textContent = "hover a node to see its input here";
+    }
+  } else if (sourceHovered && ev.target.closest("#source-panel pre")) {
+    reveal(sourceHovered);
+  }
+});
+
+document.addEventListener("mouseover", (ev) => {
+  let summary = ev.target.closest("details[id^='node-'] > summary");
+  if (summary) {
+    showInSource(summary.parentElement);
+  }
+});
+
+document.addEventListener("mousemove", (ev) => {
+  let pre = ev.target.closest("#source-panel pre");
+  if (!pre || !source) {
+    return;
+  }
+  let byte = sourceOffset(ev, pre);
+  let node = byte === null ? null : innermostAt(byte);
+  if (node !== sourceHovered) {
+    setSourceHovered(node);
+  }
+});
+
+document.addEventListener("mouseout", (ev) => {
+  let panel = document.getElementById("source-panel");
+  if (ev.target.closest("#source-panel") && !panel.contains(ev.relatedTarget)) {
+    setSourceHovered(null);
+  }
+});
+
+// The path to the focused (or last clicked) node, in a bar that stays at the
+// top of the page, each rule on it going to its node.
+function showBreadcrumb(node) {
+  let bar = document.getElementById("breadcrumb");
+  let path = [];
+  for (let n = node; n; n = parentNode(n)) {
+    path.unshift(n);
+  }
+  bar.replaceChildren();
+  for (let n of path) {
+    if (bar.childElementCount > 0) {
+      bar.append(" › ");
+    }
+    let link = document.createElement("a");
+    link.href = `#${n.id}`;
+    link.textContent = n.querySelector(":scope > summary > span.rule").textContent;
+    link.classList.toggle("current", n === node);
+    bar.appendChild(link);
+  }
+  bar.hidden = false;
+  bar.scrollLeft = bar.scrollWidth;
+}
+
+document.addEventListener("focusin", (ev) => {
+  if (ev.target.matches("details[id^='node-'] > summary")) {
+    showBreadcrumb(ev.target.parentElement);
+  }
+});
+
+document.addEventListener("click", (ev) => {
+  let summary = ev.target.closest("details[id^='node-'] > summary");
+  if (summary) {
+    showBreadcrumb(summary.parentElement);
+  }
+});
+
+// While scrolling through a deep subtree, the trace and the nearest rule
+// whose summary went off the top of the page, pinned above the tree with the
+// breadcrumb.
+function updateScrollContext() {
+  let bar = document.getElementById("scroll-context");
+  let y = Math.max(document.getElementById("pinned").getBoundingClientRect().bottom, 0) + 1;
+  let ancestor = null;
+  for (let el of document.elementsFromPoint(window.innerWidth / 2, y)) {
+    let node = el.closest(NODE);
+    if (node) {
+      let summary = node.querySelector(":scope > summary");
+      ancestor = summary.getBoundingClientRect().bottom <= y ? node : parentNode(node);
+      break;
+    }
+  }
+  if (!ancestor) {
+    bar.hidden = true;
+    return;
+  }
+  let root = ancestor;
+  while (parentNode(root)) {
+    root = parentNode(root);
+  }
+  let section = root.closest("section.trace");
+  if (!section) {
+    bar.hidden = true;
+    return;
+  }
+  let trace = document.createElement("a");
+  trace.href = `#${section.id}`;
+  trace.title = traceLabel(root);
+  trace.textContent = section.id.replace("-", " ");
+  let grammar = section.querySelector(":scope > .trace-source > span.grammar");
+  if (grammar) {
+    trace.textContent += ` (${grammar.textContent})`;
+  }
+  let rule = (node) => {
+    let link = document.createElement("a");
+    link.href = `#${node.id}`;
+    link.textContent = node.querySelector(":scope > summary > span.rule").textContent;
+    return link;
+  };
+  bar.replaceChildren(trace, " › ", rule(root));
+  if (ancestor !== root) {
+    bar.append(parentNode(ancestor) === root ? " › " : " › … › ", rule(ancestor));
+  }
+  bar.hidden = false;
+}
+
+let scrollContextQueued = false;
+document.addEventListener(
+  "scroll",
+  () => {
+    if (!scrollContextQueued) {
+      scrollContextQueued = true;
+      requestAnimationFrame(() => {
+        scrollContextQueued = false;
+        updateScrollContext();
+      });
+    }
+  },
+  { passive: true }
+);
+
+// `--hide` and `--flatten`, tried out in the browser: the report's rules are
+// listed, most attempted first, with checkboxes to hide them or flatten them
+// (nodes with a single child being replaced by it, as the options do). The
+// options that would do the same for good are shown below.
+function ruleNodes() {
+  let rules = new Map();
+  for (let node of document.querySelectorAll("details[data-start]")) {
+    let name = node.querySelector(":scope > summary > span.rule").textContent;
+    if (!rules.has(name)) {
+      rules.set(name, []);
+    }
+    rules.get(name).push(node);
+  }
+  return rules;
+}
+
+let controlledRules = null;
+
+function buildRuleControls(panel) {
+  hydrateAll();
+  controlledRules = ruleNodes();
+  let table = panel.querySelector("table");
+  let heading = document.createElement("tr");
+  for (let title of ["rule", "nodes", "hide", "flatten"]) {
+    let th = document.createElement("th");
+    th.textContent = title;
+    heading.appendChild(th);
+  }
+  table.replaceChildren(heading);
+  let sorted = [...controlledRules].sort((a, b) => b[1].length - a[1].length);
+  for (let [name, nodes] of sorted) {
+    let row = document.createElement("tr");
+    let code = document.createElement("code");
+    code.textContent = name;
+    row.insertCell().appendChild(code);
+    row.insertCell().textContent = nodes.length;
+    for (let action of ["hide", "flatten"]) {
+      let checkbox = document.createElement("input");
+      checkbox.type = "checkbox";
+      checkbox.dataset.rule = name;
+      checkbox.dataset.action = action;
+      row.insertCell().appendChild(checkbox);
+    }
+    table.appendChild(row);
+  }
+}
+
+document.addEventListener("toggle", (ev) => {
+  if (ev.target.id == "rule-controls" && ev.target.open && !controlledRules) {
+    buildRuleControls(ev.target);
+  }
+}, true);
+
+document.addEventListener("change", (ev) => {
+  let checkbox = ev.target.closest("#rule-controls input[type=checkbox]");
+  if (!checkbox) {
+    return;
+  }
+  let { rule, action } = checkbox.dataset;
+  for (let node of controlledRules.get(rule)) {
+    if (action == "hide") {
+      node.classList.toggle("rt-hidden", checkbox.checked);
+    } else if (node.querySelectorAll(":scope > details[id^='node-']").length == 1) {
+      node.classList.toggle("rt-flattened", checkbox.checked);
+      if (checkbox.checked) {
+        node.open = true;
+      }
+    }
+  }
+  let flags = [...document.querySelectorAll("#rule-controls input:checked")]
+    .map((c) => `--${c.dataset.action} ${c.dataset.rule}`);
+  document.querySelector("#rule-controls .flags").textContent = flags.join(" ") || "no options";
+});
+
+// The statistics sidebar: each rule's counts, from the JSON pegviz embeds,
+// sorted by the column whose heading was clicked last. Clicking a rule
+// filters the tree down to its nodes.
+const STATS_COLUMNS = [
+  ["rule", "rule"],
+  ["attempts", "tried"],
+  ["successes", "ok"],
+  ["failures", "failed"],
+  ["cache_hits", "cached"],
+  ["max_depth", "depth"],
+  ["matched_bytes", "bytes"],
+];
+let statsSort = "attempts";
+
+function renderStats() {
+  let stats = JSON.parse(document.getElementById("rule-stats").textContent);
+  stats.sort((a, b) => statsSort == "rule" ? a.rule.localeCompare(b.rule) : b[statsSort] - a[statsSort]);
+  let table = document.querySelector("#stats-sidebar table");
+  let heading = document.createElement("tr");
+  for (let [column, title] of STATS_COLUMNS) {
+    let th = document.createElement("th");
+    th.textContent = title;
+    th.title = `sort by ${column.replace("_", " ")}`;
+    th.dataset.sort = column;
+    heading.appendChild(th);
+  }
+  table.replaceChildren(heading);
+  for (let rule of stats) {
+    let row = table.insertRow();
+    row.dataset.rule = rule.rule;
+    for (let [column] of STATS_COLUMNS) {
+      row.insertCell().textContent = rule[column];
+    }
+  }
+}
+
+document.addEventListener("click", (ev) => {
+  let sidebar = document.getElementById("stats-sidebar");
+  if (ev.target.id == "toggle-stats") {
+    sidebar.hidden = !sidebar.hidden;
+    document.body.classList.toggle("with-stats-sidebar", !sidebar.hidden);
+    if (!sidebar.hidden) {
+      renderStats();
+    }
+    return;
+  }
+  let th = ev.target.closest("#stats-sidebar th");
+  if (th) {
+    statsSort = th.dataset.sort;
+    renderStats();
+    return;
+  }
+  let row = ev.target.closest("#stats-sidebar tr[data-rule]");
+  if (row) {
+    for (let selected of sidebar.querySelectorAll("tr.selected")) {
+      selected.classList.remove("selected");
+    }
+    row.classList.add("selected");
+    // the tree shows rule names without their grammar
+    let name = row.dataset.rule.split("::").pop();
+    document.querySelector("#tree-search input[type=search]").value = name;
+    document.querySelector("#tree-search input[type=checkbox]").checked = true;
+    document.body.classList.add("filter-only");
+    filterTree(name, true);
+  }
+});
+
+// Searching the input: a regular expression, looked for in the input of
+// every trace. Matches are highlighted in the source panel, and listed by
+// trace with the rules attempted where they start. Clicking one shows it in
+// the source panel.
+const INPUT_HITS_MAX = 10000;
+let inputQuery = null;
+let inputTimer = null;
+
+function inputHits(text) {
+  let hits = [];
+  if (!inputQuery) {
+    return hits;
+  }
+  inputQuery.lastIndex = 0;
+  for (let m; hits.length < INPUT_HITS_MAX && (m = inputQuery.exec(text)); ) {
+    if (m[0].length == 0) {
+      inputQuery.lastIndex += 1;
+      continue;
+    }
+    hits.push([m.index, m.index + m[0].length]);
+  }
+  return hits;
+}
+
+function inputSearch(query) {
+  let results = document.querySelector("#input-search .results");
+  results.replaceChildren();
+  inputQuery = null;
+  if (query) {
+    try {
+      inputQuery = new RegExp(query, "g");
+    } catch (e) {
+      results.textContent = e.message;
+    }
+  }
+  if (source) {
+    source.hits = inputHits(source.text);
+    showInSource(sourceNode);
+  }
+  if (!inputQuery) {
+    return;
+  }
+  hydrateAll();
+  let total = 0;
+  let groups = [];
+  for (let root of document.querySelectorAll(ROOTS)) {
+    let src = root === sourceRoot ? source : loadSource(root);
+    let hits = src ? inputHits(src.text) : [];
+    if (hits.length == 0) {
+      continue;
+    }
+    total += hits.length;
+    let group = document.createElement("details");
+    let summary = document.createElement("summary");
+    summary.textContent = `${traceLabel(root)} (${hits.length})`;
+    group.appendChild(summary);
+    for (let [i, [start, end]] of hits.slice(0, SEARCH_MAX).entries()) {
+      let item = document.createElement("div");
+      let code = document.createElement("code");
+      code.classList.add("input-hit");
+      code.dataset.root = root.id;
+      code.dataset.hit = i;
+      let text = src.text.slice(start, end);
+      code.textContent = text.length > 40 ? text.slice(0, 40) + "…" : text;
+      item.appendChild(code);
+      // the first node of each rule attempted there
+      let byte = src.toByte[start];
+      let rules = new Map();
+      for (let node of src.nodes.filter((node) => node.start == byte)) {
+        let name = node.el.querySelector(":scope > summary > span.rule").textContent;
+        if (!rules.has(name)) {
+          rules.set(name, node.el.id);
+        }
+      }
+      for (let [name, id] of rules) {
+        let link = document.createElement("a");
+        link.href = `#${id}`;
+        link.textContent = name;
+        item.append(" ", link);
+      }
+      group.appendChild(item);
+    }
+    groups.push(group);
+  }
+  if (groups.length == 1) {
+    groups[0].open = true;
+  }
+  let heading = document.createElement("div");
+  heading.textContent = `${total} matches`;
+  results.replaceChildren(heading, ...groups);
+}
+
+document.addEventListener("input", (ev) => {
+  if (ev.target.closest("#input-search")) {
+    clearTimeout(inputTimer);
+    inputTimer = setTimeout(() => inputSearch(ev.target.value), 200);
+  }
+});
+
+document.addEventListener("click", (ev) => {
+  let code = ev.target.closest("#input-search code.input-hit");
+  if (!code) {
+    return;
+  }
+  let panel = document.getElementById("source-panel");
+  if (panel.hidden) {
+    document.getElementById("toggle-source-panel").click();
+  }
+  showInSource(document.getElementById(code.dataset.root));
+  let pre = panel.querySelector("pre");
+  let hit = pre.querySelector(`mark.input-hit[data-hit="${code.dataset.hit}"]`);
+  if (hit) {
+    pre.scrollTop = hit.offsetTop - pre.offsetTop - pre.clientHeight / 3;
   }
 });
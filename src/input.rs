@@ -0,0 +1,1334 @@
+use crate::{
+    encoding::{self, Encoding},
+    expect,
+    format::{self, Reader, Sniffed, Spec, TraceFormat},
+    gzip, hexdump,
+    highlight::Highlight,
+    libtest::{TestCase, Tests},
+    offsets,
+    run_info::Hashing,
+    spill::{Frame, NodeStore, SpilledTree},
+    timestamp::{self, Timestamp},
+    tokens::{self, Token},
+    tracer, Args, Growth, Line, Location, Node, Note, PositionKind, Rule, State,
+};
+use sha2::{Digest as _, Sha256};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// A single trace, along with the input it was run against.
+pub(crate) struct Trace {
+    pub(crate) root: Node,
+    pub(crate) input: String,
+    /// for binary inputs, their bytes, `input` having one char for each,
+    /// see `hexdump`
+    pub(crate) binary: Option<Vec<u8>>,
+    /// for token-based grammars, the lexer's tokens from a `[PEG_TOKENS]`
+    /// section, see `tokens`
+    pub(crate) tokens: Vec<Token>,
+    /// file the trace was read from, `None` for stdin
+    pub(crate) source: Option<String>,
+    /// timestamp of the `[PEG_INPUT_START]` marker, if the log had one
+    pub(crate) timestamp: Option<Timestamp>,
+    /// the error the parser reported, if it was printed after the trace
+    pub(crate) error: Option<ParseError>,
+    /// which grammar produced the trace, for programs with several of them
+    pub(crate) grammar: Option<String>,
+    /// position among all the traces read, kept or not, starting at 0
+    pub(crate) index: usize,
+    /// with `--spill`, the root's children live on disk rather than in
+    /// `root.children`
+    pub(crate) spilled: Option<SpilledTree>,
+    /// with `--dedupe`, the indices of the later traces that were identical
+    /// to this one, and dropped
+    pub(crate) duplicates: Vec<usize>,
+    /// number of `[PEG_INPUT_START]` chunks `input` was stitched from
+    pub(crate) chunks: usize,
+    /// stable hash of `input`, as captured, see `--expect`
+    pub(crate) input_hash: u64,
+    /// whether the trace should have succeeded, with `--expect`
+    pub(crate) expected: Option<bool>,
+    /// where the last successful top-level rule stopped, filled in before
+    /// rendering
+    pub(crate) consumed: Option<Location>,
+    /// with `--input-lang`, the input's syntax highlighting, filled in
+    /// before rendering
+    pub(crate) highlight: Option<Highlight>,
+    /// the libtest test that printed the trace, when reading `cargo test`
+    /// output
+    pub(crate) test: Option<TestCase>,
+    /// what the trace's bare-integer locations counted, before they were
+    /// turned into byte offsets
+    pub(crate) position_kind: PositionKind,
+    /// how many times each rule's result came from the cache, which leaves
+    /// no node in the tree
+    pub(crate) cache_hits: HashMap<String, usize>,
+}
+
+impl Trace {
+    /// `name`, qualified with the trace's grammar when it has one, so that
+    /// rules of different grammars are told apart in statistics.
+    pub(crate) fn qualified_rule(&self, name: &str) -> String {
+        match &self.grammar {
+            Some(grammar) => format!("{}::{}", grammar, name),
+            None => name.to_string(),
+        }
+    }
+}
+
+/// A peg `ParseError`, as printed by its `Display` impl:
+/// `error at 3:14: expected one of …`
+pub(crate) struct ParseError {
+    pub(crate) loc: Location,
+    pub(crate) expected: String,
+}
+
+/// Somewhere to read traces from.
+pub(crate) enum Source {
+    Stdin,
+    File(PathBuf),
+    /// a log embedded in an earlier report, see `--append`
+    Embedded {
+        report: PathBuf,
+        name: String,
+        log: String,
+    },
+}
+
+impl Source {
+    /// Turns the positional arguments into sources: no arguments means stdin,
+    /// and so does `-`, among files. Directories stand for all the files they
+    /// contain (in name order), and so do globs, for shells that don't expand
+    /// them.
+    pub(crate) fn expand(paths: &[PathBuf]) -> io::Result<Vec<Source>> {
+        if paths.is_empty() {
+            return Ok(vec![Source::Stdin]);
+        }
+        if paths.iter().filter(|path| is_stdin(path)).count() > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pegviz: `-` can only be given once, stdin can't be read twice",
+            ));
+        }
+
+        let mut expanded = vec![];
+        for path in paths {
+            if is_glob(path) {
+                let matches = glob(path)?;
+                if matches.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("pegviz: no files match {}", path.display()),
+                    ));
+                }
+                expanded.extend(matches);
+            } else {
+                expanded.push(path.clone());
+            }
+        }
+
+        let mut sources = vec![];
+        for path in &expanded {
+            if is_stdin(path) {
+                sources.push(Source::Stdin);
+            } else if path.is_dir() {
+                let mut entries = fs::read_dir(path)?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<io::Result<Vec<_>>>()?;
+                entries.retain(|entry| entry.is_file());
+                entries.sort();
+                sources.extend(entries.into_iter().map(Source::File));
+            } else {
+                sources.push(Source::File(path.clone()));
+            }
+        }
+        Ok(sources)
+    }
+
+    /// The logs embedded in `report` with `--embed-log`, in the order they
+    /// were read.
+    pub(crate) fn embedded_in(report: &Path) -> Result<Vec<Source>, Box<dyn Error>> {
+        const NAME: &str = r#"class="download-log" data-name=""#;
+        const LOG: &str = r#"" data-log=""#;
+        let html = fs::read_to_string(report)?;
+        let mut sources = vec![];
+        let mut rest = html.as_str();
+        while let Some(i) = rest.find(NAME) {
+            rest = &rest[i + NAME.len()..];
+            let bad = || format!("pegviz: malformed embedded log in {}", report.display());
+            let (name, after) = rest.split_once(LOG).ok_or_else(bad)?;
+            let (data, after) = after.split_once('"').ok_or_else(bad)?;
+            let log = gzip::decompress(&gzip::unbase64(data)?)?;
+            sources.push(Source::Embedded {
+                report: report.to_path_buf(),
+                name: unescape_html(name),
+                log: String::from_utf8(log)?,
+            });
+            rest = after;
+        }
+        Ok(sources)
+    }
+
+    /// Opens the source for reading, as UTF-8 whatever it was written in.
+    /// Files are hashed into `hash` as they're read, see `RunInfo`.
+    fn open(&self, hash: &Rc<Cell<u64>>) -> io::Result<Box<dyn BufRead>> {
+        let reader: Box<dyn BufRead> = match self {
+            Source::Stdin => Box::new(BufReader::new(io::stdin())),
+            Source::File(path) => Box::new(BufReader::new(Hashing::new(
+                File::open(path)?,
+                hash.clone(),
+            ))),
+            Source::Embedded { log, .. } => Box::new(io::Cursor::new(log.clone().into_bytes())),
+        };
+        let reader = self.decompress(reader)?;
+        let (encoding, reader) = encoding::decode(reader)?;
+        let endianness = match encoding {
+            Encoding::Utf8 => None,
+            Encoding::Utf16Le => Some("little"),
+            Encoding::Utf16Be => Some("big"),
+        };
+        if let Some(endianness) = endianness {
+            status!(
+                "= pegviz {} is UTF-16 ({} endian), converting it",
+                self.label().as_deref().unwrap_or("stdin"),
+                endianness
+            );
+        }
+        Ok(reader)
+    }
+
+    /// Decompresses gzip and zstd files, recognized by their magic bytes, or
+    /// their extension when they don't have them (and won't decompress).
+    fn decompress(&self, mut reader: Box<dyn BufRead>) -> io::Result<Box<dyn BufRead>> {
+        let extension = match self {
+            Source::File(path) => path.extension().and_then(|e| e.to_str()),
+            _ => None,
+        };
+        let zstd = match reader.fill_buf()? {
+            [0x1f, 0x8b, ..] => false,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => true,
+            _ if extension == Some("gz") => false,
+            _ if extension == Some("zst") => true,
+            _ => return Ok(reader),
+        };
+        status!(
+            "= pegviz {} is {}-compressed, decompressing it",
+            self.label().as_deref().unwrap_or("stdin"),
+            if zstd { "zstd" } else { "gzip" }
+        );
+        Ok(if zstd {
+            zstd_decoder(reader)?
+        } else {
+            Box::new(BufReader::new(gzip::decoder(reader)))
+        })
+    }
+
+    pub(crate) fn stem(&self) -> Option<String> {
+        match self {
+            Source::Stdin => None,
+            Source::File(path) => {
+                // `trace.log.gz` is `trace`, like `trace.log`
+                let path = match path.extension().and_then(|e| e.to_str()) {
+                    Some("gz" | "zst") => path.with_extension(""),
+                    _ => path.clone(),
+                };
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+            }
+            Source::Embedded { name, .. } => {
+                Some(name.strip_suffix(".log").unwrap_or(name).to_string())
+            }
+        }
+    }
+
+    pub(crate) fn label(&self) -> Option<String> {
+        match self {
+            Source::Stdin => None,
+            Source::File(path) => Some(path.display().to_string()),
+            Source::Embedded { report, name, .. } => {
+                Some(format!("{} in {}", name, report.display()))
+            }
+        }
+    }
+}
+
+/// What `-` is passed on as, NUL being the one thing no path has in it.
+pub(crate) const STDIN: &str = "\0-";
+
+#[cfg(feature = "zstd")]
+fn zstd_decoder(reader: Box<dyn BufRead>) -> io::Result<Box<dyn BufRead>> {
+    Ok(Box::new(BufReader::new(crate::zstd::Decoder::new(reader))))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decoder(_reader: Box<dyn BufRead>) -> io::Result<Box<dyn BufRead>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pegviz: built without the `zstd` feature, decompress the log first",
+    ))
+}
+
+fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == STDIN
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.contains(['*', '?']))
+}
+
+/// The paths matching `pattern`, in name order: `*` is anything but a `/`,
+/// `?` a single character, and `**` any number of directories. Hidden files
+/// only match a pattern that starts with a dot.
+fn glob(pattern: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut candidates = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let component = component.as_os_str();
+        let wildcard = match component.to_str() {
+            Some(c) if c.contains(['*', '?']) => c,
+            _ => {
+                for candidate in &mut candidates {
+                    candidate.push(component);
+                }
+                continue;
+            }
+        };
+        let mut next = vec![];
+        for dir in &candidates {
+            if wildcard == "**" {
+                let mut stack = vec![dir.clone()];
+                while let Some(dir) = stack.pop() {
+                    for entry in read_dir_sorted(&dir)? {
+                        if entry.is_dir() && !is_hidden(&entry) {
+                            stack.push(entry);
+                        }
+                    }
+                    next.push(dir);
+                }
+                continue;
+            }
+            for entry in read_dir_sorted(dir)? {
+                let name: Vec<char> = entry
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("")
+                    .chars()
+                    .collect();
+                if (!is_hidden(&entry) || wildcard.starts_with('.'))
+                    && wildcard_matches(&wildcard.chars().collect::<Vec<_>>(), &name)
+                {
+                    next.push(entry);
+                }
+            }
+        }
+        candidates = next;
+    }
+    candidates.retain(|path| path.exists());
+    candidates.sort();
+    candidates.dedup();
+    Ok(candidates)
+}
+
+/// The entries of `dir`, none when it isn't a directory.
+fn read_dir_sorted(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let read = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    if !read.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut entries = vec![];
+    for entry in fs::read_dir(read)? {
+        entries.push(dir.join(entry?.file_name()));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.'))
+}
+
+/// Whether `name` matches `pattern`, of `*` and `?`. When a character
+/// doesn't match, only the last `*` has to take one more character: earlier
+/// ones can't do any better, so there's no backtracking further than that,
+/// which bounds the work by the product of both lengths.
+fn wildcard_matches(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // the pattern after the last `*`, and where in `name` it resumes
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match &mut star {
+                Some((after, from)) => {
+                    *from += 1;
+                    p = *after;
+                    n = *from;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Which traces to build, see `--first`, `--last` and `--sample`. Traces
+/// are counted in reading order, across all sources.
+pub(crate) struct Selection {
+    first: Option<usize>,
+    last: Option<usize>,
+    sample: Option<usize>,
+    seen: usize,
+    /// traces that `keep_next` said to build, which is what `--first` counts
+    kept: usize,
+    /// with `--dedupe`, the index of the first trace seen for each digest
+    hashes: Option<HashMap<[u8; 32], usize>>,
+}
+
+impl Selection {
+    pub(crate) fn new(args: &Args) -> Self {
+        Selection {
+            first: args.first,
+            last: args.last,
+            sample: args.sample.map(|s| s.0),
+            seen: 0,
+            kept: 0,
+            hashes: if args.dedupe {
+                Some(HashMap::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Called for each trace as it starts, tells whether to build it.
+    fn keep_next(&mut self) -> bool {
+        let index = self.seen;
+        self.seen += 1;
+        let keep = self.first.is_none_or(|first| self.kept < first)
+            && self.sample.is_none_or(|k| index.is_multiple_of(k));
+        if keep {
+            self.kept += 1;
+        }
+        keep
+    }
+
+    /// Whether no more traces will be kept, so reading can stop early.
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.first.is_some_and(|first| self.kept >= first)
+    }
+
+    /// Drops older traces beyond `--last`.
+    fn trim(&self, traces: &mut Vec<Trace>) {
+        if let Some(last) = self.last {
+            if traces.len() > last {
+                traces.drain(..traces.len() - last);
+            }
+        }
+    }
+
+    /// With `--dedupe`, finds the kept trace that the trace at `index` is
+    /// identical to, going by the digests of their input, grammar and trace
+    /// lines. The input and grammar, which are still around, are compared
+    /// as well.
+    fn original_of<'a>(
+        &mut self,
+        digest: [u8; 32],
+        index: usize,
+        input: &str,
+        grammar: &Option<String>,
+        traces: &'a mut [Trace],
+    ) -> Option<&'a mut Trace> {
+        let hashes = self.hashes.as_mut()?;
+        let original = *hashes.entry(digest).or_insert(index);
+        if original == index {
+            return None;
+        }
+        // traces are still in reading order at this point
+        match traces.binary_search_by_key(&original, |t| t.index) {
+            // without a tag, `--tag-by-input` may have given the original
+            // a grammar
+            Ok(i)
+                if traces[i].input == input
+                    && (grammar.is_none() || traces[i].grammar == *grammar) =>
+            {
+                Some(&mut traces[i])
+            }
+            // a digest collision, both are kept
+            Ok(_) => None,
+            Err(_) => {
+                // the original was dropped by `--last`, this one takes over
+                hashes.insert(digest, index);
+                None
+            }
+        }
+    }
+
+    /// Number of traces encountered so far, kept or not.
+    pub(crate) fn seen(&self) -> usize {
+        self.seen
+    }
+}
+
+/// SHA-256 of a trace's fields, for `--dedupe`: a trace that only
+/// collides with another one on a 64-bit hash would otherwise be dropped.
+#[derive(Default)]
+struct Digest(Sha256);
+
+impl Digest {
+    /// Adds a field, prefixed with its length so that fields can't run
+    /// into each other.
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.update((bytes.len() as u64).to_le_bytes());
+        self.0.update(bytes);
+    }
+
+    fn digest(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+/// Reads the traces from `source` into `traces`. Output that occurs between
+/// traces is ignored. With `capture`, the whole log is also copied there,
+/// even past the last trace `selection` wants. Returns the FNV-1a hash of
+/// file sources, for `RunInfo`.
+pub(crate) fn read_traces(
+    source: &Source,
+    args: &Args,
+    selection: &mut Selection,
+    traces: &mut Vec<Trace>,
+    mut capture: Option<&mut String>,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    enum ParseState {
+        WaitingForInputStart,
+        ReadingInput,
+        ReadingTrace,
+        SkippingTrace,
+        /// only capturing what's left
+        Done,
+    }
+    let mut state = ParseState::WaitingForInputStart;
+    // whether a parse error would belong to the last trace in `traces`
+    let mut expecting_error = false;
+    let mut stack: Vec<Node> = vec![];
+    // with `--spill`, completed nodes go to `store` instead of their
+    // parent's children, `frames` mirrors `stack`
+    let mut store: Option<NodeStore> = None;
+    let mut frames: Vec<Frame> = vec![];
+    let mut input = String::new();
+    // the input's bytes as logged, for raw binary inputs, see `binary_input`
+    let mut raw_input: Vec<u8> = vec![];
+    // set when the `[PEG_INPUT_START]` marker had a timestamp: the logger
+    // is then expected to prefix every line, including the input's.
+    let mut start: Option<Timestamp> = None;
+    let mut grammar: Option<String> = None;
+    // whether the input was logged as hex, after `[PEG_INPUT_HEX]`
+    let mut hex = false;
+    let mut binary: Option<Vec<u8>> = None;
+    // token lines, once the input's `[PEG_TOKENS]` section starts
+    let mut token_lines: Option<Vec<String>> = None;
+    let mut tokens = vec![];
+    let mut chunks = 0;
+    let mut cache_hits = HashMap::new();
+    // covers the input, grammar and trace lines, for `--dedupe`
+    let mut hasher = Digest::default();
+    let mut tests = Tests::default();
+
+    let hash = Rc::new(Cell::new(0));
+    let mut reader = source.open(&hash)?;
+    // see `--source`, for traces that come without their input
+    let given_input = match &args.source {
+        Some(path) => Some(fs::read_to_string(path)?),
+        None => None,
+    };
+    let spec = match &args.format_spec {
+        Some(path) => Some(Spec::load(path)?),
+        None => None,
+    };
+    let mut trace_format = args.trace_format.unwrap_or(TraceFormat::PEG);
+    // whether the format is to be told from the first trace line, the log's
+    // first lines having none
+    let mut detecting = false;
+    if args.trace_format.is_none() && spec.is_none() {
+        let name = source.label().unwrap_or_else(|| "stdin".into());
+        match format::sniff(reader.fill_buf()?) {
+            Sniffed::Format(format) => {
+                status!("= pegviz {} looks like {} traces", name, format);
+                trace_format = format;
+            }
+            Sniffed::Markers => detecting = true,
+            Sniffed::Unsupported(what) => {
+                return Err(format!(
+                    "pegviz: {} looks like {}, which pegviz can't read",
+                    name, what
+                )
+                .into())
+            }
+            Sniffed::Unknown => status!(
+                "= pegviz no trace markers at the start of {}, reading it as peg traces",
+                name
+            ),
+        }
+    }
+
+    let new_reader = |format: TraceFormat, input: &str| match &spec {
+        Some(spec) => Box::new(spec.clone()) as Box<dyn Reader>,
+        None => format.reader(input),
+    };
+    let mut trace_lines = new_reader(trace_format, "");
+    let mut lines = encoding::Lines::new(reader, args.utf8);
+    let mut log_line = 0;
+    while let Some(line) = lines.next() {
+        let mut line = line?;
+        encoding::clean_line(&mut line);
+        log_line += 1;
+        if let Some(capture) = capture.as_deref_mut() {
+            capture.push_str(&line);
+            capture.push('\n');
+        }
+
+        match state {
+            ParseState::WaitingForInputStart => {
+                let (ts, line) = timestamp::split(&line);
+                let line = tests.observe(line, traces);
+                let input_start = match marker(line, "PEG_INPUT_START") {
+                    Some(tag) => Some((tag, false)),
+                    None => marker(line, "PEG_INPUT_HEX").map(|tag| (tag, true)),
+                };
+                if let Some((tag, is_hex)) = input_start {
+                    expecting_error = false;
+                    if selection.is_exhausted() {
+                        if capture.is_none() {
+                            break;
+                        }
+                        state = ParseState::Done;
+                        continue;
+                    }
+                    if !selection.keep_next() {
+                        state = ParseState::SkippingTrace;
+                        continue;
+                    }
+                    status!("= pegviz input start");
+                    start = ts;
+                    grammar = tag.map(String::from);
+                    hex = is_hex;
+                    token_lines = None;
+                    chunks = 1;
+                    hasher = Digest::default();
+                    state = ParseState::ReadingInput;
+                    continue;
+                }
+
+                if let (Some(given), Some(tag)) = (&given_input, marker(line, "PEG_TRACE_START")) {
+                    expecting_error = false;
+                    if selection.is_exhausted() {
+                        if capture.is_none() {
+                            break;
+                        }
+                        state = ParseState::Done;
+                        continue;
+                    }
+                    if !selection.keep_next() {
+                        state = ParseState::SkippingTrace;
+                        continue;
+                    }
+                    status!(
+                        "= pegviz trace start, input from {}",
+                        args.source.as_ref().unwrap().display()
+                    );
+                    start = ts;
+                    grammar = tag.map(String::from);
+                    chunks = 1;
+                    hasher = Digest::default();
+                    input.clone_from(given);
+                    hasher.update(input.as_bytes());
+                    args.normalize.apply(&mut input);
+                    binary = binary_input(&mut input, given.as_bytes(), false)?;
+                    state = ParseState::ReadingTrace;
+                    trace_lines = new_reader(trace_format, &input);
+                    stack.push(trace_root(log_line));
+                    if args.spill {
+                        let new_store = NodeStore::create()?;
+                        frames.push(new_store.open_frame());
+                        store = Some(new_store);
+                    }
+                    continue;
+                }
+
+                if marker(line, "PEG_TRACE_START").is_some() {
+                    status!(
+                        "= pegviz skipping a trace with no [PEG_INPUT_START] before it, see --source"
+                    );
+                }
+
+                if expecting_error {
+                    if let Ok(mut error) = tracer::parse_error(line) {
+                        let trace = traces.last_mut().unwrap();
+                        error.loc = args.position_base.rebase(error.loc);
+                        error.loc = match (error.loc, trace.tokens.is_empty()) {
+                            (Location::Offset(index), false) => {
+                                Location::Offset(tokens::start(&trace.tokens, index))
+                            }
+                            (loc, _) => trace.position_kind.convert(loc, &trace.input),
+                        };
+                        trace.error = Some(error);
+                        expecting_error = false;
+                    }
+                }
+            }
+            ParseState::Done => {}
+            ParseState::SkippingTrace => {
+                if marker(timestamp::split(&line).1, "PEG_TRACE_STOP").is_some() {
+                    state = ParseState::WaitingForInputStart;
+                }
+            }
+            ParseState::ReadingInput => {
+                let full = &line;
+                let line = match start {
+                    Some(_) => timestamp::split(full).1,
+                    None => full,
+                };
+
+                if marker(line, "PEG_INPUT_START").is_some()
+                    || marker(line, "PEG_INPUT_HEX").is_some()
+                {
+                    // streaming parsers print their input as it arrives, each
+                    // chunk starting on a new line even though the chunks
+                    // follow each other in the input
+                    if input.ends_with('\n') {
+                        input.pop();
+                        raw_input.pop();
+                    }
+                    chunks += 1;
+                    hasher.update(line.as_bytes());
+                    continue;
+                }
+
+                if let Some(tag) = marker(line, "PEG_TRACE_START") {
+                    status!("= pegviz trace start");
+                    if let Some(tag) = tag {
+                        grammar = Some(tag.into());
+                    }
+                    state = ParseState::ReadingTrace;
+                    // before positions are mapped to the input, see `--normalize`
+                    args.normalize.apply(&mut input);
+                    binary = binary_input(&mut input, &raw_input, hex)?;
+                    raw_input.clear();
+                    if let Some(lines) = token_lines.take() {
+                        tokens = lines
+                            .iter()
+                            .map(|line| tokens::parse(line, &input))
+                            .collect::<Result<_, _>>()
+                            .map_err(|e| format!("pegviz: in [PEG_TOKENS]: {}", e))?;
+                        status!("= pegviz {} tokens", tokens.len());
+                    }
+                    trace_lines = new_reader(trace_format, &input);
+                    stack.push(trace_root(log_line));
+                    if args.spill {
+                        let new_store = NodeStore::create()?;
+                        frames.push(new_store.open_frame());
+                        store = Some(new_store);
+                    }
+                    continue;
+                }
+
+                if marker(line, "PEG_TOKENS").is_some() {
+                    token_lines = Some(vec![]);
+                    continue;
+                }
+                hasher.update(line.as_bytes());
+                if let Some(token_lines) = &mut token_lines {
+                    if !line.trim().is_empty() {
+                        token_lines.push(line.into());
+                    }
+                    continue;
+                }
+
+                use std::fmt::Write;
+                writeln!(&mut input, "{}", line)?;
+                // `clean_line` dropped a leading byte order mark and trailing
+                // `\r`s, the timestamp is all ASCII
+                let raw = lines.raw();
+                let raw = raw.strip_prefix("\u{feff}".as_bytes()).unwrap_or(raw);
+                let prefix = full.len() - line.len();
+                raw_input.extend_from_slice(&raw[prefix.min(raw.len())..]);
+                raw_input.push(b'\n');
+            }
+            ParseState::ReadingTrace => {
+                let raw = &line;
+                let (_, line) = timestamp::split(raw);
+
+                let stop = marker(line, "PEG_TRACE_STOP").is_some();
+                let lines = if stop {
+                    trace_lines.finish()
+                } else {
+                    hasher.update(line.as_bytes());
+                    if detecting && !line.trim().is_empty() {
+                        detecting = false;
+                        if let Some(format) = format::detect(line) {
+                            trace_format = format;
+                        }
+                        status!(
+                            "= pegviz trace #{} looks like {} traces",
+                            selection.seen(),
+                            trace_format
+                        );
+                        trace_lines = new_reader(trace_format, &input);
+                    }
+                    match trace_lines.line(log_line, line) {
+                        Ok(lines) => lines,
+                        Err(e) => {
+                            status!("= pegviz error:\nfor line\n|  {}\n{}", line, e);
+                            return Err("pegviz: could not parse trace line".into());
+                        }
+                    }
+                };
+                for (log_line, mut t) in lines {
+                    args.position_base.rebase_line(&mut t);
+                    if !tokens.is_empty() {
+                        tokens::convert_line(&tokens, &mut t);
+                    } else if let Some(kind) = args.position_kind {
+                        kind.convert_line(&mut t, &input);
+                    }
+
+                    // lines held back until the end of the trace have no raw line
+                    let raw = if args.keep_raw && !stop {
+                        Some((log_line, raw.clone()))
+                    } else {
+                        None
+                    };
+
+                    match t {
+                        Line::Attempt(rule) => {
+                            let node = Node {
+                                rule,
+                                state: State::Unknown,
+                                children: vec![],
+                                partial_match: false,
+                                raw: raw.into_iter().collect(),
+                                growth: Growth::None,
+                                id: 0,
+                                log_line,
+                                net: None,
+                                subtree: None,
+                                notes: vec![],
+                            };
+                            stack.push(node);
+                            if let Some(store) = &store {
+                                frames.push(store.open_frame());
+                            }
+                        }
+                        Line::Success(rule) => {
+                            let mut node = finishing(&mut stack, &rule, line)?;
+                            node.state = State::Success;
+                            node.raw.extend(raw);
+                            node.rule.next_loc = rule.next_loc;
+                            complete(node, &mut stack, &mut store, &mut frames)?;
+                        }
+                        Line::Failure(rule) => {
+                            let mut node = finishing(&mut stack, &rule, line)?;
+                            node.state = State::Failure;
+                            node.raw.extend(raw);
+                            complete(node, &mut stack, &mut store, &mut frames)?;
+                        }
+                        Line::Cache(Some(rule)) => {
+                            *cache_hits.entry(rule.name.clone()).or_insert(0) += 1;
+                            // a rule re-entering itself at the same position through
+                            // the cache: that's a left-recursive rule growing its seed
+                            if let Some(node) = stack
+                                .iter_mut()
+                                .rev()
+                                .find(|n| n.rule.name == rule.name && n.rule.loc == rule.loc)
+                            {
+                                node.growth = Growth::Iteration(0);
+                            }
+                        }
+                        Line::Note(text) => {
+                            let before = match frames.last() {
+                                Some(frame) => frame.children(),
+                                None => stack.last().unwrap().children.len(),
+                            };
+                            stack.last_mut().unwrap().notes.push(Note {
+                                before,
+                                text,
+                                log_line,
+                            });
+                        }
+                        Line::Cache(None) => {}
+                        Line::EnterLevel => {}
+                        Line::LeaveLevel => {}
+                    }
+                }
+
+                if stop {
+                    status!("= pegviz trace stop");
+                    // rules that never finished, e.g. because the parser
+                    // panicked, are kept in the unknown state
+                    while stack.len() > 1 {
+                        let node = stack.pop().unwrap();
+                        complete(node, &mut stack, &mut store, &mut frames)?;
+                    }
+                    let mut root = stack.pop().unwrap();
+                    let index = selection.seen() - 1;
+                    // last, so a trace without a grammar is told apart by
+                    // not having this field at all
+                    if let Some(grammar) = &grammar {
+                        hasher.update(grammar.as_bytes());
+                    }
+                    let digest = std::mem::take(&mut hasher).digest();
+                    if let Some(original) =
+                        selection.original_of(digest, index, &input, &grammar, traces)
+                    {
+                        original.duplicates.push(index);
+                        store = None;
+                        frames.clear();
+                        input.clear();
+                        tokens.clear();
+                        cache_hits.clear();
+                        state = ParseState::WaitingForInputStart;
+                        continue;
+                    }
+
+                    let position_kind = match (args.position_kind, &store) {
+                        // converted through the tokens as they were read
+                        _ if !tokens.is_empty() => PositionKind::Token,
+                        (Some(kind), _) => kind,
+                        // spilled nodes are already on disk, taken as bytes
+                        (None, Some(_)) => PositionKind::Byte,
+                        (None, None) => {
+                            let mut found = vec![];
+                            offsets(&root, &mut found);
+                            let kind = PositionKind::guess(&input, &found);
+                            if kind != PositionKind::Byte {
+                                status!(
+                                    "= pegviz trace #{} looks like it counts {} positions",
+                                    index + 1,
+                                    kind
+                                );
+                            }
+                            kind.convert_tree(&mut root, &input);
+                            kind
+                        }
+                    };
+
+                    let spilled = match store.take() {
+                        Some(store) => {
+                            let frame = frames.pop().unwrap();
+                            Some(store.finish(&mut root, frame)?)
+                        }
+                        None => None,
+                    };
+                    traces.push(Trace {
+                        root,
+                        chunks,
+                        input_hash: expect::input_hash(&input),
+                        expected: None,
+                        input: input.clone(),
+                        binary: binary.take(),
+                        tokens: std::mem::take(&mut tokens),
+                        source: source.label(),
+                        timestamp: start,
+                        error: None,
+                        grammar: grammar.take().or_else(|| {
+                            if args.tag_by_input {
+                                source.stem()
+                            } else {
+                                None
+                            }
+                        }),
+                        index,
+                        spilled,
+                        duplicates: vec![],
+                        consumed: None,
+                        highlight: None,
+                        test: None,
+                        position_kind,
+                        cache_hits: std::mem::take(&mut cache_hits),
+                    });
+                    tests.trace_added(traces.last_mut().unwrap());
+                    input.clear();
+                    selection.trim(traces);
+                    expecting_error = true;
+                    state = ParseState::WaitingForInputStart;
+                    continue;
+                }
+            }
+        }
+    }
+
+    if lines.replaced > 0 {
+        status!(
+            "= pegviz {} has invalid UTF-8 on {} lines, replaced with \u{fffd}",
+            source.label().as_deref().unwrap_or("stdin"),
+            lines.replaced
+        );
+    }
+
+    match source {
+        Source::File(_) => {
+            // the rest of a log past the traces `selection` wants, so that
+            // it's hashed whole without being read again
+            io::copy(&mut lines.into_inner(), &mut io::sink())?;
+            Ok(Some(hash.get()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Pops the node that a success or failure line is about, making sure it's
+/// the one that was attempted last.
+fn finishing(stack: &mut Vec<Node>, rule: &Rule, line: &str) -> Result<Node, Box<dyn Error>> {
+    // the bottom of the stack is the trace's root, which never finishes
+    let expected = match stack.len() {
+        0 | 1 => None,
+        n => Some(&stack[n - 1].rule.name),
+    };
+    if expected != Some(&rule.name) {
+        status!(
+            "= pegviz error:\nfor line\n|  {}\nexpected rule {:?} to finish, but got {:?}",
+            line,
+            expected,
+            rule.name
+        );
+        return Err("pegviz: trace finishes a rule that wasn't attempted".into());
+    }
+    Ok(stack.pop().unwrap())
+}
+
+/// For binary inputs, their bytes, `input` then standing in for them with
+/// one char for each. `raw` is the input as logged, invalid UTF-8 and `\r`s
+/// included, which `input` has lost.
+fn binary_input(input: &mut String, raw: &[u8], hex: bool) -> Result<Option<Vec<u8>>, String> {
+    let bytes = if hex {
+        hexdump::decode(input).map_err(|e| format!("pegviz: {}", e))?
+    } else if hexdump::looks_binary(input) {
+        // without the newline after the input
+        raw.strip_suffix(b"\n").unwrap_or(raw).to_vec()
+    } else {
+        return Ok(None);
+    };
+    status!("= pegviz binary input, {} bytes", bytes.len());
+    *input = hexdump::text(&bytes);
+    Ok(Some(bytes))
+}
+
+/// The node a trace's rules go in, starting at `log_line`.
+pub(crate) fn trace_root(log_line: usize) -> Node {
+    Node {
+        rule: Rule {
+            name: "Trace".into(),
+            loc: Location::ROOT,
+            next_loc: None,
+        },
+        partial_match: false,
+        state: State::Success,
+        children: vec![],
+        raw: vec![],
+        growth: Growth::None,
+        id: 0,
+        log_line,
+        net: None,
+        subtree: None,
+        notes: vec![],
+    }
+}
+
+/// Hands a node that's done parsing to its parent, or to the store when
+/// spilling.
+fn complete(
+    mut node: Node,
+    stack: &mut [Node],
+    store: &mut Option<NodeStore>,
+    frames: &mut Vec<Frame>,
+) -> io::Result<()> {
+    match store {
+        Some(store) => {
+            let frame = frames.pop().unwrap();
+            store.push(&mut node, frame, frames.last_mut().unwrap())
+        }
+        None => {
+            stack.last_mut().unwrap().children.push(node);
+            Ok(())
+        }
+    }
+}
+
+/// Matches a pegviz marker line like `[PEG_INPUT_START]`, which may carry
+/// a grammar tag in the extended dialect: `[PEG_INPUT_START json]`. Returns
+/// the tag, if any.
+fn marker<'a>(line: &'a str, name: &str) -> Option<Option<&'a str>> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let rest = inner.strip_prefix(name)?;
+    if rest.is_empty() {
+        Some(None)
+    } else {
+        let tag = rest.strip_prefix(' ')?.trim();
+        if tag.is_empty() {
+            Some(None)
+        } else {
+            Some(Some(tag))
+        }
+    }
+}
+
+/// Undoes `escape_html`.
+fn unescape_html(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// When every trace carries a timestamp, sorts them chronologically (keeping
+/// the original order for ties), reconstructing a session that was split
+/// across several log files. Returns whether the traces were reordered.
+pub(crate) fn order_chronologically(traces: &mut [Trace]) -> bool {
+    if traces.iter().any(|t| t.timestamp.is_none()) {
+        return false;
+    }
+    traces.sort_by_key(|t| t.timestamp);
+    true
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::gen::{generate, GenArgs};
+
+    /// A directory for a test's files, removed along with them when the test
+    /// ends, whether it passed or not.
+    pub(crate) struct TestDir(PathBuf);
+
+    impl TestDir {
+        pub(crate) fn new(test: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("pegviz-test-{}-{}", std::process::id(), test));
+            fs::create_dir_all(&path).unwrap();
+            TestDir(path)
+        }
+
+        pub(crate) fn join(&self, name: &str) -> PathBuf {
+            self.0.join(name)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    /// Reads the traces of `log`, with the given arguments.
+    pub(crate) fn read(
+        dir: &TestDir,
+        log: &str,
+        extra_args: &[&str],
+    ) -> Result<Vec<Trace>, Box<dyn std::error::Error>> {
+        let path = dir.join("trace.log");
+        fs::write(&path, log)?;
+        let mut all_args = vec!["-o", "unused.html"];
+        all_args.extend(extra_args);
+        let args: Args = argh::FromArgs::from_args(&["pegviz"], &all_args).unwrap();
+        let mut selection = Selection::new(&args);
+        let mut traces = vec![];
+        read_traces(
+            &Source::File(path),
+            &args,
+            &mut selection,
+            &mut traces,
+            None,
+        )?;
+        Ok(traces)
+    }
+
+    /// A generated log with `traces` traces.
+    fn generated(traces: usize) -> String {
+        generate(&GenArgs {
+            nodes: 60,
+            depth: 6,
+            seed: 5,
+            traces,
+            malformed: false,
+        })
+    }
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        wildcard_matches(&pattern, &name)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.log", "trace.log"));
+        assert!(matches("*.log", ".log"));
+        assert!(!matches("*.log", "trace.log.gz"));
+        assert!(matches("*.log*", "trace.log.gz"));
+        assert!(matches("t?ace-*-*.log", "trace-1-2.log"));
+        assert!(!matches("t?ace-*-*.log", "trace-1.log"));
+        assert!(matches("?é*", "héllo"));
+        assert!(!matches("?", ""));
+        assert!(matches("*", ""));
+        assert!(matches("**", "anything"));
+        assert!(!matches("a", "ab"));
+        assert!(!matches("ab", "a"));
+
+        // would take exponential time with backtracking on every `*`
+        let name = "a".repeat(100);
+        assert!(!matches("*a*a*a*a*a*a*a*a*a*a*b", &name));
+        assert!(matches("*a*a*a*a*a*a*a*a*a*a*", &name));
+    }
+
+    #[test]
+    fn globs() {
+        let dir = TestDir::new("globs");
+        for file in &[
+            "a.log",
+            "b.txt",
+            ".c.log",
+            "sub/d.log",
+            "sub/deeper/e.log",
+            ".hidden/f.log",
+        ] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let glob = |pattern: &str| -> Vec<String> {
+            let root = dir.join("");
+            glob(&dir.join(pattern))
+                .unwrap()
+                .iter()
+                .map(|path| path.strip_prefix(&root).unwrap().display().to_string())
+                .collect()
+        };
+
+        assert_eq!(glob("*.log"), ["a.log"]);
+        assert_eq!(glob("?.*"), ["a.log", "b.txt"]);
+        // hidden files only match a pattern that starts with a dot
+        assert_eq!(glob(".*.log"), [".c.log"]);
+        assert_eq!(glob(".*/*.log"), [".hidden/f.log"]);
+        // `**` is any number of directories, hidden ones aside
+        assert_eq!(glob("**/*.log"), ["a.log", "sub/d.log", "sub/deeper/e.log"]);
+        assert_eq!(glob("s?b/*"), ["sub/d.log", "sub/deeper"]);
+        assert!(glob("nothing/*.log").is_empty());
+    }
+
+    #[test]
+    fn raw_binary_inputs_keep_their_bytes() {
+        let dir = TestDir::new("raw_binary_inputs_keep_their_bytes");
+        let args: Args = argh::FromArgs::from_args(&["pegviz"], &["-o", "unused.html"]).unwrap();
+        let read_binary = |input: &[u8], timestamp: &str| {
+            let mut log = format!("{}[PEG_INPUT_START]\n{}", timestamp, timestamp).into_bytes();
+            log.extend_from_slice(input);
+            log.extend_from_slice(
+                format!(
+                    "\n{ts}[PEG_TRACE_START]\n\
+                     {ts}[PEG_TRACE] Attempting to match rule `b` at 1:3\n\
+                     {ts}[PEG_TRACE] Matched rule `b` at 1:3 to 1:4\n\
+                     {ts}[PEG_TRACE_STOP]\n",
+                    ts = timestamp
+                )
+                .as_bytes(),
+            );
+            let path = dir.join("trace.log");
+            fs::write(&path, log).unwrap();
+            let mut traces = vec![];
+            read_traces(
+                &Source::File(path),
+                &args,
+                &mut Selection::new(&args),
+                &mut traces,
+                None,
+            )
+            .unwrap();
+            traces.pop().unwrap()
+        };
+
+        for timestamp in &["", "2020-05-01T10:00:00Z "] {
+            // invalid UTF-8 doesn't turn into U+FFFD's bytes
+            let trace = read_binary(b"\x41\xff\x42\x00\x43", timestamp);
+            assert_eq!(trace.binary.as_deref(), Some(&b"\x41\xff\x42\x00\x43"[..]));
+            assert_eq!(trace.input, "A.B.C");
+            let rule = &trace.root.children[0].rule;
+            let pos = rule.loc.pos(&trace.input);
+            assert_eq!(
+                &trace.input[pos..rule.next_loc.unwrap().pos(&trace.input)],
+                "B"
+            );
+
+            // nor do trailing carriage returns go away
+            let trace = read_binary(b"\x00\x41\r", timestamp);
+            assert_eq!(trace.binary.as_deref(), Some(&b"\x00\x41\r"[..]));
+        }
+    }
+
+    #[test]
+    fn normalized_before_positions_are_mapped() {
+        let dir = TestDir::new("normalized_before_positions_are_mapped");
+        // logged decomposed, parsed composed: `w` matched all 4 chars
+        let log = "[PEG_INPUT_START]\ncafe\u{301}\n[PEG_TRACE_START]\n\
+                   [PEG_TRACE] Attempting to match rule `w` at 0\n\
+                   [PEG_TRACE] Matched rule `w` at 0 to 4\n\
+                   [PEG_TRACE_STOP]\n";
+        let traces = read(
+            &dir,
+            log,
+            &["--normalize", "nfc", "--position-kind", "char"],
+        )
+        .unwrap();
+        let trace = &traces[0];
+        let rule = &trace.root.children[0].rule;
+        let (start, end) = (
+            rule.loc.pos(&trace.input),
+            rule.next_loc.unwrap().pos(&trace.input),
+        );
+        assert_eq!(&trace.input[start..end], "caf\u{e9}");
+    }
+
+    #[test]
+    fn first_counts_sampled_traces() {
+        let dir = TestDir::new("first_counts_sampled_traces");
+        let log = generated(10);
+        let indices = |extra: &[&str]| -> Vec<usize> {
+            let traces = read(&dir, &log, extra).unwrap();
+            traces.iter().map(|t| t.index).collect()
+        };
+        assert_eq!(indices(&["--sample", "1/3", "--first", "2"]), [0, 3]);
+        assert_eq!(indices(&["--sample", "1/3", "--last", "2"]), [6, 9]);
+        assert_eq!(indices(&["--first", "2"]), [0, 1]);
+    }
+
+    #[test]
+    fn dedupe_drops_repeated_traces() {
+        let dir = TestDir::new("dedupe_drops_repeated_traces");
+        let log = generated(3);
+        for extra in &[&[][..], &["--spill"][..]] {
+            let mut args = vec!["--dedupe"];
+            args.extend(*extra);
+            let traces = read(&dir, &format!("{}{}", log, log), &args).unwrap();
+            assert_eq!(traces.len(), 3);
+            for (i, trace) in traces.iter().enumerate() {
+                assert_eq!(trace.duplicates, vec![i + 3]);
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! JSON output, see `--format json`, for post-processing traces with `jq`
+//! and the like.
+//!
+//! The document has a `run` object (the same information as the report's
+//! "Run information"), then one object per trace with its input and its
+//! tree. Nodes carry both the locations from the log and the byte offsets
+//! they stand for.
+
+use crate::{
+    input::Trace,
+    run_info::RunInfo,
+    spill::{Loaded, SpilledTree},
+    succeeded, Location, Node, Note, State,
+};
+use std::{error::Error, io::Write};
+
+pub(crate) fn write_json(
+    f: &mut dyn Write,
+    run_info: &RunInfo,
+    traces: &[Trace],
+) -> Result<(), Box<dyn Error>> {
+    writeln!(f, "{{")?;
+    writeln!(f, r#"  "run": {{"#)?;
+    writeln!(f, r#"    "version": {},"#, string(run_info.version))?;
+    writeln!(
+        f,
+        r#"    "command_line": {},"#,
+        string(&run_info.command_line)
+    )?;
+    let inputs: Vec<String> = run_info
+        .inputs
+        .iter()
+        .map(|(name, hash)| match hash {
+            Some(hash) => format!(
+                r#"{{ "name": {}, "fnv1a": "{:016x}" }}"#,
+                string(name),
+                hash
+            ),
+            None => format!(r#"{{ "name": {}, "fnv1a": null }}"#, string(name)),
+        })
+        .collect();
+    writeln!(f, r#"    "inputs": [{}],"#, inputs.join(", "))?;
+    writeln!(
+        f,
+        r#"    "generated": {}"#,
+        match run_info.generated {
+            Some(generated) => string(&generated.to_string()),
+            None => "null".into(),
+        }
+    )?;
+    writeln!(f, "  }},")?;
+    writeln!(f, r#"  "traces": ["#)?;
+    for (i, trace) in traces.iter().enumerate() {
+        write_trace(f, trace)?;
+        writeln!(f, "{}", if i + 1 < traces.len() { "," } else { "" })?;
+    }
+    writeln!(f, "  ]")?;
+    writeln!(f, "}}")?;
+    Ok(())
+}
+
+fn write_trace(f: &mut dyn Write, trace: &Trace) -> Result<(), Box<dyn Error>> {
+    let i = indent(2);
+    let j = indent(3);
+    writeln!(f, "{}{{", i)?;
+    writeln!(f, r#"{}"name": {},"#, j, string(&trace.root.rule.name))?;
+    writeln!(f, r#"{}"number": {},"#, j, trace.index + 1)?;
+    writeln!(
+        f,
+        r#"{}"source": {},"#,
+        j,
+        optional(trace.source.as_deref())
+    )?;
+    writeln!(
+        f,
+        r#"{}"grammar": {},"#,
+        j,
+        optional(trace.grammar.as_deref())
+    )?;
+    match &trace.test {
+        Some(test) => writeln!(
+            f,
+            r#"{}"test": {{ "name": {}, "outcome": {} }},"#,
+            j,
+            string(&test.name),
+            optional(test.outcome.as_deref())
+        )?,
+        None => writeln!(f, r#"{}"test": null,"#, j)?,
+    }
+    writeln!(
+        f,
+        r#"{}"timestamp": {},"#,
+        j,
+        optional(trace.timestamp.as_ref().map(|ts| ts.to_string()).as_deref())
+    )?;
+    writeln!(f, r#"{}"input": {},"#, j, string(&trace.input))?;
+    match &trace.error {
+        Some(error) => writeln!(
+            f,
+            r#"{}"error": {{ "loc": {}, "expected": {} }},"#,
+            j,
+            location(error.loc),
+            string(&error.expected)
+        )?,
+        None => writeln!(f, r#"{}"error": null,"#, j)?,
+    }
+    writeln!(
+        f,
+        r#"{}"consumed": {},"#,
+        j,
+        trace.consumed.map_or("null".into(), location)
+    )?;
+    writeln!(f, r#"{}"succeeded": {},"#, j, succeeded(trace))?;
+    let duplicates: Vec<String> = trace
+        .duplicates
+        .iter()
+        .map(|index| (index + 1).to_string())
+        .collect();
+    writeln!(f, r#"{}"duplicates": [{}],"#, j, duplicates.join(", "))?;
+    writeln!(f, r#"{}"notes": {},"#, j, notes(&trace.root.notes))?;
+    write!(f, r#"{}"nodes": "#, j)?;
+    match &trace.spilled {
+        Some(tree) => {
+            // numbered in the same order as they're shown in reports
+            let mut next_id = trace.root.id + 1;
+            write_spilled(f, trace, tree, tree.top_level()?, &mut next_id, 3)?
+        }
+        None => write_nodes(f, trace, &trace.root.children, 3)?,
+    }
+    writeln!(f)?;
+    write!(f, "{}}}", i)?;
+    Ok(())
+}
+
+fn write_nodes(
+    f: &mut dyn Write,
+    trace: &Trace,
+    nodes: &[Node],
+    depth: usize,
+) -> Result<(), Box<dyn Error>> {
+    if nodes.is_empty() {
+        write!(f, "[]")?;
+        return Ok(());
+    }
+    writeln!(f, "[")?;
+    for (n, node) in nodes.iter().enumerate() {
+        write_head(f, trace, node, depth + 1)?;
+        write_nodes(f, trace, &node.children, depth + 2)?;
+        write_tail(f, depth + 1, n + 1 == nodes.len())?;
+    }
+    write!(f, "{}]", indent(depth))?;
+    Ok(())
+}
+
+fn write_spilled(
+    f: &mut dyn Write,
+    trace: &Trace,
+    tree: &SpilledTree,
+    nodes: Vec<Loaded>,
+    next_id: &mut usize,
+    depth: usize,
+) -> Result<(), Box<dyn Error>> {
+    if nodes.is_empty() {
+        write!(f, "[]")?;
+        return Ok(());
+    }
+    writeln!(f, "[")?;
+    let count = nodes.len();
+    for (n, mut loaded) in nodes.into_iter().enumerate() {
+        loaded.node.id = *next_id;
+        *next_id += 1;
+        write_head(f, trace, &loaded.node, depth + 1)?;
+        let children = tree.children(&loaded)?;
+        write_spilled(f, trace, tree, children, next_id, depth + 2)?;
+        write_tail(f, depth + 1, n + 1 == count)?;
+    }
+    write!(f, "{}]", indent(depth))?;
+    Ok(())
+}
+
+/// Everything in a node's object up to its children.
+fn write_head(
+    f: &mut dyn Write,
+    trace: &Trace,
+    node: &Node,
+    depth: usize,
+) -> Result<(), Box<dyn Error>> {
+    let input = &trace.input;
+    let (i, j) = (indent(depth), indent(depth + 1));
+    writeln!(f, "{}{{", i)?;
+    writeln!(f, r#"{}"id": {},"#, j, node.id)?;
+    writeln!(f, r#"{}"rule": {},"#, j, string(&node.rule.name))?;
+    writeln!(
+        f,
+        r#"{}"state": "{}","#,
+        j,
+        match node.state {
+            State::Success => "success",
+            State::Failure => "failure",
+            State::Unknown => "unknown",
+        }
+    )?;
+    writeln!(f, r#"{}"partial_match": {},"#, j, node.partial_match)?;
+    writeln!(f, r#"{}"loc": {},"#, j, location(node.rule.loc))?;
+    writeln!(
+        f,
+        r#"{}"next_loc": {},"#,
+        j,
+        node.rule.next_loc.map_or("null".into(), location)
+    )?;
+    writeln!(f, r#"{}"start": {},"#, j, node.rule.loc.pos(input))?;
+    writeln!(
+        f,
+        r#"{}"end": {},"#,
+        j,
+        node.rule
+            .next_loc
+            .map_or("null".into(), |loc| loc.pos(input).to_string())
+    )?;
+    writeln!(f, r#"{}"log_line": {},"#, j, node.log_line)?;
+    writeln!(f, r#"{}"notes": {},"#, j, notes(&node.notes))?;
+    write!(f, r#"{}"children": "#, j)?;
+    Ok(())
+}
+
+fn write_tail(f: &mut dyn Write, depth: usize, last: bool) -> Result<(), Box<dyn Error>> {
+    writeln!(f)?;
+    writeln!(f, "{}}}{}", indent(depth), if last { "" } else { "," })?;
+    Ok(())
+}
+
+fn location(loc: Location) -> String {
+    match loc {
+        Location::LineCol { line, column } => {
+            format!(r#"{{ "line": {}, "column": {} }}"#, line, column)
+        }
+        Location::Offset(offset) => format!(r#"{{ "offset": {} }}"#, offset),
+    }
+}
+
+fn notes(notes: &[Note]) -> String {
+    let notes: Vec<String> = notes
+        .iter()
+        .map(|note| {
+            format!(
+                r#"{{ "before": {}, "text": {}, "log_line": {} }}"#,
+                note.before,
+                string(&note.text),
+                note.log_line
+            )
+        })
+        .collect();
+    format!("[{}]", notes.join(", "))
+}
+
+fn optional(s: Option<&str>) -> String {
+    s.map_or("null".into(), string)
+}
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+/// `s` as a JSON string literal.
+pub(crate) fn string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
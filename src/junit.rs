@@ -0,0 +1,170 @@
+//! JUnit XML output, see `--format junit`, for CI systems to show parse
+//! regressions with the rest of the test results.
+//!
+//! Each trace is a test case, failed when its input didn't parse (or parsed
+//! when it was `--expect`ed not to). Failures say where parsing stopped
+//! and which rules led to the deepest failure there.
+
+use crate::{
+    consumption, escape_html, farthest_failure, input::Trace, succeeded, Args, Node, State,
+};
+use std::{error::Error, io::Write};
+
+pub(crate) fn write_junit(
+    f: &mut dyn Write,
+    args: &Args,
+    traces: &[Trace],
+) -> Result<(), Box<dyn Error>> {
+    // one suite per log, in the order they were read
+    let mut suites: Vec<(String, Vec<String>, usize)> = vec![];
+    for trace in traces {
+        let source = trace.source.clone().unwrap_or_else(|| "stdin".into());
+        let case = test_case(args, trace, &source)?;
+        let failed = case.contains("<failure");
+        match suites.last_mut() {
+            Some((name, cases, failures)) if *name == source => {
+                cases.push(case);
+                *failures += failed as usize;
+            }
+            _ => suites.push((source, vec![case], failed as usize)),
+        }
+    }
+
+    let tests = traces.len();
+    let failures: usize = suites.iter().map(|(_, _, failures)| failures).sum();
+    writeln!(f, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        f,
+        r#"<testsuites name="pegviz" tests="{}" failures="{}">"#,
+        tests, failures
+    )?;
+    for (name, cases, failures) in &suites {
+        writeln!(
+            f,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" errors="0" skipped="0">"#,
+            xml(name),
+            cases.len(),
+            failures
+        )?;
+        for case in cases {
+            write!(f, "{}", case)?;
+        }
+        writeln!(f, "  </testsuite>")?;
+    }
+    writeln!(f, "</testsuites>")?;
+    Ok(())
+}
+
+fn test_case(args: &Args, trace: &Trace, source: &str) -> Result<String, Box<dyn Error>> {
+    let name = match &trace.test {
+        Some(test) => test.name.clone(),
+        None => trace.root.rule.name.clone(),
+    };
+    let classname = trace.grammar.as_deref().unwrap_or(source);
+    let mut case = format!(
+        r#"    <testcase name="{}" classname="{}" time="0""#,
+        xml(&name),
+        xml(classname)
+    );
+
+    let failure = match (succeeded(trace), trace.expected) {
+        (true, Some(false)) => Some((
+            "parsed, but was expected to fail".to_string(),
+            String::new(),
+        )),
+        (false, Some(false)) | (true, _) => None,
+        (false, _) => Some(failure(args, trace)?),
+    };
+    match failure {
+        Some((message, details)) => {
+            case.push_str(">\n");
+            case.push_str(&format!(
+                "      <failure message=\"{}\" type=\"parse-failure\">{}</failure>\n",
+                xml(&message),
+                xml(&details)
+            ));
+            case.push_str("    </testcase>\n");
+        }
+        None => case.push_str("/>\n"),
+    }
+    Ok(case)
+}
+
+/// The failure's message, and the path to the deepest failure as details.
+fn failure(args: &Args, trace: &Trace) -> Result<(String, String), Box<dyn Error>> {
+    let input = &trace.input;
+    let pos = match farthest_failure(trace)? {
+        Some(pos) => pos,
+        // nothing failed, but the input wasn't consumed to the end
+        None => consumption(trace).0,
+    };
+    let mut message = format!("stops parsing at {}", args.line_col(input, pos));
+    if let Some(error) = &trace.error {
+        message.push_str(&format!(": expected {}", error.expected));
+    }
+
+    let loaded;
+    let children = match &trace.spilled {
+        Some(tree) => {
+            loaded = tree.load_all()?;
+            &loaded
+        }
+        None => &trace.root.children,
+    };
+    let mut deepest = vec![];
+    deepest_failure(trace, pos, children, &mut vec![], &mut deepest);
+    let mut details = String::new();
+    for (depth, node) in deepest.iter().enumerate() {
+        let mark = match node.state {
+            State::Success => "✓",
+            State::Failure => "✗",
+            State::Unknown => "?",
+        };
+        details.push_str(&format!(
+            "{}{} {} at {}\n",
+            "  ".repeat(depth),
+            mark,
+            node.rule.name,
+            args.display_loc(input, node.rule.loc)
+        ));
+    }
+    if let Some(last) = deepest.last() {
+        let path: Vec<&str> = deepest.iter().map(|n| n.rule.name.as_str()).collect();
+        message.push_str(&format!(
+            " (deepest failure: {}, log line {})",
+            path.join(" > "),
+            last.log_line
+        ));
+    }
+    Ok((message, details))
+}
+
+/// Finds the deepest node that failed at `pos`, along with its ancestors.
+fn deepest_failure<'a>(
+    trace: &Trace,
+    pos: usize,
+    nodes: &'a [Node],
+    path: &mut Vec<&'a Node>,
+    deepest: &mut Vec<&'a Node>,
+) {
+    for node in nodes {
+        path.push(node);
+        if matches!(node.state, State::Failure)
+            && node.rule.loc.pos(&trace.input) == pos
+            && path.len() > deepest.len()
+        {
+            *deepest = path.clone();
+        }
+        deepest_failure(trace, pos, &node.children, path, deepest);
+        path.pop();
+    }
+}
+
+/// Escapes `s` for XML, leaving out the control characters it can't have.
+fn xml(s: &str) -> String {
+    let kept: String = s
+        .chars()
+        .filter(|&c| !c.is_control() || matches!(c, '\n' | '\t'))
+        .collect();
+    escape_html(&kept).replace('\'', "&apos;")
+}
@@ -0,0 +1,150 @@
+//! Attributing traces to the tests that printed them, when reading the
+//! output of `cargo test`.
+//!
+//! With `--nocapture` and a single test thread, libtest prints
+//! `test name ... ` before running each test (so its output carries on on
+//! the same line), and the outcome after. With several threads, only
+//! `test name ... ok` lines are printed, once each test is done, so traces
+//! go to the next test that finishes. Without `--nocapture`, the output of
+//! failed tests comes in `---- name stdout ----` sections.
+
+use crate::input::Trace;
+
+/// A test, as seen in libtest's output.
+#[derive(Debug, Clone)]
+pub(crate) struct TestCase {
+    pub(crate) name: String,
+    /// `ok`, `FAILED` or `ignored`, once known
+    pub(crate) outcome: Option<String>,
+}
+
+enum TestLine<'a> {
+    /// `test name ... `, possibly followed by the test's own output
+    Started { name: &'a str, rest: &'a str },
+    /// `test name ... ok`
+    Finished { name: &'a str, outcome: &'a str },
+    /// `ok` on its own line, for the test that was started last
+    Outcome(&'a str),
+    /// `---- name stdout ----`
+    Captured { name: &'a str },
+    /// `failures:` and `test result: …` end the captured sections
+    Summary,
+}
+
+fn is_outcome(s: &str) -> bool {
+    matches!(s, "ok" | "FAILED" | "ignored") || s.starts_with("ignored, ")
+}
+
+fn parse(line: &str) -> Option<TestLine<'_>> {
+    if let Some(rest) = line.strip_prefix("test ") {
+        if rest.starts_with("result: ") {
+            return Some(TestLine::Summary);
+        }
+        let (name, rest) = rest.split_once(" ... ")?;
+        if name.is_empty() || name.contains(' ') {
+            return None;
+        }
+        return Some(if is_outcome(rest.trim_end()) {
+            TestLine::Finished {
+                name,
+                outcome: rest.trim_end(),
+            }
+        } else {
+            TestLine::Started { name, rest }
+        });
+    }
+    if let Some(name) = line
+        .strip_prefix("---- ")
+        .and_then(|rest| rest.strip_suffix(" stdout ----"))
+    {
+        return Some(TestLine::Captured { name });
+    }
+    if line == "failures:" {
+        return Some(TestLine::Summary);
+    }
+    if is_outcome(line.trim_end()) {
+        return Some(TestLine::Outcome(line.trim_end()));
+    }
+    None
+}
+
+/// Keeps track of which test is running while a log is read.
+#[derive(Default)]
+pub(crate) struct Tests {
+    /// the test whose output is being read, if that's known
+    current: Option<TestCase>,
+    /// whether `current` comes from a `---- name stdout ----` section
+    captured: bool,
+    /// indices of the traces read since the last test finished, that
+    /// weren't attributed to a test yet
+    pending: Vec<usize>,
+}
+
+impl Tests {
+    /// Looks at a line read between traces, returns the part of it that's
+    /// not libtest's own output.
+    pub(crate) fn observe<'a>(&mut self, line: &'a str, traces: &mut [Trace]) -> &'a str {
+        match parse(line) {
+            None => line,
+            Some(TestLine::Started { name, rest }) => {
+                self.current = Some(TestCase {
+                    name: name.into(),
+                    outcome: None,
+                });
+                self.captured = false;
+                rest
+            }
+            Some(TestLine::Finished { name, outcome }) => {
+                self.finish(name, outcome, traces);
+                ""
+            }
+            Some(TestLine::Outcome(outcome)) => {
+                match self.current.take() {
+                    Some(current) if !self.captured => self.finish(&current.name, outcome, traces),
+                    current => self.current = current,
+                }
+                ""
+            }
+            Some(TestLine::Captured { name }) => {
+                self.current = Some(TestCase {
+                    name: name.into(),
+                    // only failed tests have their output shown
+                    outcome: Some("FAILED".into()),
+                });
+                self.captured = true;
+                ""
+            }
+            Some(TestLine::Summary) => {
+                self.current = None;
+                ""
+            }
+        }
+    }
+
+    /// Attributes a trace that was just read.
+    pub(crate) fn trace_added(&mut self, trace: &mut Trace) {
+        match &self.current {
+            Some(test) => trace.test = Some(test.clone()),
+            None => self.pending.push(trace.index),
+        }
+    }
+
+    fn finish(&mut self, name: &str, outcome: &str, traces: &mut [Trace]) {
+        let pending = std::mem::take(&mut self.pending);
+        for trace in traces.iter_mut() {
+            let ours = match &trace.test {
+                Some(test) => test.name == name && test.outcome.is_none(),
+                None => pending.contains(&trace.index),
+            };
+            if ours {
+                trace.test = Some(TestCase {
+                    name: name.into(),
+                    outcome: Some(outcome.into()),
+                });
+            }
+        }
+        if self.current.as_ref().is_some_and(|c| c.name == name) {
+            self.current = None;
+        }
+    }
+}
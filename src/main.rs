@@ -1,13 +1,68 @@
 use argh::FromArgs;
+use input::{ParseError, Selection, Source, Trace};
+use normalize::Normalization;
 use std::{
     cmp::Ordering,
+    collections::HashMap,
     error::Error,
     fmt,
     fs::File,
-    io::{BufRead, BufReader, Write},
+    io::Write,
     path::PathBuf,
+    sync::atomic::{self, AtomicBool},
 };
 
+/// Set when stdout is the output, see `--format text`.
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Prints a status line (`= pegviz …`), to stdout unless the output goes
+/// there, in which case it's printed to stderr so as not to get mixed in.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if crate::STATUS_TO_STDERR.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod advise;
+mod aggregate;
+mod assets;
+mod chrome;
+mod chunks;
+mod cluster;
+mod csv;
+mod encoding;
+mod expect;
+mod fixtures;
+mod flame;
+mod format;
+mod gen;
+mod gzip;
+mod hexdump;
+mod highlight;
+mod input;
+mod json;
+mod junit;
+mod libtest;
+mod markdown;
+mod normalize;
+mod pretty;
+mod run_info;
+mod sarif;
+mod spill;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod svg;
+mod text;
+mod timestamp;
+mod tokens;
+mod tui;
+#[cfg(feature = "zstd")]
+mod zstd;
+
 #[derive(Debug)]
 enum State {
     Success,
@@ -15,23 +70,41 @@ enum State {
     Unknown,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct Location {
-    line: usize,
-    column: usize,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Location {
+    /// `line:column`, as printed by peg for `str` inputs
+    LineCol { line: usize, column: usize },
+    /// a bare offset, as printed by custom `Position` impls, treated as a
+    /// byte position
+    Offset(usize),
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}:{}", self.line, self.column)
+        match self {
+            Location::LineCol { line, column } => write!(f, "{}:{}", line, column),
+            Location::Offset(offset) => write!(f, "@{}", offset),
+        }
     }
 }
 
 impl Ord for Location {
     fn cmp(&self, other: &Self) -> Ordering {
-        match self.line.cmp(&other.line) {
-            Ordering::Equal => self.column.cmp(&other.column),
-            x => x,
+        match (self, other) {
+            (
+                Location::LineCol { line, column },
+                Location::LineCol {
+                    line: other_line,
+                    column: other_column,
+                },
+            ) => match line.cmp(other_line) {
+                Ordering::Equal => column.cmp(other_column),
+                x => x,
+            },
+            (Location::Offset(a), Location::Offset(b)) => a.cmp(b),
+            // a single trace doesn't mix conventions
+            (Location::LineCol { .. }, Location::Offset(_)) => Ordering::Less,
+            (Location::Offset(_), Location::LineCol { .. }) => Ordering::Greater,
         }
     }
 }
@@ -48,9 +121,67 @@ struct Node {
     partial_match: bool,
     state: State,
     children: Vec<Node>,
+    /// the `[PEG_TRACE]` lines this node was built from, along with their line
+    /// number in the log, only kept with `--keep-raw`
+    raw: Vec<(usize, String)>,
+    growth: Growth,
+    /// unique across all traces, used for anchors (`#node-{id}`)
+    id: usize,
+    /// line of the log the node starts at (counting from 1), 0 for nodes
+    /// pegviz made up
+    log_line: usize,
+    /// what the node consumed for good, see `account_net`
+    net: Option<Net>,
+    /// how big the tree under the node is, see `count_subtrees`
+    subtree: Option<Subtree>,
+    /// `[PEG_NOTE]` lines logged while the node was being parsed
+    notes: Vec<Note>,
 }
 
-#[derive(Debug)]
+/// A message the application logged in the middle of a trace, like
+/// "entering recovery mode". It's shown among the node's children, with no
+/// bearing on the analysis.
+#[derive(Debug, Clone)]
+struct Note {
+    /// index of the child the note was logged before, `children.len()` if
+    /// it came after all of them
+    before: usize,
+    text: String,
+    log_line: usize,
+}
+
+/// The nodes under a node, for the badges that say which collapsed branches
+/// are worth opening.
+#[derive(Debug, Clone, Copy, Default)]
+struct Subtree {
+    /// not counting the node itself
+    nodes: usize,
+    /// failed nodes among them
+    failures: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Net {
+    /// bytes the rule consumed, counting only children the parser kept
+    consumed: usize,
+    /// bytes consumed by children the parser then went back on
+    backtracked: usize,
+}
+
+/// Role of a node in the "growing the seed" loop peg runs for
+/// `#[cache_left_rec]` rules: the rule is attempted over and over at the same
+/// position, each attempt re-entering itself through the cache, for as long
+/// as it keeps consuming more input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Growth {
+    None,
+    /// 1-based attempt number (0 until the loop is grouped)
+    Iteration(usize),
+    /// synthetic node holding all the iterations as children
+    Loop,
+}
+
+#[derive(Debug, Clone)]
 struct Rule {
     name: String,
     loc: Location,
@@ -74,21 +205,32 @@ enum Line {
     Attempt(Rule),
     Failure(Rule),
     Success(Rule),
-    Cache,
+    /// a cached result was used for this rule, the rule name and location
+    /// are only known for the wording we recognize
+    Cache(Option<Rule>),
     EnterLevel,
     LeaveLevel,
+    Note(String),
 }
 
 peg::parser! {
     grammar tracer() for str {
+        // a `ParseError` printed anywhere in a line, e.g. after a log prefix
+        // or `Error: `
+        pub(crate) rule parse_error() -> ParseError
+            = (!"error at " [_])* "error at " loc:location() ": expected " expected:$([_]*) {
+                ParseError { loc, expected: expected.into() }
+            }
+
         pub(crate) rule line() -> Line
             = "[PEG_TRACE] " l:line0() { l }
+            / "[PEG_NOTE]" " "? text:$([_]*) { Line::Note(text.into()) }
 
         rule line0() -> Line
             = r:attempt() { Line::Attempt(r) }
             / r:fail() { Line::Failure(r) }
             / r:succ() { Line::Success(r) }
-            / cach() { Line::Cache }
+            / r:cach() { Line::Cache(r) }
             / enter() { Line::EnterLevel }
             / leave() { Line::LeaveLevel }
 
@@ -101,8 +243,10 @@ peg::parser! {
         rule succ() -> Rule
             = "Matched rule " r:rule0() { r }
 
-        rule cach()
-            = "Cached " ("match" / "fail") " of rule " [_]*
+        rule cach() -> Option<Rule>
+            = "Cached " ("match" / "fail") " of rule "
+              r:(rule0() / rule1(<identifier()>, <at6()>)) { Some(r) }
+            / "Cached " [_]* { None }
 
         rule enter()
             = "Entering level " [_]*
@@ -110,9 +254,13 @@ peg::parser! {
         rule leave()
             = "Leaving level " [_]*
 
+        // peg 0.5 prints `name at 1:1 (pos 0)`, 0.6 and later backquote
+        // names and say where matches end, releases before 0.5 print bare
+        // names with just `line:column`
         rule rule0() -> Rule
             = rule1(<identifier()>, <at5()>)
-            / rule1(<backquoted(<identifier()>)>, <at6()>)
+            / rule1(<backquoted(<quoted()>)>, <at6()>)
+            / rule1(<identifier()>, <at6()>)
 
         rule rule1(name: rule<&'input str>, at: rule<(Location, Option<Location>)>) -> Rule
             = name:name() at:at() {
@@ -127,31 +275,78 @@ peg::parser! {
             = " at " at:location() " (pos " int() ")" { (at, None) }
 
         rule at6() -> (Location, Option<Location>)
-            = " at " at:location() to:(" to " to:location() { to })? { (at, to) }
+            = " at " at:span() to:(" to " to:span() { to.0 })? { (at.0, to.or(at.1)) }
+
+        rule span() -> (Location, Option<Location>)
+            = start:location() ".." end:location() { (start, Some(end)) }
+            / l:location() { (l, None) }
 
         rule backquoted<T>(e: rule<T>) -> T
             = "`" e:e() "`" { e }
 
+        // custom tracing wrappers may use names with spaces, punctuation,
+        // even backquotes: anything goes until the closing backquote.
+        rule quoted() -> &'input str
+            = $((!"` at " [_])*)
+
         rule identifier() -> &'input str
             = $(['A'..='Z' | 'a'..='z' | '0'..='9' | '_']*)
 
         rule location() -> Location
-            = line:int() ":" column:int() { Location { line, column } }
+            = line:int() ":" column:int() { Location::LineCol { line, column } }
+            / offset:int() { Location::Offset(offset) }
 
         rule int() -> usize
             = digits:$(['0'..='9']+) { digits.parse().unwrap() }
     }
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Gen(gen::GenArgs),
+    Aggregate(aggregate::AggregateArgs),
+}
+
 #[derive(FromArgs)]
 /// Creates an HTML visualization for a trace generated from https://crates.io/crates/peg
 struct Args {
     #[argh(positional)]
-    input: Option<PathBuf>,
+    /// trace files, directories containing trace files or globs - stdin is
+    /// read if none are given, or where `-` is
+    inputs: Vec<PathBuf>,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
 
     #[argh(option, short = 'o')]
-    /// output path, "./trace.html" for example
-    output: PathBuf,
+    /// output path, "./trace.html" for example (required, unless exporting)
+    output: Option<PathBuf>,
+
+    #[argh(option, default = "OutputFormat::Html")]
+    /// what to write to --output: html (the default, the interactive
+    /// report), chrome (trace events, for Perfetto), csv (per-rule
+    /// statistics), folded or speedscope (flame graphs of the input rules
+    /// went over), json (the traces' trees, for scripts), junit (a test case
+    /// per trace, for CI), markdown (nested lists, to paste in issues),
+    /// sqlite (a database of the traces' nodes, for SQL), svg (a static
+    /// drawing of the trees) or text (an indented tree, printed when there's
+    /// no --output)
+    format: OutputFormat,
+
+    #[argh(switch)]
+    /// browse the traces in the terminal, before writing --output if given
+    tui: bool,
+
+    #[argh(option, default = "text::Color::Auto")]
+    /// colors in text output: auto (when printing to a terminal), always or
+    /// never
+    color: text::Color,
+
+    #[argh(option)]
+    /// also write the parsed traces as Rust consts to this path, to use as
+    /// test fixtures
+    export_rust: Option<PathBuf>,
 
     #[argh(option, short = 'f')]
     /// name of rules to flatten - if they have only a single child,
@@ -161,140 +356,786 @@ struct Args {
     #[argh(option, short = 'h')]
     /// name of rules to hide altogether
     hide: Vec<String>,
+
+    #[argh(switch)]
+    /// start in the compact view: one dense line per node, without snippets
+    /// (the viewer can switch back and forth)
+    compact: bool,
+
+    #[argh(switch)]
+    /// expand successful nodes and ghost failed ones, to follow the parse
+    /// path without losing the failures around it
+    dim_failures: bool,
+
+    #[argh(switch)]
+    /// keep the trace lines that produced each node, so they can be shown
+    /// in the viewer
+    keep_raw: bool,
+
+    #[argh(option)]
+    /// format of the trace logs: peg (rust-peg's trace output), pest (parse
+    /// attempts), nom (nom-tracable), lalrpop (shifts and reductions),
+    /// tree-sitter (`parse --debug`), chumsky (`.debug()` output), combine
+    /// (traced with `tracing`) or json (one event per line). Guessed from the
+    /// first lines of each log when not given
+    trace_format: Option<format::TraceFormat>,
+
+    #[argh(option)]
+    /// file describing the trace lines of a format of your own, instead of
+    /// --trace-format: what attempts, successes and failures look like, with
+    /// placeholders for the rule and positions
+    format_spec: Option<PathBuf>,
+
+    #[argh(option, default = "encoding::Utf8Errors::Lossy")]
+    /// what to do with invalid UTF-8 in the log: lossy (the default,
+    /// replaces it with U+FFFD) or strict (stops with an error)
+    utf8: encoding::Utf8Errors,
+
+    #[argh(option)]
+    /// file holding the parsed input, for traces logged without a
+    /// [PEG_INPUT_START] block before their [PEG_TRACE_START]
+    source: Option<PathBuf>,
+
+    #[argh(option, default = "Normalization::None")]
+    /// unicode normalization to apply to the captured input before mapping
+    /// positions to it: nfc, nfd, or none (the default)
+    normalize: Normalization,
+
+    #[argh(option)]
+    /// reformat the input shown in the input panel, to make minified data
+    /// readable: json, or auto (only when the input looks minified). Node
+    /// snippets keep showing the input as parsed
+    pretty_input: Option<pretty::PrettyInput>,
+
+    #[argh(option)]
+    /// highlight the syntax of the input in the input panel and node
+    /// snippets: json or sql
+    input_lang: Option<highlight::Lang>,
+
+    #[argh(option)]
+    /// snippet context for a given rule, as `rule=before:after` (in chars),
+    /// for rules that match long constructs - defaults to 10:25. Like for
+    /// --flatten and --hide, names may be qualified as `grammar::rule`
+    context_for: Vec<ContextOverride>,
+
+    #[argh(switch)]
+    /// tag traces with the name of the file they were read from (without
+    /// extension) as their grammar, when each file holds a single grammar's
+    /// traces
+    tag_by_input: bool,
+
+    #[argh(switch)]
+    /// keep parsed nodes in a temporary file rather than in memory, for
+    /// traces too large to fit in RAM. Slower, left-recursion isn't grouped,
+    /// and peg 0.5 traces (without end locations) may miss partial matches
+    spill: bool,
+
+    #[argh(switch)]
+    /// only show identical traces (same input, grammar and trace lines)
+    /// once, noting how many times they were seen
+    dedupe: bool,
+
+    #[argh(switch)]
+    /// suggest trying the alternatives of a choice in a different order,
+    /// when one keeps matching after others failed at the same position
+    advise: bool,
+
+    #[argh(option)]
+    /// file listing which traces should succeed and which should fail, to
+    /// flag the ones that didn't (one `<trace number or hash:…>
+    /// <success|failure>` per line)
+    expect: Option<PathBuf>,
+
+    #[argh(switch)]
+    /// group failures by rule and by the shape of the input they failed at,
+    /// and list the largest groups
+    clusters: bool,
+
+    #[argh(option)]
+    /// write failed traces as SARIF to this path, for code scanning UIs to
+    /// annotate where the inputs stopped parsing
+    sarif: Option<PathBuf>,
+
+    #[argh(option)]
+    /// directory of checked-in test inputs, searched for the files failed
+    /// traces were run against (by content) for --sarif
+    sarif_inputs: Vec<PathBuf>,
+
+    #[argh(option)]
+    /// directory to write a likely reproduction of each failed trace to, as
+    /// `trace-N.txt`: its input, cut at the end of the line with the farthest
+    /// failure (a guess, it isn't checked to still fail)
+    repro: Option<PathBuf>,
+
+    #[argh(switch)]
+    /// embed the logs that were read, compressed, in the report, so they
+    /// can be downloaded and processed again later
+    embed_log: bool,
+
+    #[argh(switch)]
+    /// add the traces read to the report at --output instead of replacing
+    /// it, re-reading the logs embedded in it (implies --embed-log)
+    append: bool,
+
+    #[argh(switch, long = "static")]
+    /// write the report with every node expanded and no script, to grep,
+    /// diff or print it
+    static_html: bool,
+
+    #[argh(switch)]
+    /// build collapsed nodes into the page only when they're expanded, for
+    /// huge traces (searching or expanding everything still builds them all)
+    lazy: bool,
+
+    #[argh(switch)]
+    /// write the big subtrees of the report to files next to it, which the
+    /// page loads once it's up, for huge traces
+    chunked: bool,
+
+    #[argh(option)]
+    /// write the report's stylesheet and script to this directory, once, and
+    /// link to them rather than inlining them in each report
+    assets_dir: Option<PathBuf>,
+
+    #[argh(option, default = "0")]
+    /// byte offset of the parsed input in its enclosing file, added to the
+    /// offsets shown, for parsers that run over part of a larger document
+    base_offset: usize,
+
+    #[argh(option)]
+    /// line of the enclosing file the parsed input starts on, so shown
+    /// positions match the ones in an editor (defaults to the position base)
+    base_line: Option<usize>,
+
+    #[argh(option)]
+    /// column of the enclosing file the parsed input starts at, which only
+    /// shifts positions on the input's first line (defaults to the position
+    /// base)
+    base_column: Option<usize>,
+
+    #[argh(option)]
+    /// what bare-integer locations (`at 12`) count: byte (offsets into a
+    /// str), char, or token (indices into a slice, printed one token per
+    /// line). Guessed for each trace when not given
+    position_kind: Option<PositionKind>,
+
+    #[argh(option, default = "PositionBase(1)")]
+    /// whether lines and columns count from 0 or 1 (the default, like peg),
+    /// both in the log and in the report
+    position_base: PositionBase,
+
+    #[argh(option)]
+    /// only process the first N traces, counting only those --sample keeps
+    first: Option<usize>,
+
+    #[argh(option)]
+    /// only process the last N traces
+    last: Option<usize>,
+
+    #[argh(option)]
+    /// only process one trace out of k, written as `1/k`
+    sample: Option<Sample>,
+
+    #[argh(option, default = "PrimaryPosition::LineCol")]
+    /// which position convention to show first: line-col (the default,
+    /// counted in chars) or byte (absolute byte offsets)
+    primary_position: PrimaryPosition,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Html,
+    Chrome,
+    Csv,
+    Folded,
+    Json,
+    Junit,
+    Markdown,
+    Speedscope,
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    Svg,
+    Text,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(OutputFormat::Html),
+            "chrome" => Ok(OutputFormat::Chrome),
+            "csv" => Ok(OutputFormat::Csv),
+            "folded" => Ok(OutputFormat::Folded),
+            "json" => Ok(OutputFormat::Json),
+            "junit" => Ok(OutputFormat::Junit),
+            "markdown" => Ok(OutputFormat::Markdown),
+            "speedscope" => Ok(OutputFormat::Speedscope),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(OutputFormat::Sqlite),
+            #[cfg(not(feature = "sqlite"))]
+            "sqlite" => Err("pegviz was built without the `sqlite` feature".into()),
+            "svg" => Ok(OutputFormat::Svg),
+            "text" => Ok(OutputFormat::Text),
+            _ => Err(format!(
+                "unknown output format {:?}, expected html, chrome, csv, folded, json, junit, markdown, speedscope, sqlite, svg or text",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum PrimaryPosition {
+    LineCol,
+    Byte,
+}
+
+impl std::str::FromStr for PrimaryPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "line-col" => Ok(PrimaryPosition::LineCol),
+            "byte" => Ok(PrimaryPosition::Byte),
+            _ => Err(format!(
+                "unknown position convention {:?}, expected line-col or byte",
+                s
+            )),
+        }
+    }
+}
+
+/// What the first line and column are numbered, see `--position-base`.
+/// Internally, positions always count from 1.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct PositionBase(usize);
+
+impl std::str::FromStr for PositionBase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(PositionBase(0)),
+            "1" => Ok(PositionBase(1)),
+            _ => Err(format!("expected a position base of 0 or 1, got {:?}", s)),
+        }
+    }
+}
+
+impl PositionBase {
+    /// `loc`, read from a log in this convention, counting from 1.
+    fn rebase(self, loc: Location) -> Location {
+        match loc {
+            Location::LineCol { line, column } => Location::LineCol {
+                line: line + 1 - self.0,
+                column: column + 1 - self.0,
+            },
+            loc => loc,
+        }
+    }
+
+    /// Rebases every location in `line`, see `rebase`.
+    fn rebase_line(self, line: &mut Line) {
+        let rule = match line {
+            Line::Attempt(rule) | Line::Failure(rule) | Line::Success(rule) => rule,
+            Line::Cache(Some(rule)) => rule,
+            _ => return,
+        };
+        rule.loc = self.rebase(rule.loc);
+        rule.next_loc = rule.next_loc.map(|loc| self.rebase(loc));
+    }
+}
+
+/// What bare-integer locations count, see `--position-kind`. Internally,
+/// they're always byte offsets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PositionKind {
+    Byte,
+    Char,
+    /// index into the tokens, which the input lists one per line
+    Token,
+}
+
+impl std::str::FromStr for PositionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "byte" => Ok(PositionKind::Byte),
+            "char" => Ok(PositionKind::Char),
+            "token" => Ok(PositionKind::Token),
+            _ => Err(format!(
+                "unknown position kind {:?}, expected byte, char or token",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PositionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PositionKind::Byte => "byte",
+            PositionKind::Char => "char",
+            PositionKind::Token => "token",
+        })
+    }
+}
+
+impl PositionKind {
+    /// Guesses from the offsets in a trace: a parse that got through its
+    /// input ends at its length, counted in whichever unit the parser uses.
+    /// Offsets splitting a char can only be char indices.
+    fn guess(input: &str, offsets: &[usize]) -> PositionKind {
+        let input = input.strip_suffix('\n').unwrap_or(input);
+        if offsets
+            .iter()
+            .any(|&o| o < input.len() && !input.is_char_boundary(o))
+        {
+            return PositionKind::Char;
+        }
+        match offsets.iter().max() {
+            Some(&max) if max == input.len() => PositionKind::Byte,
+            Some(&max) if max == input.chars().count() => PositionKind::Char,
+            Some(&max) if max == input.lines().count() => PositionKind::Token,
+            _ => PositionKind::Byte,
+        }
+    }
+
+    /// `loc`, counted in this kind, as a byte offset into `input`.
+    fn convert(self, loc: Location, input: &str) -> Location {
+        let offset = match loc {
+            Location::Offset(offset) => offset,
+            loc => return loc,
+        };
+        let input = input.strip_suffix('\n').unwrap_or(input);
+        Location::Offset(match self {
+            PositionKind::Byte => offset,
+            PositionKind::Char => input
+                .char_indices()
+                .nth(offset)
+                .map_or(input.len(), |(i, _)| i),
+            PositionKind::Token => match offset {
+                0 => 0,
+                n => input
+                    .match_indices('\n')
+                    .nth(n - 1)
+                    .map_or(input.len(), |(i, _)| i + 1),
+            },
+        })
+    }
+
+    /// Converts every location in `line`, see `convert`.
+    fn convert_line(self, line: &mut Line, input: &str) {
+        let rule = match line {
+            Line::Attempt(rule) | Line::Failure(rule) | Line::Success(rule) => rule,
+            Line::Cache(Some(rule)) => rule,
+            _ => return,
+        };
+        rule.loc = self.convert(rule.loc, input);
+        rule.next_loc = rule.next_loc.map(|loc| self.convert(loc, input));
+    }
+
+    /// Converts every location in the tree under `node`.
+    fn convert_tree(self, node: &mut Node, input: &str) {
+        node.rule.loc = self.convert(node.rule.loc, input);
+        node.rule.next_loc = node.rule.next_loc.map(|loc| self.convert(loc, input));
+        for child in &mut node.children {
+            self.convert_tree(child, input);
+        }
+    }
+}
+
+/// The bare-integer locations in the tree under `node`.
+fn offsets(node: &Node, out: &mut Vec<usize>) {
+    for loc in std::iter::once(node.rule.loc).chain(node.rule.next_loc) {
+        if let Location::Offset(offset) = loc {
+            out.push(offset);
+        }
+    }
+    for child in &node.children {
+        offsets(child, out);
+    }
+}
+
+/// Sampling rate for `--sample`: one trace out of `.0`.
+#[derive(Clone, Copy)]
+struct Sample(usize);
+
+impl std::str::FromStr for Sample {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("1/").map(str::parse) {
+            Some(Ok(k)) if k > 0 => Ok(Sample(k)),
+            _ => Err(format!("expected `1/k` with k > 0, got {:?}", s)),
+        }
+    }
+}
+
+/// Snippet context override for a single rule, see `--context-for`.
+struct ContextOverride {
+    rule: String,
+    before: usize,
+    after: usize,
+}
+
+impl std::str::FromStr for ContextOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("expected `rule=before:after`, got {:?}", s);
+        let (rule, context) = s.split_at(s.rfind('=').ok_or_else(err)?);
+        let mut context = context[1..].splitn(2, ':');
+        let mut next = || -> Result<usize, String> {
+            context.next().and_then(|n| n.parse().ok()).ok_or_else(err)
+        };
+        Ok(ContextOverride {
+            rule: rule.into(),
+            before: next()?,
+            after: next()?,
+        })
+    }
+}
+
+/// Whether a rule name given on the command line designates `node`. Names
+/// may be qualified with a grammar, as in `json::value`, to only match in
+/// traces tagged with that grammar.
+fn rule_matches(pattern: &str, trace: &Trace, node: &Node) -> bool {
+    match pattern.rfind("::") {
+        Some(i) if trace.grammar.is_some() => {
+            trace.grammar.as_deref() == Some(&pattern[..i]) && pattern[i + 2..] == node.rule.name
+        }
+        _ => pattern == node.rule.name,
+    }
 }
 
 impl Args {
-    fn should_flatten(&self, node: &Node) -> bool {
-        self.flatten.iter().any(|x| x == &node.rule.name) && node.children.len() == 1
+    /// Line and column of byte offset `pos` in `input`, in the enclosing
+    /// file's coordinates (see `--base-line`) and the position convention.
+    fn line_col(&self, input: &str, pos: usize) -> Location {
+        let base = self.position_base.0;
+        match line_col(input, pos) {
+            Location::LineCol { line, column } => Location::LineCol {
+                line: line - 1 + self.base_line.unwrap_or(base),
+                column: if line == 1 {
+                    column - 1 + self.base_column.unwrap_or(base)
+                } else {
+                    column - 1 + base
+                },
+            },
+            loc => loc,
+        }
+    }
+
+    /// Byte offset `pos` in the enclosing file's coordinates (see
+    /// `--base-offset`).
+    fn offset(&self, pos: usize) -> usize {
+        pos + self.base_offset
+    }
+
+    /// `loc` in the enclosing file's coordinates, keeping its convention.
+    fn display_loc(&self, input: &str, loc: Location) -> Location {
+        match loc {
+            Location::LineCol { .. } => self.line_col(input, loc.pos(input)),
+            Location::Offset(_) => Location::Offset(self.offset(loc.pos(input))),
+        }
+    }
+    fn should_flatten(&self, trace: &Trace, node: &Node, child_count: usize) -> bool {
+        self.flatten.iter().any(|x| rule_matches(x, trace, node)) && child_count == 1
+    }
+
+    fn should_hide(&self, trace: &Trace, node: &Node) -> bool {
+        self.hide.iter().any(|x| rule_matches(x, trace, node))
     }
 
-    fn should_hide(&self, node: &Node) -> bool {
-        self.hide.iter().any(|x| x == &node.rule.name)
+    /// How many chars of input to show before and after a node's match.
+    fn context(&self, trace: &Trace, node: &Node) -> (usize, usize) {
+        self.context_for
+            .iter()
+            .rev()
+            .find(|x| rule_matches(&x.rule, trace, node))
+            .map(|x| (x.before, x.after))
+            .unwrap_or((10, 25))
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Args = argh::from_env();
-
-    enum ParseState {
-        WaitingForInputStart,
-        ReadingInput,
-        ReadingTrace,
-    }
-    let mut state = ParseState::WaitingForInputStart;
-    let mut traces: Vec<(Node, String)> = Default::default();
-    let mut stack: Vec<Node> = vec![];
-    let mut input = String::new();
-    let mut trace_number = 1;
-
-    let stdin = std::io::stdin();
-    let stream = match &args.input {
-        Some(input) => Box::new(BufReader::new(File::open(&input)?)) as Box<dyn BufRead>,
-        None => Box::new(stdin.lock()) as Box<dyn BufRead>,
-    };
+/// Like `argh::from_env`, but lets `-` through, for stdin: argh would take
+/// it for an unknown option.
+fn args_from_env() -> Args {
+    let strings: Vec<String> = std::env::args()
+        .map(|arg| if arg == "-" { input::STDIN.into() } else { arg })
+        .collect();
+    let strs: Vec<&str> = strings.iter().map(|s| s.as_str()).collect();
+    Args::from_args(&[strs[0]], &strs[1..]).unwrap_or_else(|early_exit| {
+        println!("{}", early_exit.output);
+        std::process::exit(match early_exit.status {
+            Ok(()) => 0,
+            Err(()) => 1,
+        })
+    })
+}
 
-    for line in stream.lines() {
-        let line = line?;
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = args_from_env();
+    match &args.command {
+        Some(Command::Gen(gen_args)) => return Ok(gen::run(gen_args)?),
+        Some(Command::Aggregate(agg_args)) => return aggregate::run(&args, agg_args),
+        None => {}
+    }
+    if args.output.is_none()
+        && args.export_rust.is_none()
+        && args.sarif.is_none()
+        && !args.tui
+        && args.format != OutputFormat::Text
+    {
+        return Err("pegviz: the --output argument is mandatory".into());
+    }
+    if args.tui && !cfg!(unix) {
+        return Err("pegviz: --tui drives the terminal through /dev/tty and stty, which only Unix has, try --format text".into());
+    }
+    if args.output.is_none() && args.format == OutputFormat::Text && !args.tui {
+        STATUS_TO_STDERR.store(true, atomic::Ordering::Relaxed);
+    }
 
-        match state {
-            ParseState::WaitingForInputStart => {
-                if line == "[PEG_INPUT_START]" {
-                    println!("= pegviz input start");
-                    state = ParseState::ReadingInput;
-                    continue;
+    let mut sources = Source::expand(&args.inputs)?;
+    if args.append {
+        match &args.output {
+            Some(output) if output.exists() => {
+                let earlier = Source::embedded_in(output)?;
+                if earlier.is_empty() {
+                    return Err(format!(
+                        "pegviz: {} has no embedded logs to append to, it wasn't generated with --embed-log",
+                        output.display()
+                    )
+                    .into());
                 }
+                status!(
+                    "= pegviz appending to {} earlier logs from {}",
+                    earlier.len(),
+                    output.display()
+                );
+                sources.splice(0..0, earlier);
             }
-            ParseState::ReadingInput => {
-                if line == "[PEG_TRACE_START]" {
-                    println!("= pegviz trace start");
-                    state = ParseState::ReadingTrace;
-                    stack.push(Node {
-                        rule: Rule {
-                            name: format!("Trace #{}", trace_number),
-                            loc: Location { column: 0, line: 0 },
-                            next_loc: None,
-                        },
-                        partial_match: false,
-                        state: State::Success,
-                        children: vec![],
-                    });
-                    trace_number += 1;
-                    continue;
-                }
+            Some(_) => {}
+            None => return Err("pegviz: --append needs --output".into()),
+        }
+    }
+    if args.chunked && args.static_html {
+        return Err(
+            "pegviz: --chunked needs the report's script, which --static leaves out".into(),
+        );
+    }
+    // appended reports keep their logs, to be appended to again
+    let embed_log = args.embed_log || args.append;
+
+    let mut traces: Vec<Trace> = vec![];
+    let mut selection = Selection::new(&args);
+    // with `--embed-log`, each source's name and contents
+    let mut logs: Vec<(String, String)> = vec![];
+    // of the sources read, see `RunInfo`
+    let mut hashes = vec![];
+    for source in &sources {
+        if selection.is_exhausted() && !embed_log {
+            break;
+        }
+        let mut log = String::new();
+        let capture = if embed_log { Some(&mut log) } else { None };
+        hashes.push(input::read_traces(
+            source,
+            &args,
+            &mut selection,
+            &mut traces,
+            capture,
+        )?);
+        if embed_log {
+            let stem = source.stem().unwrap_or_else(|| "stdin".into());
+            let mut name = format!("{}.log", stem);
+            // stdin, appended to several times over
+            let mut n = 1;
+            while logs.iter().any(|(other, _)| *other == name) {
+                n += 1;
+                name = format!("{}-{}.log", stem, n);
+            }
+            logs.push((name, log));
+        }
+    }
+    if traces.len() < selection.seen() {
+        status!(
+            "= pegviz kept {} of {} traces",
+            traces.len(),
+            selection.seen()
+        );
+    }
 
-                use std::fmt::Write;
-                writeln!(&mut input, "{}", line)?;
+    let merged = sources.len() > 1 && input::order_chronologically(&mut traces);
+    if merged {
+        status!(
+            "= pegviz merged traces from {} files by timestamp",
+            sources.len()
+        );
+    }
+    let mut per_test: HashMap<String, usize> = HashMap::new();
+    for trace in &mut traces {
+        trace.root.rule.name = match &trace.test {
+            Some(test) => {
+                let n = per_test.entry(test.name.clone()).or_default();
+                *n += 1;
+                match n {
+                    1 => test.name.clone(),
+                    n => format!("{} #{}", test.name, n),
+                }
             }
-            ParseState::ReadingTrace => {
-                if line == "[PEG_TRACE_STOP]" {
-                    println!("= pegviz trace stop");
-                    assert_eq!(stack.len(), 1);
-                    let root = stack.pop().unwrap();
-                    traces.push((root, input.clone()));
-                    input.clear();
-                    state = ParseState::WaitingForInputStart;
-                    continue;
+            None => format!("Trace #{}", trace.index + 1),
+        };
+    }
+
+    status!("=======================================");
+    status!("= pegviz input stop");
+    status!("=======================================");
+
+    if traces.is_empty() {
+        status!("pegviz: no trace, exiting");
+        return Ok(());
+    }
+
+    if let Some(path) = &args.export_rust {
+        fixtures::write_fixtures(&mut File::create(path)?, &traces)?;
+        status!("= pegviz exported Rust fixtures to {}", path.display());
+    }
+
+    let mut next_id = 0;
+    for trace in &mut traces {
+        if trace.spilled.is_none() {
+            group_left_recursion(&mut trace.root);
+            account_net(&mut trace.root, &trace.input);
+            count_subtrees(&mut trace.root);
+            backfill_next_loc(&mut trace.root, None);
+            mark_partial_matches(&mut trace.root);
+        }
+        number_nodes(&mut trace.root, &mut next_id);
+        if let Some(tree) = &trace.spilled {
+            next_id += tree.nodes;
+        }
+        trace.consumed = consumed(trace)?;
+        if trace.binary.is_none() {
+            trace.highlight = args.input_lang.map(|lang| lang.highlight(&trace.input));
+        }
+    }
+
+    if let Some(path) = &args.expect {
+        let expectations = expect::Expectations::load(path)?;
+        let mut diverging = vec![];
+        for trace in &mut traces {
+            trace.expected = expectations.get(trace);
+            if let Some(expected) = trace.expected {
+                if expected != succeeded(trace) {
+                    diverging.push(format!("#{}", trace.index + 1));
                 }
+            }
+        }
+        if diverging.is_empty() {
+            status!("= pegviz all traces ended as expected");
+        } else {
+            status!(
+                "= pegviz {} traces did not end as expected: {}",
+                diverging.len(),
+                diverging.join(", ")
+            );
+        }
+    }
 
-                let t = match tracer::line(&line) {
-                    Ok(t) => t,
-                    Err(e) => {
-                        println!("= pegviz error:\nfor line\n|  {}\n{:#?}", line, e);
-                        return Ok(());
-                    }
-                };
-
-                match t {
-                    Line::Attempt(rule) => {
-                        let node = Node {
-                            rule,
-                            state: State::Unknown,
-                            children: vec![],
-                            partial_match: false,
-                        };
-                        stack.push(node);
-                    }
-                    Line::Success(rule) => {
-                        let mut node = stack.pop().unwrap();
-                        if rule.name != node.rule.name {
-                            panic!(
-                                "pegviz: expected rule {:?} to finish, but got {:?}",
-                                rule.name, node.rule.name
-                            );
-                        }
-                        node.state = State::Success;
-                        node.rule.next_loc = rule.next_loc;
-                        stack.last_mut().unwrap().children.push(node);
-                    }
-                    Line::Failure(rule) => {
-                        let mut node = stack.pop().unwrap();
-                        if rule.name != node.rule.name {
-                            panic!(
-                                "pegviz: expected rule {:?} to finish, but got {:?}",
-                                rule.name, node.rule.name
-                            );
-                        }
-                        node.state = State::Failure;
-                        stack.last_mut().unwrap().children.push(node);
-                    }
-                    Line::Cache => {}
-                    Line::EnterLevel => {}
-                    Line::LeaveLevel => {}
+    if let Some(path) = &args.sarif {
+        let files = sarif::InputFiles::scan(&args.sarif_inputs)?;
+        let failed = sarif::write_sarif(&mut File::create(path)?, &args, &traces, &files)?;
+        status!(
+            "= pegviz wrote {} failed traces to {}",
+            failed,
+            path.display()
+        );
+    }
+
+    if let Some(dir) = &args.repro {
+        std::fs::create_dir_all(dir)?;
+        let mut written = 0;
+        for trace in &traces {
+            if let Some(end) = failing_prefix(trace)? {
+                let path = dir.join(format!("trace-{}.txt", trace.index + 1));
+                match &trace.binary {
+                    Some(bytes) => std::fs::write(path, &bytes[..end])?,
+                    None => std::fs::write(path, &trace.input[..end])?,
                 }
+                written += 1;
             }
         }
+        status!(
+            "= pegviz wrote {} reproductions to {}",
+            written,
+            dir.display()
+        );
     }
 
-    println!("=======================================");
-    println!("= pegviz input stop");
-    println!("=======================================");
+    if args.tui {
+        tui::run(&args, &traces)?;
+    }
 
-    if traces.is_empty() {
-        println!("pegviz: no trace, exiting");
+    let output = match (&args.output, args.format) {
+        (Some(output), _) => output,
+        (None, OutputFormat::Text) if !args.tui => {
+            let color = args.color.enabled(true);
+            match text::write_text(&mut std::io::stdout().lock(), &args, &traces, color) {
+                // piped into `head`, say
+                Err(e)
+                    if e.downcast_ref::<std::io::Error>()
+                        .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) => {}
+                res => res?,
+            }
+            return Ok(());
+        }
+        (None, _) => return Ok(()),
+    };
+
+    let mut out = File::create(output)?;
+    let mut run_info = run_info::RunInfo::collect(&sources, &hashes)?;
+    if args.static_html {
+        // so that a report only changes when its logs do
+        run_info.generated = None;
+    }
+    let written = match args.format {
+        OutputFormat::Html => false,
+        OutputFormat::Chrome => chrome::write_chrome(&mut out, &args, &traces).map(|_| true)?,
+        OutputFormat::Csv => csv::write_csv(&mut out, &traces).map(|_| true)?,
+        OutputFormat::Folded => flame::write_folded(&mut out, &args, &traces).map(|_| true)?,
+        OutputFormat::Json => json::write_json(&mut out, &run_info, &traces).map(|_| true)?,
+        OutputFormat::Junit => junit::write_junit(&mut out, &args, &traces).map(|_| true)?,
+        OutputFormat::Markdown => {
+            markdown::write_markdown(&mut out, &args, &traces).map(|_| true)?
+        }
+        OutputFormat::Speedscope => {
+            flame::write_speedscope(&mut out, &args, &traces).map(|_| true)?
+        }
+        #[cfg(feature = "sqlite")]
+        OutputFormat::Sqlite => sqlite::write_sqlite(output, &traces).map(|_| true)?,
+        OutputFormat::Svg => svg::write_svg(&mut out, &args, &traces).map(|_| true)?,
+        OutputFormat::Text => {
+            let color = args.color.enabled(false);
+            text::write_text(&mut out, &args, &traces, color).map(|_| true)?
+        }
+    };
+    if written {
+        status!(
+            "= pegviz wrote {} traces to {}",
+            traces.len(),
+            output.display()
+        );
         return Ok(());
     }
-
-    let mut out = File::create(&args.output)?;
+    let chunks = if args.chunked {
+        Some(chunks::Chunks::new(output)?)
+    } else {
+        None
+    };
 
     writeln!(
         &mut out,
@@ -303,24 +1144,185 @@ fn main() -> Result<(), Box<dyn Error>> {
     <html lang="en">
         <head>
         <meta charset="utf-8"/>
-            <style>{style}</style>
-            <script>{script}</script>
+            {assets}
         </head>
-        <body>
+        <body class="{body_class}">
         <div id="notifications"></div>
+        <div id="source-panel" hidden><div class="title"></div><pre></pre></div>
+        <aside id="stats-sidebar" hidden><table></table></aside>
+        <div id="toolbar">
+            <span title="shift-click a rule to prune it, alt-click to flatten it"><span class="count">0</span> nodes pruned or flattened</span>
+            <button id="export-pruned">Export</button>
+            <button id="toggle-compact" title="one line per node, without snippets">Compact</button>
+            <button id="toggle-source-panel" title="the input beside the tree, showing what the hovered node matched">Source</button>
+            <button id="jump-farthest" title="show where the hovered trace (or the first failed one) failed farthest in its input (f)">Farthest failure</button>
+            <button id="previous-failure" title="the previous failed node (p)">Previous failure</button>
+            <button id="next-failure" title="the next failed node (n)">Next failure</button>
+            <button id="toggle-failures-only" title="hide the successful subtrees with no failure in them">Failures only</button>
+            <button id="toggle-stats" title="attempts, successes and failures of each rule">Stats</button>
+            <button id="expand-all" title="expand every node (+)">Expand all</button>
+            <button id="collapse-all" title="collapse every node below the traces' top-level rules (-)">Collapse all</button>
+            <label title="open the tree down to this depth, top-level rules being at 1">depth <input id="open-depth" type="number" min="0"></label>
+            <button id="toggle-scheme" title="switch between the dark and light color schemes">Light/Dark</button>
+            {embedded_logs}
+            <span id="global-search">
+                <input type="search" placeholder="search all traces" title="rule names and matched text">
+                <div class="results"></div>
+            </span>
+            <span id="input-search">
+                <input type="search" placeholder="search the input" title="a regular expression, looked for in every trace's input: matches are highlighted in the source panel, and listed with the rules attempted where they start">
+                <div class="results"></div>
+            </span>
+            <span id="tree-search">
+                <input type="search" placeholder="filter rules" title="rule names: matches are highlighted and the tree opened to show them, Enter goes from one to the next">
+                <label title="hide the nodes that don't match and have no match under them"><input type="checkbox"> only matches</label>
+                <span class="count"></span>
+            </span>
+            <details id="annotations">
+                <summary title="hover a node and press b to bookmark it, a to annotate it"><span class="count">0</span> bookmarks and notes</summary>
+                <ul></ul>
+                <button id="export-annotations">Export</button>
+                <button id="import-annotations">Import</button>
+                <input id="import-annotations-file" type="file" accept="application/json" hidden>
+            </details>
+            <details id="rule-controls">
+                <summary title="try out --hide and --flatten without running pegviz again">hide and flatten rules</summary>
+                <table></table>
+                <p>same as <code class="flags">no options</code></p>
+            </details>
+        </div>
+        <details id="legend">
+            <summary>Legend</summary>
+            <dl>
+                <dt><span class="rule success">rule</span></dt><dd>matched</dd>
+                <dt><span class="rule failure">rule</span></dt><dd>failed</dd>
+                <dt><span class="rule failure partial-match" title="partial match: matched some input, but then failed and the parser backtracked" aria-label="rule, partial match">rule</span></dt><dd>failed, after rules it tried had matched some input</dd>
+                <dt><span class="rule unknown">rule</span></dt><dd>never finished, e.g. because the parser panicked</dd>
+                <dt><span class="rule failure at-error">rule</span></dt><dd>failed where the parse error is</dd>
+                <dt><code><em>before</em><strong>matched</strong><span>after</span></code></dt><dd>the input before the rule's position, what it matched, and what follows</dd>
+                <dt><code>↩</code></dt><dd>the rule ended before where it started, as the parser backtracked</dd>
+            </dl>
+        </details>
+        <div id="pinned">
+            <div id="scroll-context" hidden></div>
+            <nav id="breadcrumb" hidden></nav>
+        </div>
     "#,
-        style = include_str!("style.css"),
-        script = include_str!("index.js")
+        assets = assets::tags(args.assets_dir.as_deref(), output, !args.static_html)?,
+        embedded_logs = embedded_logs(&logs),
+        body_class = [
+            match args.primary_position {
+                PrimaryPosition::LineCol => "",
+                PrimaryPosition::Byte => "byte-primary",
+            },
+            if args.compact { "compact" } else { "" },
+            if args.static_html { "static" } else { "" },
+        ]
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" "),
     )?;
+    if !args.static_html {
+        write_stats_json(&mut out, &traces)?;
+    }
+    run_info.write_html(&mut out)?;
 
-    for trace in &mut traces {
-        backfill_next_loc(&mut trace.0, None);
-        mark_partial_matches(&mut trace.0);
+    if args.advise {
+        let mut advisor = advise::Advisor::default();
+        for trace in &traces {
+            advisor.add_trace(trace)?;
+        }
+        let advice = advisor.advice();
+        status!("= pegviz {} choice ordering suggestions", advice.len());
+        for advice in &advice {
+            status!("= pegviz   {}", advice.describe());
+        }
+        advise::write_advice(&mut out, &advice)?;
     }
 
-    for trace in &traces {
-        let (root, input) = &trace;
-        visit(&mut out, &args, root, input)?;
+    if args.clusters {
+        let mut clusters = cluster::Clusters::default();
+        for trace in &traces {
+            clusters.add_trace(&args, trace, true)?;
+        }
+        let largest = clusters.largest(20);
+        for (rule, shape, cluster) in &largest {
+            status!(
+                "= pegviz {} failures of `{}` at `{}` in {} traces",
+                cluster.count,
+                rule,
+                shape,
+                cluster.traces
+            );
+        }
+        cluster::write_clusters(&mut out, &largest)?;
+    }
+
+    // a section per file, unless their traces were merged by timestamp
+    let files = if sources.len() > 1 && !merged {
+        let mut files: Vec<(Option<&str>, usize)> = vec![];
+        for trace in &traces {
+            match files.last_mut() {
+                Some((source, count)) if *source == trace.source.as_deref() => *count += 1,
+                _ => files.push((trace.source.as_deref(), 1)),
+            }
+        }
+        write_file_index(&mut out, &files)?;
+        files
+    } else {
+        vec![]
+    };
+    if traces.len() > 1 {
+        write_trace_index(&mut out, &args, &traces)?;
+    }
+
+    let mut files = files.iter().enumerate().peekable();
+    let mut previous_test = None;
+    for (i, trace) in traces.iter().enumerate() {
+        if let Some((i, (source, count))) =
+            files.next_if(|(_, (source, _))| *source == trace.source.as_deref())
+        {
+            writeln!(
+                &mut out,
+                r#"<h2 class="source-file" id="file-{}"><code>{}</code> <span class="count">{} trace{}</span></h2>"#,
+                i + 1,
+                escape_html(source.unwrap_or("stdin")),
+                count,
+                if *count == 1 { "" } else { "s" }
+            )?;
+            previous_test = None;
+        }
+        let test = trace.test.as_ref().map(|t| &t.name);
+        if test.is_some() && test != previous_test {
+            write_test_heading(&mut out, trace.test.as_ref().unwrap())?;
+        }
+        previous_test = test;
+        writeln!(&mut out, r#"<section class="trace" id="trace-{}">"#, i + 1)?;
+        write_source_label(&mut out, &args, trace)?;
+        write_parse_error(&mut out, &args, trace)?;
+        write_farthest_failure(&mut out, &args, trace)?;
+        write_input_panel(&mut out, &args, trace)?;
+        write_repro(&mut out, &args, trace)?;
+        match &trace.spilled {
+            Some(tree) => {
+                write_summary(&mut out, &args, trace, &trace.root)?;
+                let lazy = lazy_children(&args, &trace.root, tree.nodes > 0);
+                if lazy {
+                    write!(&mut out, r#"<template class="children">"#)?;
+                }
+                spill::visit_spilled(&mut out, &args, trace, tree, chunks.as_ref())?;
+                if lazy {
+                    write!(&mut out, "</template>")?;
+                }
+                writeln!(&mut out, "</details>")?;
+            }
+            None => visit(&mut out, &args, trace, &trace.root, chunks.as_ref())?,
+        }
+        writeln!(&mut out, "</section>")?;
+    }
+    if let Some(chunks) = &chunks {
+        chunks.write_index(&mut out)?;
     }
     writeln!(
         &mut out,
@@ -330,7 +1332,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     "#
     )?;
 
-    println!("= pegviz generated to {}", args.output.display());
+    status!("= pegviz generated to {}", output.display());
 
     Ok(())
 }
@@ -355,6 +1357,100 @@ fn print_backfilled(node: &Node, state: &str) {
     }
 }
 
+/// `n` with its digits grouped by thousands, `1,204`.
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut res = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            res.push(',');
+        }
+        res.push(c);
+    }
+    res
+}
+
+/// Gives every node an id that's unique across all traces, in pre-order.
+fn number_nodes(node: &mut Node, next_id: &mut usize) {
+    node.id = *next_id;
+    *next_id += 1;
+    for c in &mut node.children {
+        number_nodes(c, next_id);
+    }
+}
+
+/// Groups the successive attempts of a left-recursive rule (see [`Growth`])
+/// under a single node that holds the final result.
+fn group_left_recursion(node: &mut Node) {
+    for c in &mut node.children {
+        group_left_recursion(c);
+    }
+
+    if !node
+        .children
+        .iter()
+        .any(|c| c.growth == Growth::Iteration(0))
+    {
+        return;
+    }
+
+    let mut children = vec![];
+    // for each original child, the index of the node it ended up in
+    let mut moved_to = vec![];
+    let mut iter = std::mem::take(&mut node.children).into_iter().peekable();
+    while let Some(first) = iter.next() {
+        moved_to.push(children.len());
+        if first.growth != Growth::Iteration(0) {
+            children.push(first);
+            continue;
+        }
+
+        let mut iterations = vec![first];
+        while let Some(next) = iter.peek() {
+            let first = &iterations[0].rule;
+            if next.rule.name != first.name || next.rule.loc != first.loc {
+                break;
+            }
+            moved_to.push(children.len());
+            iterations.push(iter.next().unwrap());
+        }
+        for (i, it) in iterations.iter_mut().enumerate() {
+            it.growth = Growth::Iteration(i + 1);
+        }
+
+        // peg keeps the last iteration that grew the match
+        let result = iterations
+            .iter()
+            .rev()
+            .find(|it| matches!(it.state, State::Success));
+        children.push(Node {
+            rule: Rule {
+                next_loc: result.and_then(|it| it.rule.next_loc),
+                ..iterations[0].rule.clone()
+            },
+            partial_match: false,
+            state: if result.is_some() {
+                State::Success
+            } else {
+                State::Failure
+            },
+            children: iterations,
+            raw: vec![],
+            growth: Growth::Loop,
+            id: 0,
+            log_line: 0,
+            net: None,
+            subtree: None,
+            notes: vec![],
+        });
+    }
+    // notes logged between iterations end up before the whole loop
+    for note in &mut node.notes {
+        note.before = moved_to.get(note.before).copied().unwrap_or(children.len());
+    }
+    node.children = children;
+}
+
 fn mark_partial_matches(node: &mut Node) -> bool {
     for c in &mut node.children {
         mark_partial_matches(c);
@@ -366,6 +1462,60 @@ fn mark_partial_matches(node: &mut Node) -> bool {
     ret
 }
 
+/// Counts the nodes under each node, and the failures among them.
+fn count_subtrees(node: &mut Node) -> Subtree {
+    let mut subtree = Subtree::default();
+    for child in &mut node.children {
+        let under = count_subtrees(child);
+        subtree.nodes += 1 + under.nodes;
+        subtree.failures += under.failures + matches!(child.state, State::Failure) as usize;
+    }
+    node.subtree = Some(subtree);
+    subtree
+}
+
+/// Works out what each node consumed for good, before `backfill_next_loc`
+/// makes up ends for the nodes the log didn't give one. A child that matched
+/// is "backtracked" when the node failed, or when a later child starts before
+/// the point it matched up to: the parser rewound over it (for example when
+/// the first few items of an alternative matched, then another didn't).
+fn account_net(node: &mut Node, input: &str) -> Option<usize> {
+    let start = node.rule.loc.pos(input);
+    let mut kept: Vec<(usize, usize)> = vec![];
+    let mut backtracked = 0;
+    for child in &mut node.children {
+        let end = account_net(child, input);
+        let child_start = child.rule.loc.pos(input);
+        while let Some(&(kept_start, kept_end)) = kept.last() {
+            if kept_end <= child_start {
+                break;
+            }
+            backtracked += kept_end - kept_start;
+            kept.pop();
+        }
+        if let Some(end) = end {
+            kept.push((child_start, end));
+        }
+    }
+
+    let end = match node.state {
+        State::Success => node
+            .rule
+            .next_loc
+            .map(|loc| loc.pos(input))
+            .or_else(|| kept.iter().map(|&(_, end)| end).max()),
+        State::Failure | State::Unknown => {
+            backtracked += kept.iter().map(|&(s, e)| e - s).sum::<usize>();
+            None
+        }
+    };
+    node.net = Some(Net {
+        consumed: end.map_or(0, |end| end.saturating_sub(start)),
+        backtracked,
+    });
+    end
+}
+
 fn backfill_next_loc(node: &mut Node, next: Option<&Node>) {
     for i in 1..node.children.len() {
         if let ([prev], [next]) = &mut node.children[i - 1..i + 1].split_at_mut(1) {
@@ -393,12 +1543,28 @@ fn backfill_next_loc(node: &mut Node, next: Option<&Node>) {
 }
 
 impl Location {
+    /// Placeholder location for the root node of a trace.
+    const ROOT: Location = Location::LineCol { line: 0, column: 0 };
+
+    /// Byte offset of this location in `input`. Columns are counted in chars,
+    /// like peg does for `str` inputs.
     fn pos(&self, input: &str) -> usize {
+        let (target_line, target_column) = match *self {
+            Location::LineCol { line, column } => (line, column),
+            Location::Offset(mut offset) => {
+                offset = offset.min(input.len());
+                while !input.is_char_boundary(offset) {
+                    offset -= 1;
+                }
+                return offset;
+            }
+        };
+
         let mut line = 1;
         let mut column = 1;
 
-        for (i, c) in input.chars().enumerate() {
-            if line == self.line && column == self.column {
+        for (i, c) in input.char_indices() {
+            if line == target_line && column == target_column {
                 return i;
             }
 
@@ -412,24 +1578,864 @@ impl Location {
                 }
             }
         }
+        if line == target_line && column == target_column {
+            // end of input
+            return input.len();
+        }
         0
     }
 }
 
-fn visit(f: &mut dyn Write, args: &Args, node: &Node, input: &str) -> Result<(), Box<dyn Error>> {
-    if args.should_flatten(node) {
-        return visit(f, args, &node.children[0], input);
+/// Line and column (counted in chars) of byte offset `pos` in `input`.
+fn line_col(input: &str, pos: usize) -> Location {
+    let before = &input[..pos];
+    Location::LineCol {
+        line: before.matches('\n').count() + 1,
+        column: before.chars().rev().take_while(|&c| c != '\n').count() + 1,
+    }
+}
+
+/// Byte offset of the char `n` chars before `pos`, or of the start of input.
+fn chars_before(input: &str, pos: usize, n: usize) -> usize {
+    input[..pos]
+        .char_indices()
+        .rev()
+        .take(n)
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(pos)
+}
+
+/// Byte offset of the char `n` chars after `pos`, or of the end of input.
+fn chars_after(input: &str, pos: usize, n: usize) -> usize {
+    input[pos..]
+        .char_indices()
+        .nth(n)
+        .map(|(i, _)| pos + i)
+        .unwrap_or_else(|| input.len())
+}
+
+/// Whether `node` is a failed attempt at the position of the trace's final
+/// parse error.
+fn is_at_error(trace: &Trace, node: &Node) -> bool {
+    match &trace.error {
+        Some(error) => {
+            matches!(node.state, State::Failure)
+                && node.rule.loc != Location::ROOT
+                && node.rule.loc.pos(&trace.input) == error.loc.pos(&trace.input)
+        }
+        None => false,
     }
+}
 
+/// Shows the parse error the program reported, cross-linked to the input
+/// panel and to the rules that failed at that position.
+fn write_parse_error(f: &mut dyn Write, args: &Args, trace: &Trace) -> Result<(), Box<dyn Error>> {
+    let error = match &trace.error {
+        Some(error) => error,
+        None => return Ok(()),
+    };
+
+    fn collect(trace: &Trace, node: &Node, out: &mut Vec<(usize, String)>) {
+        if is_at_error(trace, node) {
+            out.push((node.id, node.rule.name.clone()));
+        }
+        for c in &node.children {
+            collect(trace, c, out);
+        }
+    }
+    let mut failed = vec![];
+    match &trace.spilled {
+        Some(tree) => tree.walk(trace.root.id + 1, &mut |node, id, _| {
+            if is_at_error(trace, node) {
+                failed.push((id, node.rule.name.clone()));
+            }
+        })?,
+        None => collect(trace, &trace.root, &mut failed),
+    }
+
+    write!(
+        f,
+        r##"<div class="parse-error">error at {loc}: expected {expected} <a href="#error-{id}">show in input</a>"##,
+        loc = args.display_loc(&trace.input, error.loc),
+        expected = escape_html(&error.expected),
+        id = trace.root.id,
+    )?;
+    if !failed.is_empty() {
+        write!(f, " — failed here:")?;
+        let max = 20;
+        for (id, name) in failed.iter().take(max) {
+            write!(f, r##" <a href="#node-{}">{}</a>"##, id, escape_html(name))?;
+        }
+        if failed.len() > max {
+            write!(f, " and {} more", failed.len() - max)?;
+        }
+    }
+    writeln!(f, "</div>")?;
+    Ok(())
+}
+
+fn write_farthest_failure(
+    f: &mut dyn Write,
+    args: &Args,
+    trace: &Trace,
+) -> Result<(), Box<dyn Error>> {
+    let FarthestFailure { loc, failed } = match farthest_to_show(trace)? {
+        Some(farthest) => farthest,
+        None => return Ok(()),
+    };
+    write!(
+        f,
+        r##"<div class="farthest-failure">farthest failure at {loc} <a href="#farthest-{id}">show in input</a> — failed here:"##,
+        loc = args.display_loc(&trace.input, loc),
+        id = trace.root.id,
+    )?;
+    let max = 20;
+    for (id, name) in failed.iter().take(max) {
+        write!(f, r##" <a href="#node-{}">{}</a>"##, id, escape_html(name))?;
+    }
+    if failed.len() > max {
+        write!(f, " and {} more", failed.len() - max)?;
+    }
+    writeln!(f, "</div>")?;
+    Ok(())
+}
+
+/// Every rule's statistics, as in `--format csv`, for the report's sidebar
+/// to show.
+fn write_stats_json(f: &mut dyn Write, traces: &[Trace]) -> Result<(), Box<dyn Error>> {
+    let rows: Vec<String> = csv::rule_stats(traces)?
+        .iter()
+        .map(|(name, rule)| {
+            format!(
+                r#"{{"rule":{},"attempts":{},"successes":{},"failures":{},"cache_hits":{},"max_depth":{},"matched_bytes":{}}}"#,
+                json::string(name),
+                rule.attempts,
+                rule.successes,
+                rule.failures,
+                rule.cache_hits,
+                rule.max_depth,
+                rule.matched_bytes
+            )
+        })
+        .collect();
+    // `</script>` in a rule name would end the script early
+    writeln!(
+        f,
+        r#"<script type="application/json" id="rule-stats">[{}]</script>"#,
+        rows.join(",").replace("</", "<\\/")
+    )?;
+    Ok(())
+}
+
+/// The whole input of a trace, with the parse error position (if any) and
+/// the farthest failure highlighted.
+fn write_input_panel(f: &mut dyn Write, args: &Args, trace: &Trace) -> Result<(), Box<dyn Error>> {
+    let input = &trace.input;
+    let pretty = args.pretty_input.and_then(|mode| mode.apply(input));
+    let (text, title) = match &pretty {
+        Some(pretty) => (&pretty.text, "Input (reformatted)"),
+        None => (input, "Input"),
+    };
+    // the input as is, for the source panel to show
+    if !args.static_html {
+        writeln!(
+            f,
+            r#"<template class="source" data-root="node-{}">{}</template>"#,
+            trace.root.id,
+            escape_html(input)
+        )?;
+    }
+    write!(
+        f,
+        r#"<details class="input-panel"{}><summary>{}</summary><pre>"#,
+        if args.static_html { " open" } else { "" },
+        if trace.binary.is_some() {
+            "Input (binary)"
+        } else {
+            title
+        }
+    )?;
+    let mut marks = vec![];
+    if let Some(error) = &trace.error {
+        marks.push((
+            error.loc.pos(input),
+            format!(
+                r#"id="error-{}" class="error-pos" title="error at {}""#,
+                trace.root.id,
+                args.display_loc(input, error.loc)
+            ),
+        ));
+    }
+    if let Some(FarthestFailure { loc, .. }) = farthest_to_show(trace)? {
+        marks.push((
+            loc.pos(input),
+            format!(
+                r#"id="farthest-{}" class="farthest-failure" title="farthest failure, at {}""#,
+                trace.root.id,
+                args.display_loc(input, loc)
+            ),
+        ));
+    }
+    marks.sort_by_key(|(pos, _)| *pos);
+    if let Some(bytes) = &trace.binary {
+        let marks: Vec<_> = marks
+            .iter()
+            .map(|(pos, attrs)| (*pos, attrs.as_str()))
+            .collect();
+        hexdump::write_dump(f, bytes, &marks)?;
+        writeln!(f, "</pre></details>")?;
+        return Ok(());
+    }
+    if let Some(pretty) = &pretty {
+        marks
+            .iter_mut()
+            .for_each(|(pos, _)| *pos = pretty.pos(*pos));
+    }
+    // the reformatted text isn't the one the trace's highlighting is for
+    let highlight = match &pretty {
+        Some(pretty) => args.input_lang.map(|lang| lang.highlight(&pretty.text)),
+        None => None,
+    };
+    let highlight = highlight.as_ref().or(trace.highlight.as_ref());
+    let piece = |start: usize, end: usize| match highlight {
+        Some(highlight) => highlight.html(text, start, end),
+        None => escape_html(&text[start..end]),
+    };
+    let mut html = String::new();
+    let mut written = 0;
+    let mut i = 0;
+    while i < marks.len() {
+        let pos = marks[i].0;
+        // marks at the same position nest, around the same char
+        let same = marks[i..].iter().take_while(|(p, _)| *p == pos).count();
+        let end = chars_after(text, pos, 1);
+        let c = &text[pos..end];
+        html.push_str(&piece(written, pos));
+        for (_, attrs) in &marks[i..i + same] {
+            html.push_str(&format!("<mark {}>", attrs));
+        }
+        // keep the position visible at the end of a line or of the input
+        if c.is_empty() || c == "\n" {
+            html.push(' ');
+        } else {
+            html.push_str(&piece(pos, end));
+        }
+        html.push_str(&"</mark>".repeat(same));
+        if c == "\n" {
+            html.push('\n');
+        }
+        written = end;
+        i += same;
+    }
+    html.push_str(&piece(written, text.len()));
+
+    // a line at a time, numbered as positions are in the tree (reformatted
+    // lines aren't the ones positions count), with those where rules failed
+    // marked in the gutter
+    let starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let mut failures = vec![0; starts.len()];
+    for pos in failure_positions(trace)? {
+        let pos = pretty.as_ref().map_or(pos, |pretty| pretty.pos(pos));
+        failures[starts.partition_point(|&start| start <= pos) - 1] += 1;
+    }
+    let first = match args.line_col(input, 0) {
+        Location::LineCol { line, .. } => line,
+        Location::Offset(_) => 1,
+    };
+    let mut lines: Vec<&str> = html.split('\n').collect();
+    // nothing follows the last newline, failing there is failing at the end
+    // of the line before
+    if lines.len() > 1 && lines.last() == Some(&"") {
+        lines.pop();
+        failures[lines.len() - 1] += failures[lines.len()];
+    }
+    for (i, line) in lines.iter().enumerate() {
+        let number = match pretty {
+            Some(_) => String::new(),
+            None => (first + i).to_string(),
+        };
+        let gutter = match failures[i] {
+            0 => format!(r#"<span class="gutter">{}</span>"#, number),
+            n => format!(
+                r#"<span class="gutter failed" title="{} failure{} on this line">{}</span>"#,
+                n,
+                if n == 1 { "" } else { "s" },
+                number
+            ),
+        };
+        writeln!(
+            f,
+            r#"<span class="line">{}<span class="text">{}</span></span>"#,
+            gutter, line
+        )?;
+    }
+    writeln!(f, "</pre></details>")?;
+    Ok(())
+}
+
+/// Where each failed node of a trace failed, as byte offsets.
+fn failure_positions(trace: &Trace) -> Result<Vec<usize>, Box<dyn Error>> {
+    let mut positions = vec![];
+    let mut visit = |node: &Node| {
+        if matches!(node.state, State::Failure) {
+            positions.push(node.rule.loc.pos(&trace.input));
+        }
+    };
+    match &trace.spilled {
+        Some(tree) => tree.walk(0, &mut |node, _, _| visit(node))?,
+        None => {
+            fn walk(node: &Node, visit: &mut dyn FnMut(&Node)) {
+                visit(node);
+                node.children.iter().for_each(|child| walk(child, visit));
+            }
+            for child in &trace.root.children {
+                walk(child, &mut visit);
+            }
+        }
+    }
+    Ok(positions)
+}
+
+/// Where the furthest successful top-level rule of a trace stopped, if any
+/// did.
+fn consumed(trace: &Trace) -> Result<Option<Location>, Box<dyn Error>> {
+    let furthest = |nodes: &mut dyn Iterator<Item = &Node>| {
+        nodes
+            .filter(|node| matches!(node.state, State::Success))
+            .filter_map(|node| node.rule.next_loc)
+            .max()
+    };
+    Ok(match &trace.spilled {
+        Some(tree) => furthest(&mut tree.top_level()?.iter().map(|l| &l.node)),
+        None => furthest(&mut trace.root.children.iter()),
+    })
+}
+
+/// Byte offset of the farthest failure in a trace: where the parser said
+/// the error was, or else the last position a rule failed to match at.
+fn farthest_failure(trace: &Trace) -> Result<Option<usize>, Box<dyn Error>> {
+    if let Some(error) = &trace.error {
+        return Ok(Some(error.loc.pos(&trace.input)));
+    }
+    Ok(farthest_rule_failure(trace)?.map(|farthest| farthest.loc.pos(&trace.input)))
+}
+
+/// The right-most position a rule failed to match at in a trace, the
+/// classic guess at where a PEG parse went wrong.
+struct FarthestFailure {
+    loc: Location,
+    /// the nodes that failed there, by id and rule name
+    failed: Vec<(usize, String)>,
+}
+
+fn farthest_rule_failure(trace: &Trace) -> Result<Option<FarthestFailure>, Box<dyn Error>> {
+    let mut farthest: Option<(usize, FarthestFailure)> = None;
+    let mut visit = |node: &Node, id: usize| {
+        if !matches!(node.state, State::Failure) {
+            return;
+        }
+        let pos = node.rule.loc.pos(&trace.input);
+        match &mut farthest {
+            Some((f, farthest)) if *f == pos => farthest.failed.push((id, node.rule.name.clone())),
+            Some((f, _)) if *f > pos => {}
+            _ => {
+                let failed = vec![(id, node.rule.name.clone())];
+                farthest = Some((
+                    pos,
+                    FarthestFailure {
+                        loc: node.rule.loc,
+                        failed,
+                    },
+                ))
+            }
+        }
+    };
+    match &trace.spilled {
+        Some(tree) => tree.walk(trace.root.id + 1, &mut |node, id, _| visit(node, id))?,
+        None => {
+            fn walk(node: &Node, visit: &mut dyn FnMut(&Node, usize)) {
+                visit(node, node.id);
+                node.children.iter().for_each(|child| walk(child, visit));
+            }
+            walk(&trace.root, &mut visit);
+        }
+    }
+    Ok(farthest.map(|(_, farthest)| farthest))
+}
+
+/// The farthest rule failure of a failed trace, when it's worth pointing
+/// out: the parser's own error is usually there already.
+fn farthest_to_show(trace: &Trace) -> Result<Option<FarthestFailure>, Box<dyn Error>> {
+    if succeeded(trace) {
+        return Ok(None);
+    }
+    let input = &trace.input;
+    Ok(
+        farthest_rule_failure(trace)?.filter(|farthest| match &trace.error {
+            Some(error) => error.loc.pos(input) != farthest.loc.pos(input),
+            None => true,
+        }),
+    )
+}
+
+/// For a failed trace, the end of a prefix of its input that is likely to
+/// fail the same way: the input, cut at the end of the line with the
+/// farthest failure. That's a guess, pegviz can't run the parser to check:
+/// the parser usually didn't look past that position, except perhaps to
+/// read the token it choked on, but lookahead may well have.
+fn failing_prefix(trace: &Trace) -> Result<Option<usize>, Box<dyn Error>> {
+    if succeeded(trace) {
+        return Ok(None);
+    }
+    let input = &trace.input;
+    Ok(
+        farthest_failure(trace)?.map(|pos| match input[pos..].find('\n') {
+            // failing on the newline itself: it's part of the reproduction
+            Some(0) => pos + 1,
+            Some(i) => pos + i,
+            None => input.len(),
+        }),
+    )
+}
+
+fn write_repro(f: &mut dyn Write, args: &Args, trace: &Trace) -> Result<(), Box<dyn Error>> {
+    let end = match failing_prefix(trace)? {
+        // binary inputs can't be copied as text, `--repro` writes their bytes
+        Some(_) if trace.binary.is_some() => return Ok(()),
+        Some(end) => end,
+        None => return Ok(()),
+    };
+    let input = trace.input.strip_suffix('\n').unwrap_or(&trace.input);
+    let prefix = &trace.input[..end];
+    writeln!(
+        f,
+        r#"<details class="repro"{}><summary>Likely reproduction: first {} of {} lines <span class="copy">copy</span></summary><pre>{}</pre></details>"#,
+        if args.static_html { " open" } else { "" },
+        prefix.lines().count(),
+        input.lines().count(),
+        escape_html(prefix)
+    )?;
+    Ok(())
+}
+
+/// Links to each file's section, when traces come from several.
+fn write_file_index(
+    f: &mut dyn Write,
+    files: &[(Option<&str>, usize)],
+) -> Result<(), Box<dyn Error>> {
+    writeln!(f, r#"<nav id="files"><h2>{} files</h2><ol>"#, files.len())?;
+    for (i, (source, count)) in files.iter().enumerate() {
+        writeln!(
+            f,
+            r##"<li><a href="#file-{}">{}</a> <span class="count">{} trace{}</span></li>"##,
+            i + 1,
+            escape_html(source.unwrap_or("stdin")),
+            count,
+            if *count == 1 { "" } else { "s" }
+        )?;
+    }
+    writeln!(f, "</ol></nav>")?;
+    Ok(())
+}
+
+/// Lists the traces with how they went, the start of their input and their
+/// size, to go from one to another rather than scroll through them all.
+fn write_trace_index(
+    f: &mut dyn Write,
+    args: &Args,
+    traces: &[Trace],
+) -> Result<(), Box<dyn Error>> {
+    writeln!(f, r#"<nav id="traces"><h2>{} traces</h2>"#, traces.len())?;
+    // the selector needs the script, static reports keep the long page
+    if !args.static_html {
+        write!(
+            f,
+            r#"<label>show <select id="trace-select"><option value="">all traces</option>"#
+        )?;
+        for (i, trace) in traces.iter().enumerate() {
+            write!(
+                f,
+                r#"<option value="trace-{}">{}</option>"#,
+                i + 1,
+                escape_html(&trace.root.rule.name)
+            )?;
+        }
+        writeln!(f, "</select></label>")?;
+    }
+    writeln!(
+        f,
+        "<table><tr><th>trace</th><th>outcome</th><th>input</th><th>nodes</th></tr>"
+    )?;
+    for (i, trace) in traces.iter().enumerate() {
+        let max = 48;
+        let preview = match &trace.binary {
+            Some(bytes) => hexdump::hex(&bytes[..bytes.len().min(max / 3)]),
+            None => trace
+                .input
+                .chars()
+                .take(max)
+                .collect::<String>()
+                .replace('\n', "⏎"),
+        };
+        let more = match &trace.binary {
+            Some(bytes) => bytes.len() > max / 3,
+            None => trace.input.chars().nth(max).is_some(),
+        };
+        let nodes = match (&trace.spilled, trace.root.subtree) {
+            (Some(tree), _) => tree.nodes,
+            (None, Some(subtree)) => subtree.nodes,
+            (None, None) => 0,
+        };
+        let outcome = if succeeded(trace) {
+            "success"
+        } else {
+            "failure"
+        };
+        writeln!(
+            f,
+            r##"<tr><td><a href="#trace-{}">{}</a></td><td class="{}">{}</td><td><code>{}{}</code></td><td>{}</td></tr>"##,
+            i + 1,
+            escape_html(&trace.root.rule.name),
+            outcome,
+            outcome,
+            escape_html(&preview),
+            if more { "…" } else { "" },
+            thousands(nodes)
+        )?;
+    }
+    writeln!(f, "</table></nav>")?;
+    Ok(())
+}
+
+/// Starts the section of a test's traces, when reading `cargo test` output.
+fn write_test_heading(f: &mut dyn Write, test: &libtest::TestCase) -> Result<(), Box<dyn Error>> {
+    let outcome = match test.outcome.as_deref() {
+        Some(outcome) => format!(
+            r#" <span class="outcome {}">{}</span>"#,
+            outcome.split(',').next().unwrap().to_lowercase(),
+            escape_html(outcome)
+        ),
+        None => String::new(),
+    };
+    writeln!(
+        f,
+        r#"<h2 class="test-case">test <code>{}</code>{}</h2>"#,
+        escape_html(&test.name),
+        outcome
+    )?;
+    Ok(())
+}
+
+/// Heads a trace with how much of the input it consumed, and where it came
+/// from, when it was read from a file or the log was timestamped.
+fn write_source_label(f: &mut dyn Write, args: &Args, trace: &Trace) -> Result<(), Box<dyn Error>> {
+    let label = match (&trace.source, &trace.timestamp) {
+        (Some(source), Some(ts)) => format!("{} — {}", source, ts),
+        (Some(source), None) => source.clone(),
+        (None, Some(ts)) => ts.to_string(),
+        (None, None) => String::new(),
+    };
+    write!(
+        f,
+        r#"<div class="trace-source" title="input hash:{:016x}">"#,
+        trace.input_hash
+    )?;
+    write_outcome(f, trace)?;
+    write_consumption(f, args, trace)?;
+    if let Some(grammar) = &trace.grammar {
+        write!(
+            f,
+            r#"<span class="grammar">{}</span>"#,
+            escape_html(grammar)
+        )?;
+    }
+    write!(f, "{}", escape_html(&label))?;
+    if trace.chunks > 1 {
+        write!(
+            f,
+            r#"<span class="chunks">stitched from {} input chunks</span>"#,
+            trace.chunks
+        )?;
+    }
+    if !trace.duplicates.is_empty() {
+        let others: Vec<String> = trace
+            .duplicates
+            .iter()
+            .map(|i| format!("#{}", i + 1))
+            .collect();
+        write!(
+            f,
+            r#"<span class="dupes" title="identical traces: {}">seen {} times</span>"#,
+            others.join(", "),
+            trace.duplicates.len() + 1
+        )?;
+    }
+    writeln!(f, "</div>")?;
+    Ok(())
+}
+
+/// Byte offset where the trace stopped consuming input, and the input's
+/// length.
+fn consumption(trace: &Trace) -> (usize, usize) {
+    // the log adds a newline after the input, whether or not it had one
+    let len = trace.input.strip_suffix('\n').unwrap_or(&trace.input).len();
+    let stop = trace
+        .consumed
+        .map(|loc| loc.pos(&trace.input).min(len))
+        .unwrap_or(0);
+    (stop, len)
+}
+
+/// A trace succeeded if it consumed all of its input without the parser
+/// reporting an error.
+fn succeeded(trace: &Trace) -> bool {
+    let (stop, len) = consumption(trace);
+    trace.error.is_none() && stop == len
+}
+
+fn write_outcome(f: &mut dyn Write, trace: &Trace) -> Result<(), Box<dyn Error>> {
+    let expected = match trace.expected {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    let outcome = |success: bool| if success { "success" } else { "failure" };
+    if expected == succeeded(trace) {
+        write!(
+            f,
+            r#"<span class="outcome as-expected">expected {}</span>"#,
+            outcome(expected)
+        )?;
+    } else {
+        write!(
+            f,
+            r#"<span class="outcome unexpected">expected {}, got {}</span>"#,
+            outcome(expected),
+            outcome(!expected)
+        )?;
+    }
+    Ok(())
+}
+
+fn write_consumption(f: &mut dyn Write, args: &Args, trace: &Trace) -> Result<(), Box<dyn Error>> {
+    let (stop, len) = consumption(trace);
+    let input = &trace.input[..len];
+    let percent = if input.is_empty() {
+        100
+    } else {
+        stop * 100 / input.len()
+    };
+    let class = if stop == input.len() {
+        "full"
+    } else {
+        "partial"
+    };
+    write!(
+        f,
+        r#"<span class="consumed {}">consumed {}%"#,
+        class, percent
+    )?;
+    if stop < input.len() {
+        write!(f, " — stopped at {}", args.line_col(input, stop))?;
+    }
+    write!(f, "</span>")?;
+    Ok(())
+}
+
+/// Download buttons for the logs embedded with `--embed-log`, which are
+/// gzipped and base64-encoded in their `data-log` attribute.
+fn embedded_logs(logs: &[(String, String)]) -> String {
+    let mut res = String::new();
+    for (name, log) in logs {
+        let compressed = gzip::compress(log.as_bytes());
+        res.push_str(&format!(
+            r#"<button class="download-log" data-name="{}" data-log="{}" title="{} KB, {} KB compressed">Download {}</button>"#,
+            escape_html(name),
+            gzip::base64(&compressed),
+            log.len() / 1024,
+            compressed.len() / 1024,
+            escape_html(name),
+        ));
+    }
+    res
+}
+
+fn escape_html(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+fn visit(
+    f: &mut dyn Write,
+    args: &Args,
+    trace: &Trace,
+    node: &Node,
+    chunks: Option<&chunks::Chunks>,
+) -> Result<(), Box<dyn Error>> {
+    if args.should_flatten(trace, node, node.children.len()) {
+        write_notes(f, trace, &node.notes, 0..usize::MAX)?;
+        return visit(f, args, trace, &node.children[0], chunks);
+    }
+
+    write_summary(f, args, trace, node)?;
+    let lazy = lazy_children(args, node, !node.children.is_empty());
+    if lazy {
+        write!(f, r#"<template class="children">"#)?;
+    }
+    // with `--chunked`, subtrees small enough for a chunk go in one, the
+    // bigger ones are written here, with their children chunked in turn
+    let mut run = chunks::Run::default();
+    for (i, child) in node.children.iter().enumerate() {
+        let nodes = 1 + child.subtree.map_or(0, |subtree| subtree.nodes);
+        let f: &mut dyn Write = match chunks {
+            Some(chunks) if nodes <= chunks::NODES => {
+                chunks.add(f, &mut run, nodes)?;
+                &mut run.html
+            }
+            Some(chunks) => {
+                chunks.flush(f, &mut run)?;
+                &mut *f
+            }
+            None => &mut *f,
+        };
+        write_notes(f, trace, &node.notes, i..i + 1)?;
+        if args.should_hide(trace, child) {
+            continue;
+        }
+        let chunks = chunks.filter(|_| nodes > chunks::NODES);
+        visit(f, args, trace, child, chunks)?;
+    }
+    if let Some(chunks) = chunks {
+        chunks.flush(f, &mut run)?;
+    }
+    write_notes(f, trace, &node.notes, node.children.len()..usize::MAX)?;
+    if lazy {
+        write!(f, "</template>")?;
+    }
+    writeln!(f, "</details>")?;
+
+    Ok(())
+}
+
+/// Writes the notes logged before a child whose index is in `before`.
+fn write_notes(
+    f: &mut dyn Write,
+    trace: &Trace,
+    notes: &[Note],
+    before: std::ops::Range<usize>,
+) -> Result<(), Box<dyn Error>> {
+    for note in notes.iter().filter(|note| before.contains(&note.before)) {
+        writeln!(
+            f,
+            r#"<div class="peg-note" title="line {} of {}">{}</div>"#,
+            note.log_line,
+            escape_html(trace.source.as_deref().unwrap_or("stdin")),
+            escape_html(&note.text)
+        )?;
+    }
+    Ok(())
+}
+
+/// What a node's snippet says when hovered: where the rule started and
+/// ended, as line:column and byte offset both, and how much it matched.
+fn snippet_title(args: &Args, trace: &Trace, node: &Node) -> Option<String> {
+    let rule = &node.rule;
+    // the root node of a trace doesn't have a real position
+    if rule.loc == Location::ROOT {
+        return None;
+    }
+    let input = &trace.input;
+    let at = |pos: usize| format!("{} (byte {})", args.line_col(input, pos), args.offset(pos));
+    let start = rule.loc.pos(input);
+    Some(match rule.next_loc.map(|loc| loc.pos(input)) {
+        None => format!("at {}", at(start)),
+        Some(end) if end < start => format!("from {} back to {}", at(start), at(end)),
+        Some(end) => {
+            let bytes = end - start;
+            let chars = input[start..end].chars().count();
+            format!(
+                "from {} to {}, {} byte{}{}",
+                at(start),
+                at(end),
+                bytes,
+                if bytes == 1 { "" } else { "s" },
+                if chars != bytes && trace.binary.is_none() {
+                    format!(" ({} chars)", chars)
+                } else {
+                    String::new()
+                }
+            )
+        }
+    })
+}
+
+/// Whether `node` is expanded when the report opens: static reports can't
+/// be expanded, everything is.
+fn starts_open(args: &Args, node: &Node) -> bool {
+    args.static_html || args.dim_failures && matches!(node.state, State::Success)
+}
+
+/// With `--lazy`, whether the children of `node` go in a template, which
+/// the script only builds into the page once the node is expanded.
+pub(crate) fn lazy_children(args: &Args, node: &Node, has_children: bool) -> bool {
+    args.lazy && has_children && !starts_open(args, node)
+}
+
+/// Opens the `<details>` for `node` and writes its `<summary>`, the caller
+/// is responsible for the children and closing tag.
+fn write_summary(
+    f: &mut dyn Write,
+    args: &Args,
+    trace: &Trace,
+    node: &Node,
+) -> Result<(), Box<dyn Error>> {
     let rule = &node.rule;
+    let input = &trace.input;
 
+    // with `--dim-failures`, the successful parse path is laid out, and the
+    // failures around it are there but out of the way
+    let mut attrs = String::new();
+    if args.dim_failures && matches!(node.state, State::Failure) && !is_at_error(trace, node) {
+        attrs.push_str(r#" class="dimmed""#);
+    }
+    if starts_open(args, node) {
+        attrs.push_str(" open");
+    }
+    // byte offsets of what the node matched and its locations as logged,
+    // for the source panel and exports, see `nodeJson`
+    if rule.loc != Location::ROOT {
+        attrs.push_str(&format!(
+            r#" data-start="{}" data-loc="{}""#,
+            rule.loc.pos(input),
+            rule.loc
+        ));
+        if let Some(next_loc) = rule.next_loc {
+            attrs.push_str(&format!(
+                r#" data-end="{}" data-next-loc="{}""#,
+                next_loc.pos(input),
+                next_loc
+            ));
+        }
+    }
     write!(
         f,
         r#"
-    <details>
+    <details id="node-{id}"{attrs}>
         <summary>
-        <span class="rule {class} {class2}">{name}</span>
-        <code>"#,
+        <span class="rule {class} {class2}{class3}"{partial}>{name}</span>"#,
+        id = node.id,
+        attrs = attrs,
         class = match node.state {
             State::Success => "success",
             State::Failure => "failure",
@@ -440,63 +2446,180 @@ fn visit(f: &mut dyn Write, args: &Args, node: &Node, input: &str) -> Result<(),
         } else {
             ""
         },
-        name = rule.name
+        class3 = if is_at_error(trace, node) {
+            " at-error"
+        } else {
+            ""
+        },
+        // spelled out, for those who can't tell the colors apart
+        partial = if node.partial_match && matches!(node.state, State::Failure) {
+            format!(
+                r#" title="partial match: matched some input, but then failed and the parser backtracked" aria-label="{}, partial match""#,
+                escape_html(&rule.name)
+            )
+        } else {
+            String::new()
+        },
+        name = escape_html(&rule.name)
     )?;
+    match node.growth {
+        Growth::None => {}
+        Growth::Iteration(1) => write!(f, r#"<span class="growth">seed</span>"#)?,
+        Growth::Iteration(i) => write!(f, r#"<span class="growth">iteration {}</span>"#, i)?,
+        Growth::Loop => write!(
+            f,
+            r#"<span class="growth">left recursion, {} iterations</span>"#,
+            node.children.len()
+        )?,
+    }
+    match snippet_title(args, trace, node) {
+        Some(title) => write!(f, r#"<code title="{}">"#, escape_html(&title))?,
+        None => write!(f, "<code>")?,
+    }
 
-    let before = 10;
-    let after = 25;
+    let (before, after) = args.context(trace, node);
+    let rulepos = rule.loc.pos(input);
+    // binary inputs show their bytes, `input` has one char for each
+    let snippet = |start: usize, end: usize| match &trace.binary {
+        Some(bytes) if start < end => hexdump::hex(&bytes[start..end]) + " ",
+        Some(_) => String::new(),
+        None => match &trace.highlight {
+            Some(highlight) => highlight.html(input, start, end),
+            None => escape_html(&input[start..end]),
+        },
+    };
     write!(
         f,
         r#"<em>{}</em>"#,
-        &input[if rule.loc.pos(input) < before {
-            0
-        } else {
-            rule.loc.pos(input) - before
-        }..rule.loc.pos(input)]
+        snippet(chars_before(input, rulepos, before), rulepos)
     )?;
-    let rulepos = rule.loc.pos(input);
-    if let Some(next_loc) = rule.next_loc.as_ref() {
-        let nextpos = next_loc.pos(input);
-        match nextpos.cmp(&rulepos) {
-            Ordering::Greater => {
-                write!(f, r#"<strong>{}</strong>"#, &input[rulepos..nextpos])?;
-            }
-            Ordering::Less => {
-                write!(f, r#"↩"#)?;
+    let restpos = match rule.next_loc.as_ref() {
+        Some(next_loc) => {
+            let nextpos = next_loc.pos(input);
+            match nextpos.cmp(&rulepos) {
+                Ordering::Greater => {
+                    write!(f, r#"<strong>{}</strong>"#, snippet(rulepos, nextpos))?;
+                }
+                Ordering::Less => {
+                    write!(f, r#"↩"#)?;
+                }
+                Ordering::Equal => {}
             }
-            Ordering::Equal => {}
+            nextpos
         }
+        None => rulepos,
+    };
+    let restend = chars_after(input, restpos, after);
+    write!(
+        f,
+        r#"<span>{}{}</span>"#,
+        snippet(restpos, restend),
+        if input.len() > restend { "…" } else { "" }
+    )?;
+
+    write!(f, "</code>")?;
+    // binary inputs show a hex dump, not the bytes that were matched
+    if restpos > rulepos && !args.static_html && trace.binary.is_none() {
+        write!(
+            f,
+            r#"<button type="button" class="copy" title="copy the text this node matched">⧉</button>"#
+        )?;
+    }
+    if restpos > rulepos && !trace.tokens.is_empty() {
+        let matched = tokens::within(&trace.tokens, rulepos, restpos);
+        let names: Vec<&str> = matched.iter().take(8).map(|t| t.name.as_str()).collect();
         write!(
             f,
-            r#"<span>{}{}</span>"#,
-            &input[nextpos..std::cmp::min(nextpos + after, input.len())],
-            if input.len() > nextpos + after {
-                "…"
+            r#"<span class="tokens" title="{} token{}">{}{}</span>"#,
+            matched.len(),
+            if matched.len() == 1 { "" } else { "s" },
+            escape_html(&names.join(" ")),
+            if matched.len() > names.len() {
+                " …"
             } else {
                 ""
             }
         )?;
-    } else {
+    }
+    // the root node of a trace doesn't have a real position
+    if rule.loc != Location::ROOT {
+        let (line_col, offsets) = match rule.next_loc.as_ref() {
+            Some(_) => (
+                format!(
+                    "{}–{}",
+                    args.line_col(input, rulepos),
+                    args.line_col(input, restpos)
+                ),
+                format!("{}..{}", args.offset(rulepos), args.offset(restpos)),
+            ),
+            None => (
+                args.line_col(input, rulepos).to_string(),
+                format!("@{}", args.offset(rulepos)),
+            ),
+        };
         write!(
             f,
-            r#"<span>{}{}</span>"#,
-            &input[rulepos..std::cmp::min(rulepos + after, input.len())],
-            if input.len() > rulepos + after {
-                "…"
+            r#"<span class="loc" title="line:column and byte offsets, click to switch"><span class="line-col">{}</span><span class="byte-offset">{}</span></span>"#,
+            line_col, offsets
+        )?;
+    }
+    if let (Some(net), true) = (node.net, rule.loc != Location::ROOT) {
+        let shown = restpos.saturating_sub(rulepos);
+        if net.backtracked > 0 || net.consumed != shown {
+            write!(
+                f,
+                r#"<span class="net" title="bytes consumed for good, and bytes matched by children the parser then went back on">net {} B{}</span>"#,
+                net.consumed,
+                if net.backtracked > 0 {
+                    format!(", {} B backtracked", net.backtracked)
+                } else {
+                    String::new()
+                }
+            )?;
+        }
+    }
+    if let Some(subtree) = node.subtree.filter(|subtree| subtree.nodes > 0) {
+        write!(
+            f,
+            r#"<span class="subtree" title="nodes under this one, and how many of them failed">{} node{}{}</span>"#,
+            thousands(subtree.nodes),
+            if subtree.nodes == 1 { "" } else { "s" },
+            match subtree.failures {
+                0 => String::new(),
+                1 => " / 1 failure".into(),
+                n => format!(" / {} failures", thousands(n)),
+            }
+        )?;
+    }
+    if node.log_line != 0 {
+        write!(
+            f,
+            r#"<span class="log-line{}" title="line {} of {}{}">L{}</span>"#,
+            if node.raw.is_empty() {
+                ""
             } else {
+                " raw-toggle"
+            },
+            node.log_line,
+            escape_html(trace.source.as_deref().unwrap_or("stdin")),
+            if node.raw.is_empty() {
                 ""
-            }
+            } else {
+                ", click to show the trace lines for this node"
+            },
+            node.log_line
         )?;
     }
-
-    writeln!(f, "</code></summary>")?;
-    for child in &node.children {
-        if args.should_hide(child) {
-            continue;
-        }
-        visit(f, args, child, input)?;
+    writeln!(f, "</summary>")?;
+    if !node.raw.is_empty() {
+        let width = node.raw.last().map_or(0, |(n, _)| n.to_string().len());
+        let lines: Vec<String> = node
+            .raw
+            .iter()
+            .map(|(n, line)| format!("{:>width$} │ {}", n, escape_html(line), width = width))
+            .collect();
+        writeln!(f, r#"<pre class="raw">{}</pre>"#, lines.join("\n"))?;
     }
-    writeln!(f, "</details>")?;
 
     Ok(())
 }
@@ -0,0 +1,160 @@
+//! Markdown output, see `--format markdown`, to paste traces into issues:
+//! the input in a code fence, then the tree as nested bullet lists.
+
+use crate::{input::Trace, Args, Node, Note, State};
+use std::{error::Error, io::Write};
+
+/// chars of input shown after each node
+const SNIPPET: usize = 24;
+
+pub(crate) fn write_markdown(
+    f: &mut dyn Write,
+    args: &Args,
+    traces: &[Trace],
+) -> Result<(), Box<dyn Error>> {
+    for (i, trace) in traces.iter().enumerate() {
+        if i > 0 {
+            writeln!(f)?;
+        }
+        match &trace.source {
+            Some(source) => writeln!(f, "### {} — {}", trace.root.rule.name, code(source))?,
+            None => writeln!(f, "### {}", trace.root.rule.name)?,
+        }
+        writeln!(f)?;
+        if let Some(error) = &trace.error {
+            writeln!(
+                f,
+                "**error at {}**: expected {}",
+                args.display_loc(&trace.input, error.loc),
+                code(&error.expected)
+            )?;
+            writeln!(f)?;
+        }
+        let fence = fence(&trace.input);
+        writeln!(f, "{}text", fence)?;
+        writeln!(f, "{}", trace.input.trim_end_matches('\n'))?;
+        writeln!(f, "{}", fence)?;
+        writeln!(f)?;
+        let notes = &trace.root.notes;
+        match &trace.spilled {
+            Some(tree) => write_list(f, args, trace, &tree.load_all()?, notes, 0)?,
+            None => write_list(f, args, trace, &trace.root.children, notes, 0)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_list(
+    f: &mut dyn Write,
+    args: &Args,
+    trace: &Trace,
+    children: &[Node],
+    // the parent's
+    notes: &[Note],
+    depth: usize,
+) -> Result<(), Box<dyn Error>> {
+    let indent = "  ".repeat(depth);
+    for (i, child) in children.iter().enumerate() {
+        for note in notes.iter().filter(|note| note.before == i) {
+            writeln!(f, "{}- _{}_", indent, escape(&note.text))?;
+        }
+        if args.should_hide(trace, child) {
+            continue;
+        }
+        // a flattened node is replaced by its only child
+        let mut shown = child;
+        while args.should_flatten(trace, shown, shown.children.len()) {
+            shown = &shown.children[0];
+        }
+        writeln!(f, "{}- {}", indent, item(args, trace, shown))?;
+        write_list(f, args, trace, &shown.children, &shown.notes, depth + 1)?;
+    }
+    for note in notes.iter().filter(|note| note.before >= children.len()) {
+        writeln!(f, "{}- _{}_", indent, escape(&note.text))?;
+    }
+    Ok(())
+}
+
+fn item(args: &Args, trace: &Trace, node: &Node) -> String {
+    let input = &trace.input;
+    let start = node.rule.loc.pos(input);
+    let mark = match node.state {
+        State::Success => "✅",
+        State::Failure if node.partial_match => "🟡",
+        State::Failure => "❌",
+        State::Unknown => "❔",
+    };
+    let (positions, text) = match (&node.state, node.rule.next_loc) {
+        (State::Success, Some(next)) => {
+            let end = next.pos(input).max(start);
+            (
+                format!(
+                    "{}–{}",
+                    args.display_loc(input, node.rule.loc),
+                    args.display_loc(input, next)
+                ),
+                &input[start..end],
+            )
+        }
+        _ => (
+            args.display_loc(input, node.rule.loc).to_string(),
+            &input[start..],
+        ),
+    };
+    let mut snippet: String = text.chars().take(SNIPPET).collect();
+    let more = snippet.len() < text.len();
+    snippet = code(&format!("{:?}", snippet));
+    if more {
+        snippet.push('…');
+    }
+    let partial = if node.partial_match {
+        " (partial match)"
+    } else {
+        ""
+    };
+    format!(
+        "{} {} {}{} {}",
+        mark,
+        code(&node.rule.name),
+        positions,
+        partial,
+        snippet
+    )
+}
+
+/// `text` as inline code, with enough backticks around it that the ones
+/// inside don't end it.
+fn code(text: &str) -> String {
+    let ticks = "`".repeat(longest_run(text, '`') + 1);
+    if text.starts_with('`') || text.ends_with('`') {
+        format!("{} {} {}", ticks, text, ticks)
+    } else {
+        format!("{}{}{}", ticks, text, ticks)
+    }
+}
+
+/// A code fence longer than any run of backticks in `text`.
+fn fence(text: &str) -> String {
+    "`".repeat(longest_run(text, '`').max(2) + 1)
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    let (mut longest, mut run) = (0, 0);
+    for t in text.chars() {
+        run = if t == c { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    longest
+}
+
+/// Escapes what would be taken for formatting in notes.
+fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>#|".contains(c) {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
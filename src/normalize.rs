@@ -0,0 +1,74 @@
+//! Unicode normalization of the captured input. When the parser ran over
+//! normalized text but the log captured it as-is (or the other way around),
+//! every column after the first decomposed char is off, which skews snippets.
+
+use std::{fmt, str::FromStr};
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Normalization {
+    None,
+    Nfc,
+    Nfd,
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Normalization::None),
+            "nfc" => Ok(Normalization::Nfc),
+            "nfd" => Ok(Normalization::Nfd),
+            _ => Err(format!(
+                "unknown normalization {:?}, expected one of: nfc, nfd, none",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Normalization::None => "none",
+            Normalization::Nfc => "nfc",
+            Normalization::Nfd => "nfd",
+        })
+    }
+}
+
+impl Normalization {
+    /// Normalizes `s` in place.
+    pub(crate) fn apply(self, s: &mut String) {
+        match self {
+            Normalization::None => {}
+            Normalization::Nfc => *s = s.nfc().collect(),
+            Normalization::Nfd => *s = s.nfd().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let apply = |n: &str, s: &str| {
+            let mut s = s.to_string();
+            n.parse::<Normalization>().unwrap().apply(&mut s);
+            s
+        };
+        assert_eq!(
+            apply("nfc", "cafe\u{301} \u{1100}\u{1161}"),
+            "caf\u{e9} \u{ac00}"
+        );
+        assert_eq!(
+            apply("nfd", "caf\u{e9} \u{ac00}"),
+            "cafe\u{301} \u{1100}\u{1161}"
+        );
+        assert_eq!(apply("none", "cafe\u{301}"), "cafe\u{301}");
+        assert!("nfkc".parse::<Normalization>().is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Pretty-printing of the captured input for the input panel, see
+//! `--pretty-input`. Minified data is one enormous line, which makes finding
+//! anything in it hopeless. Only the panel shows the pretty version: nodes
+//! keep pointing into the original input, and a map translates positions
+//! from one to the other.
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PrettyInput {
+    /// always format as JSON
+    Json,
+    /// format as JSON when the input looks like minified JSON
+    Auto,
+}
+
+impl FromStr for PrettyInput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(PrettyInput::Json),
+            "auto" => Ok(PrettyInput::Auto),
+            _ => Err(format!(
+                "unknown input format {:?}, expected one of: json, auto",
+                s
+            )),
+        }
+    }
+}
+
+pub(crate) struct Pretty {
+    pub(crate) text: String,
+    /// for each byte offset of the original input (and its end), the
+    /// matching byte offset in `text`
+    map: Vec<usize>,
+}
+
+impl Pretty {
+    /// Where byte offset `pos` of the original input ended up.
+    pub(crate) fn pos(&self, pos: usize) -> usize {
+        self.map[pos.min(self.map.len() - 1)]
+    }
+}
+
+impl PrettyInput {
+    /// The pretty version of `input`, if there should be one.
+    pub(crate) fn apply(self, input: &str) -> Option<Pretty> {
+        match self {
+            PrettyInput::Json => Some(json(input)),
+            PrettyInput::Auto if looks_minified(input) => Some(json(input)),
+            PrettyInput::Auto => None,
+        }
+    }
+}
+
+/// Starts like JSON and fits on a single long line.
+fn looks_minified(input: &str) -> bool {
+    let input = input.trim();
+    input.len() > 80 && (input.starts_with('{') || input.starts_with('[')) && !input.contains('\n')
+}
+
+/// Re-indents JSON, dropping the whitespace that was there. This doesn't
+/// validate anything: the input may well be invalid (that's often why it's
+/// being looked at), so whatever isn't understood is copied as-is.
+fn json(input: &str) -> Pretty {
+    let mut text = String::with_capacity(input.len() * 2);
+    let mut map = Vec::with_capacity(input.len() + 1);
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    let newline = |text: &mut String, depth: usize| {
+        text.push('\n');
+        text.push_str(&"  ".repeat(depth));
+    };
+
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_string {
+            map.extend(std::iter::repeat_n(text.len(), c.len_utf8()));
+            text.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        if c.is_whitespace() {
+            // dropped, it maps to whatever comes next
+            map.extend(std::iter::repeat_n(text.len(), c.len_utf8()));
+            continue;
+        }
+        if c == '}' || c == ']' {
+            depth = depth.saturating_sub(1);
+            // keep `{}` and `[]` together
+            if !text.ends_with('{') && !text.ends_with('[') {
+                newline(&mut text, depth);
+            }
+        }
+        map.extend(std::iter::repeat_n(text.len(), c.len_utf8()));
+        text.push(c);
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => {
+                depth += 1;
+                while let Some(c) = chars.next_if(|c| c.is_whitespace()) {
+                    map.extend(std::iter::repeat_n(text.len(), c.len_utf8()));
+                }
+                if chars.peek() != Some(&'}') && chars.peek() != Some(&']') {
+                    newline(&mut text, depth);
+                }
+            }
+            ',' => newline(&mut text, depth),
+            ':' => text.push(' '),
+            _ => {}
+        }
+    }
+    map.push(text.len());
+    Pretty { text, map }
+}
@@ -0,0 +1,194 @@
+//! What a report was generated from, and how, so a report found long after
+//! the fact can be traced back to its logs and regenerated.
+
+use crate::{
+    escape_html,
+    expect::{fnv1a, FNV_OFFSET},
+    input::Source,
+    timestamp::Timestamp,
+};
+use std::{
+    cell::Cell,
+    error::Error,
+    fs::File,
+    io::{self, Read, Write},
+    rc::Rc,
+};
+
+pub(crate) struct RunInfo {
+    pub(crate) version: &'static str,
+    pub(crate) command_line: String,
+    /// name and FNV-1a hash of each input, stdin isn't hashed. Logs from an
+    /// earlier report are hashed as they were embedded
+    pub(crate) inputs: Vec<(String, Option<u64>)>,
+    /// left out of `--static` reports, so that they only change along with
+    /// their logs
+    pub(crate) generated: Option<Timestamp>,
+}
+
+impl RunInfo {
+    /// `hashes` are those `read_traces` returned for the first sources, the
+    /// others weren't read (see `--first`) and get hashed here.
+    pub(crate) fn collect(sources: &[Source], hashes: &[Option<u64>]) -> io::Result<RunInfo> {
+        let mut inputs = vec![];
+        for (i, source) in sources.iter().enumerate() {
+            inputs.push(match source {
+                Source::Stdin => ("stdin".to_string(), None),
+                Source::File(path) => (
+                    path.display().to_string(),
+                    match hashes.get(i).copied().flatten() {
+                        Some(hash) => Some(hash),
+                        None => Some(hash_file(File::open(path)?)?),
+                    },
+                ),
+                Source::Embedded { log, .. } => (
+                    source.label().unwrap(),
+                    Some(crate::expect::input_hash(log)),
+                ),
+            });
+        }
+        Ok(RunInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            command_line: std::env::args()
+                .map(|arg| quote(&arg))
+                .collect::<Vec<_>>()
+                .join(" "),
+            inputs,
+            generated: Some(Timestamp::now()),
+        })
+    }
+
+    pub(crate) fn write_html(&self, f: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+        write!(
+            f,
+            r#"<details class="run-info"><summary>pegviz {}"#,
+            self.version
+        )?;
+        if let Some(generated) = self.generated {
+            write!(f, ", generated {}", generated)?;
+        }
+        writeln!(
+            f,
+            r#"</summary><dl>
+<dt>command line</dt><dd><code>{}</code></dd>
+<dt>inputs</dt><dd><ul>"#,
+            escape_html(&self.command_line)
+        )?;
+        for (name, hash) in &self.inputs {
+            match hash {
+                Some(hash) => writeln!(
+                    f,
+                    "<li><code>{}</code> (fnv1a:{:016x})</li>",
+                    escape_html(name),
+                    hash
+                )?,
+                None => writeln!(f, "<li><code>{}</code></li>", escape_html(name))?,
+            }
+        }
+        writeln!(f, "</ul></dd></dl></details>")?;
+        Ok(())
+    }
+}
+
+fn hash_file(file: File) -> io::Result<u64> {
+    let hash = Rc::new(Cell::new(FNV_OFFSET));
+    io::copy(&mut Hashing::new(file, hash.clone()), &mut io::sink())?;
+    Ok(hash.get())
+}
+
+/// Hashes what's read through it into `hash`, so that inputs are hashed as
+/// they're read rather than read twice.
+pub(crate) struct Hashing<R> {
+    inner: R,
+    hash: Rc<Cell<u64>>,
+}
+
+impl<R> Hashing<R> {
+    /// `hash` starts out as the hash of nothing.
+    pub(crate) fn new(inner: R, hash: Rc<Cell<u64>>) -> Self {
+        hash.set(FNV_OFFSET);
+        Hashing { inner, hash }
+    }
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hash.set(fnv1a(self.hash.get(), &buf[..n]));
+        Ok(n)
+    }
+}
+
+/// Quotes `arg` for a POSIX shell, when needed, so the command line can be
+/// pasted back.
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gen::{generate, GenArgs},
+        gzip,
+        input::{self, tests::TestDir, Selection},
+        Args,
+    };
+
+    #[test]
+    fn inputs_are_hashed_whole_while_read() {
+        let dir = TestDir::new("inputs_are_hashed_whole_while_read");
+        let log = generate(&GenArgs {
+            nodes: 60,
+            depth: 6,
+            seed: 1,
+            traces: 5,
+            malformed: false,
+        });
+        let plain = dir.join("trace.log");
+        std::fs::write(&plain, &log).unwrap();
+        let gzipped = dir.join("trace.log.gz");
+        std::fs::write(&gzipped, gzip::compress(log.as_bytes())).unwrap();
+
+        // `--first` stops reading traces well before the end of the log
+        for extra in &[&[][..], &["--first", "1"][..]] {
+            let mut all_args = vec!["-o", "unused.html"];
+            all_args.extend(*extra);
+            let args: Args = argh::FromArgs::from_args(&["pegviz"], &all_args).unwrap();
+            for path in &[&plain, &gzipped] {
+                let source = Source::File(path.to_path_buf());
+                let hash = input::read_traces(
+                    &source,
+                    &args,
+                    &mut Selection::new(&args),
+                    &mut vec![],
+                    None,
+                )
+                .unwrap();
+                let expected = hash_file(File::open(path).unwrap()).unwrap();
+                assert_eq!(hash, Some(expected), "{} {:?}", path.display(), extra);
+            }
+        }
+    }
+
+    #[test]
+    fn static_reports_leave_out_the_time() {
+        let mut run_info = RunInfo::collect(&[Source::Stdin], &[None]).unwrap();
+        let mut html = vec![];
+        run_info.write_html(&mut html).unwrap();
+        assert!(String::from_utf8(html).unwrap().contains(", generated "));
+
+        run_info.generated = None;
+        let mut html = vec![];
+        run_info.write_html(&mut html).unwrap();
+        assert!(!String::from_utf8(html).unwrap().contains("generated"));
+    }
+}
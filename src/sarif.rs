@@ -0,0 +1,181 @@
+//! SARIF export of failed traces, see `--sarif`, for GitHub's and GitLab's
+//! code scanning to annotate test inputs that stopped parsing.
+//!
+//! Logs don't say which file an input was read from, so the files given
+//! with `--sarif-inputs` are matched to traces by content. Traces whose
+//! input isn't found point at the log instead.
+
+use crate::{
+    consumption, expect::input_hash, farthest_failure, input::Trace, json, line_col, succeeded,
+    Args, Location,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Checked-in inputs, by the hash of their contents.
+pub(crate) struct InputFiles {
+    by_hash: HashMap<u64, PathBuf>,
+}
+
+impl InputFiles {
+    pub(crate) fn scan(dirs: &[PathBuf]) -> io::Result<InputFiles> {
+        let mut files = InputFiles {
+            by_hash: HashMap::new(),
+        };
+        for dir in dirs {
+            files.scan_dir(dir)?;
+        }
+        Ok(files)
+    }
+
+    fn scan_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                self.scan_dir(&path)?;
+            } else if let Ok(text) = fs::read_to_string(&path) {
+                self.by_hash.entry(key(&text)).or_insert(path);
+            }
+        }
+        Ok(())
+    }
+
+    fn find(&self, trace: &Trace) -> Option<&Path> {
+        self.by_hash.get(&key(&trace.input)).map(PathBuf::as_path)
+    }
+}
+
+/// The log adds a newline after the input whether or not the file ended
+/// with one, so trailing newlines don't count.
+fn key(text: &str) -> u64 {
+    input_hash(text.trim_end_matches('\n'))
+}
+
+/// Writes a SARIF log with one result per failed trace, returns how many
+/// there were. Traces `--expect`ed to fail are left out.
+pub(crate) fn write_sarif(
+    f: &mut dyn Write,
+    args: &Args,
+    traces: &[Trace],
+    files: &InputFiles,
+) -> Result<usize, Box<dyn Error>> {
+    let mut results = vec![];
+    for trace in traces {
+        if succeeded(trace) || trace.expected == Some(false) {
+            continue;
+        }
+        results.push(result(args, trace, files)?);
+    }
+
+    writeln!(
+        f,
+        r#"{{
+  "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+  "version": "2.1.0",
+  "runs": [{{
+    "tool": {{ "driver": {{
+      "name": "pegviz",
+      "version": {},
+      "informationUri": "https://github.com/fasterthanlime/pegviz",
+      "rules": [{{ "id": "parse-failure", "shortDescription": {{ "text": "Input doesn't parse" }} }}]
+    }} }},
+    "columnKind": "unicodeCodePoints",
+    "results": [{}
+    ]
+  }}]
+}}"#,
+        json::string(env!("CARGO_PKG_VERSION")),
+        results.join(",")
+    )?;
+    Ok(results.len())
+}
+
+fn result(args: &Args, trace: &Trace, files: &InputFiles) -> Result<String, Box<dyn Error>> {
+    let pos = match farthest_failure(trace)? {
+        Some(pos) => pos,
+        // nothing failed, but the input wasn't consumed to the end
+        None => consumption(trace).0,
+    };
+    let mut message = match &trace.grammar {
+        Some(grammar) => format!("{} ({})", trace.root.rule.name, grammar),
+        None => trace.root.rule.name.clone(),
+    };
+    let location = match (files.find(trace), &trace.source) {
+        (Some(path), _) => {
+            let (line, column) = match line_col(&trace.input, pos) {
+                Location::LineCol { line, column } => (line, column),
+                Location::Offset(_) => unreachable!(),
+            };
+            message.push_str(&format!(" stops parsing at {}:{}", line, column));
+            Some((path.to_path_buf(), line, column))
+        }
+        (None, source) => {
+            message.push_str(&format!(
+                " stops parsing at {}",
+                args.line_col(&trace.input, pos)
+            ));
+            if !files.by_hash.is_empty() {
+                message.push_str(", its input wasn't found in --sarif-inputs");
+            }
+            // the best we can do is the trace in the log
+            source
+                .as_ref()
+                .map(|source| (PathBuf::from(source), trace.root.log_line, 1))
+        }
+    };
+    if let Some(error) = &trace.error {
+        message.push_str(&format!(": expected {}", error.expected));
+    }
+
+    let locations = match location {
+        Some((path, line, column)) => format!(
+            r#",
+        "locations": [{{ "physicalLocation": {{
+          "artifactLocation": {{ "uri": {} }},
+          "region": {{ "startLine": {}, "startColumn": {} }}
+        }} }}]"#,
+            json::string(&uri(&path)),
+            line.max(1),
+            column.max(1)
+        ),
+        None => String::new(),
+    };
+    Ok(format!(
+        r#"
+      {{
+        "ruleId": "parse-failure",
+        "level": "error",
+        "message": {{ "text": {} }}{}
+      }}"#,
+        json::string(&message),
+        locations
+    ))
+}
+
+/// A URI reference for `path`, relative when the path is, which code
+/// scanning resolves against the repository's root.
+fn uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let path = path.strip_prefix("./").unwrap_or(&path);
+    let mut res = String::new();
+    if path.starts_with('/') {
+        res.push_str("file://");
+    }
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                res.push(b as char)
+            }
+            _ => res.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    res
+}
@@ -0,0 +1,496 @@
+//! Disk-backed storage for the nodes of traces too large to keep in memory,
+//! see `--spill`.
+//!
+//! Nodes are appended to a temporary file as they complete, so a node's
+//! record always comes right after the records of its whole subtree
+//! (post-order). Each record ends with its own length, which lets readers
+//! walk a node's children backwards from the node itself, and starts with
+//! the offset where its subtree begins.
+
+use crate::{
+    chunks::{self, Chunks, Run},
+    input::Trace,
+    lazy_children, write_notes, write_summary, Args, Growth, Location, Node, Note, Rule, State,
+};
+use std::{
+    cell::RefCell,
+    convert::TryInto,
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+/// Bookkeeping for a node that's still being parsed, while its children get
+/// spilled.
+pub(crate) struct Frame {
+    subtree_start: u64,
+    /// number of nodes in the subtree so far, not counting the node itself
+    descendants: usize,
+    /// number of children so far
+    children: usize,
+    partial_child: bool,
+}
+
+/// A store that's being written to, while parsing a trace.
+pub(crate) struct NodeStore {
+    file: BufWriter<File>,
+    len: u64,
+    // dropped last, so the file is closed before it's removed
+    path: TempPath,
+}
+
+impl Frame {
+    pub(crate) fn children(&self) -> usize {
+        self.children
+    }
+}
+
+impl NodeStore {
+    pub(crate) fn create() -> io::Result<Self> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "pegviz-{}-{}.nodes",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(NodeStore {
+            file: BufWriter::new(file),
+            len: 0,
+            path: TempPath(path),
+        })
+    }
+
+    /// Starts the frame of a node whose children are about to be spilled.
+    pub(crate) fn open_frame(&self) -> Frame {
+        Frame {
+            subtree_start: self.len,
+            descendants: 0,
+            children: 0,
+            partial_child: false,
+        }
+    }
+
+    /// Writes a completed node, whose children have all been written
+    /// already, and accounts for it in its parent's frame. This is where
+    /// partial matches are determined, as children are no longer around
+    /// afterwards.
+    pub(crate) fn push(
+        &mut self,
+        node: &mut Node,
+        frame: Frame,
+        parent: &mut Frame,
+    ) -> io::Result<()> {
+        node.partial_match = (matches!(node.state, State::Success) && !node.rule.is_zero_len())
+            || frame.partial_child;
+
+        let mut rec = vec![];
+        put_u64(&mut rec, frame.subtree_start);
+        put_u64(&mut rec, frame.descendants as u64);
+        rec.push(match node.state {
+            State::Success => 0,
+            State::Failure => 1,
+            State::Unknown => 2,
+        });
+        rec.push(node.partial_match as u8);
+        put_u64(&mut rec, node.log_line as u64);
+        put_loc(&mut rec, Some(node.rule.loc));
+        put_loc(&mut rec, node.rule.next_loc);
+        put_str(&mut rec, &node.rule.name);
+        put_u64(&mut rec, node.raw.len() as u64);
+        for (log_line, raw) in &node.raw {
+            put_u64(&mut rec, *log_line as u64);
+            put_str(&mut rec, raw);
+        }
+        put_u64(&mut rec, node.notes.len() as u64);
+        for note in &node.notes {
+            put_u64(&mut rec, note.before as u64);
+            put_u64(&mut rec, note.log_line as u64);
+            put_str(&mut rec, &note.text);
+        }
+        let len = rec.len() as u64 + 8;
+        put_u64(&mut rec, len);
+
+        self.file.write_all(&rec)?;
+        self.len += len;
+
+        parent.descendants += frame.descendants + 1;
+        parent.children += 1;
+        parent.partial_child |= node.partial_match;
+        Ok(())
+    }
+
+    /// Done writing: the store can now be read from. `frame` is the root's.
+    pub(crate) fn finish(self, root: &mut Node, frame: Frame) -> io::Result<SpilledTree> {
+        root.partial_match = frame.partial_child;
+        let NodeStore { file, len, path } = self;
+        let file = file.into_inner().map_err(|e| e.into_error())?;
+        Ok(SpilledTree {
+            file: RefCell::new(file),
+            len,
+            nodes: frame.descendants,
+            _path: path,
+        })
+    }
+}
+
+/// The children of a trace's root, stored on disk.
+pub(crate) struct SpilledTree {
+    // dropped last, so the file is closed before it's removed
+    file: RefCell<File>,
+    len: u64,
+    /// total number of spilled nodes
+    pub(crate) nodes: usize,
+    _path: TempPath,
+}
+
+/// The path of a store's file, which is removed when the store is dropped,
+/// whether it got to be read from or not (errors, duplicate traces...).
+struct TempPath(PathBuf);
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+/// A node loaded back from the store, without its children.
+pub(crate) struct Loaded {
+    pub(crate) node: Node,
+    /// offset of the first record of this node's subtree
+    subtree_start: u64,
+    /// offset of this node's own record
+    offset: u64,
+    /// number of nodes in the subtree, not counting this node
+    pub(crate) descendants: usize,
+}
+
+impl SpilledTree {
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = vec![0; len];
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Loads the node whose record ends at `end`.
+    fn load_ending_at(&self, end: u64) -> io::Result<Loaded> {
+        let len = get_u64(&mut &self.read_at(end - 8, 8)?[..]);
+        let offset = end - len;
+        let rec = self.read_at(offset, len as usize)?;
+        let mut r = &rec[..];
+
+        let subtree_start = get_u64(&mut r);
+        let descendants = get_u64(&mut r) as usize;
+        let state = match take(&mut r, 1)[0] {
+            0 => State::Success,
+            1 => State::Failure,
+            _ => State::Unknown,
+        };
+        let partial_match = take(&mut r, 1)[0] != 0;
+        let log_line = get_u64(&mut r) as usize;
+        let loc = get_loc(&mut r).unwrap_or(Location::ROOT);
+        let next_loc = get_loc(&mut r);
+        let name = get_str(&mut r);
+        let raw = (0..get_u64(&mut r))
+            .map(|_| (get_u64(&mut r) as usize, get_str(&mut r)))
+            .collect();
+        let notes = (0..get_u64(&mut r))
+            .map(|_| Note {
+                before: get_u64(&mut r) as usize,
+                log_line: get_u64(&mut r) as usize,
+                text: get_str(&mut r),
+            })
+            .collect();
+
+        Ok(Loaded {
+            node: Node {
+                rule: Rule {
+                    name,
+                    loc,
+                    next_loc,
+                },
+                partial_match,
+                state,
+                children: vec![],
+                raw,
+                growth: Growth::None,
+                id: 0,
+                log_line,
+                net: None,
+                subtree: None,
+                notes,
+            },
+            subtree_start,
+            offset,
+            descendants,
+        })
+    }
+
+    /// Loads the nodes whose records are in the `start..end` region, which
+    /// must exactly hold a sequence of sibling subtrees.
+    fn siblings(&self, start: u64, end: u64) -> io::Result<Vec<Loaded>> {
+        let mut res = vec![];
+        let mut end = end;
+        while end > start {
+            let loaded = self.load_ending_at(end)?;
+            end = loaded.subtree_start;
+            res.push(loaded);
+        }
+        res.reverse();
+        Ok(res)
+    }
+
+    /// The children of the trace's root.
+    pub(crate) fn top_level(&self) -> io::Result<Vec<Loaded>> {
+        self.siblings(0, self.len)
+    }
+
+    pub(crate) fn children(&self, parent: &Loaded) -> io::Result<Vec<Loaded>> {
+        self.siblings(parent.subtree_start, parent.offset)
+    }
+
+    /// Reads all the spilled nodes back into memory, for outputs that need
+    /// the whole tree at once.
+    pub(crate) fn load_all(&self) -> io::Result<Vec<Node>> {
+        fn load(tree: &SpilledTree, loaded: Loaded) -> io::Result<Node> {
+            let children = tree.children(&loaded)?;
+            let mut node = loaded.node;
+            for child in children {
+                node.children.push(load(tree, child)?);
+            }
+            Ok(node)
+        }
+
+        self.top_level()?
+            .into_iter()
+            .map(|loaded| load(self, loaded))
+            .collect()
+    }
+
+    /// Calls `f` for every spilled node in pre-order, along with its id
+    /// (ids start at `first_id`) and depth (1 for the root's children).
+    pub(crate) fn walk(
+        &self,
+        first_id: usize,
+        f: &mut dyn FnMut(&Node, usize, usize),
+    ) -> io::Result<()> {
+        fn walk_siblings(
+            tree: &SpilledTree,
+            siblings: Vec<Loaded>,
+            next_id: &mut usize,
+            depth: usize,
+            f: &mut dyn FnMut(&Node, usize, usize),
+        ) -> io::Result<()> {
+            for loaded in siblings {
+                f(&loaded.node, *next_id, depth);
+                *next_id += 1;
+                let children = tree.children(&loaded)?;
+                walk_siblings(tree, children, next_id, depth + 1, f)?;
+            }
+            Ok(())
+        }
+
+        let mut next_id = first_id;
+        walk_siblings(self, self.top_level()?, &mut next_id, 1, f)
+    }
+}
+
+/// Like `visit`, for the spilled children of a trace's root. Locations that
+/// `backfill_next_loc` would have filled in are computed on the fly, since
+/// siblings are known at this point.
+pub(crate) fn visit_spilled(
+    f: &mut dyn Write,
+    args: &Args,
+    trace: &Trace,
+    tree: &SpilledTree,
+    chunks: Option<&Chunks>,
+) -> Result<(), Box<dyn Error>> {
+    let mut visitor = Visitor {
+        f,
+        args,
+        trace,
+        tree,
+        chunks,
+        next_id: trace.root.id + 1,
+    };
+    visitor.visit_siblings(tree.top_level()?, &trace.root.notes, None, true)
+}
+
+struct Visitor<'a> {
+    f: &'a mut dyn Write,
+    args: &'a Args,
+    trace: &'a Trace,
+    tree: &'a SpilledTree,
+    chunks: Option<&'a Chunks>,
+    next_id: usize,
+}
+
+impl Visitor<'_> {
+    fn visit_siblings(
+        &mut self,
+        siblings: Vec<Loaded>,
+        // the parent's
+        notes: &[Note],
+        next: Option<Location>,
+        // `visit` doesn't check whether the only child of a flattened node
+        // should be hidden
+        check_hide: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let locs: Vec<Location> = siblings.iter().map(|s| s.node.rule.loc).collect();
+        // as in `visit`, with `--chunked`
+        let mut run = Run::default();
+        for (i, loaded) in siblings.into_iter().enumerate() {
+            let next = locs.get(i + 1).copied().or(next);
+            let nodes = 1 + loaded.descendants;
+            match self.chunks {
+                Some(chunks) if nodes <= chunks::NODES => {
+                    chunks.add(self.f, &mut run, nodes)?;
+                    let mut visitor = Visitor {
+                        f: &mut run.html,
+                        chunks: None,
+                        ..*self
+                    };
+                    write_notes(visitor.f, self.trace, notes, i..i + 1)?;
+                    visitor.visit(loaded, next, check_hide)?;
+                    self.next_id = visitor.next_id;
+                }
+                chunks => {
+                    if let Some(chunks) = chunks {
+                        chunks.flush(self.f, &mut run)?;
+                    }
+                    write_notes(self.f, self.trace, notes, i..i + 1)?;
+                    self.visit(loaded, next, check_hide)?;
+                }
+            }
+        }
+        if let Some(chunks) = self.chunks {
+            chunks.flush(self.f, &mut run)?;
+        }
+        write_notes(self.f, self.trace, notes, locs.len()..usize::MAX)?;
+        Ok(())
+    }
+
+    fn visit(
+        &mut self,
+        mut loaded: Loaded,
+        next: Option<Location>,
+        check_hide: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if loaded.node.rule.next_loc.is_none() {
+            loaded.node.rule.next_loc = next;
+        }
+        loaded.node.id = self.next_id;
+        self.next_id += 1;
+
+        if check_hide && self.args.should_hide(self.trace, &loaded.node) {
+            self.next_id += loaded.descendants;
+            return Ok(());
+        }
+
+        let children = self.tree.children(&loaded)?;
+        if self
+            .args
+            .should_flatten(self.trace, &loaded.node, children.len())
+        {
+            write_notes(self.f, self.trace, &loaded.node.notes, 0..usize::MAX)?;
+            return self.visit_siblings(children, &[], next, false);
+        }
+
+        write_summary(self.f, self.args, self.trace, &loaded.node)?;
+        let lazy = lazy_children(self.args, &loaded.node, !children.is_empty());
+        if lazy {
+            write!(self.f, r#"<template class="children">"#)?;
+        }
+        self.visit_siblings(children, &loaded.node.notes, next, true)?;
+        if lazy {
+            write!(self.f, "</template>")?;
+        }
+        writeln!(self.f, "</details>")?;
+        Ok(())
+    }
+}
+
+fn put_u64(buf: &mut Vec<u8>, n: u64) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_u64(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn put_loc(buf: &mut Vec<u8>, loc: Option<Location>) {
+    match loc {
+        None => buf.push(0),
+        Some(Location::LineCol { line, column }) => {
+            buf.push(1);
+            put_u64(buf, line as u64);
+            put_u64(buf, column as u64);
+        }
+        Some(Location::Offset(offset)) => {
+            buf.push(2);
+            put_u64(buf, offset as u64);
+        }
+    }
+}
+
+fn take<'a>(r: &mut &'a [u8], n: usize) -> &'a [u8] {
+    let (head, tail) = r.split_at(n);
+    *r = tail;
+    head
+}
+
+fn get_u64(r: &mut &[u8]) -> u64 {
+    u64::from_le_bytes(take(r, 8).try_into().unwrap())
+}
+
+fn get_str(r: &mut &[u8]) -> String {
+    let len = get_u64(r) as usize;
+    String::from_utf8_lossy(take(r, len)).into_owned()
+}
+
+fn get_loc(r: &mut &[u8]) -> Option<Location> {
+    match take(r, 1)[0] {
+        1 => Some(Location::LineCol {
+            line: get_u64(r) as usize,
+            column: get_u64(r) as usize,
+        }),
+        2 => Some(Location::Offset(get_u64(r) as usize)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_removed() {
+        // dropped while being written to, e.g. on a read error
+        let store = NodeStore::create().unwrap();
+        let path = store.path.0.clone();
+        assert!(path.exists());
+        drop(store);
+        assert!(!path.exists());
+
+        // dropped after being read from
+        let store = NodeStore::create().unwrap();
+        let path = store.path.0.clone();
+        let frame = store.open_frame();
+        let tree = store
+            .finish(&mut crate::input::trace_root(1), frame)
+            .unwrap();
+        assert!(path.exists());
+        drop(tree);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,184 @@
+//! SQLite export, see `--format sqlite`, to ask big traces questions in SQL.
+
+use crate::{input::Trace, succeeded, Node, State};
+use rusqlite::{params, Connection};
+use std::{collections::HashMap, error::Error, fs, io, path::Path};
+
+const SCHEMA: &[(&str, &str)] = &[
+    (
+        "traces",
+        "CREATE TABLE traces (id INTEGER PRIMARY KEY, number INTEGER, name TEXT, \
+         source TEXT, grammar TEXT, test TEXT, input TEXT, succeeded INTEGER, \
+         error_pos INTEGER, error_expected TEXT)",
+    ),
+    (
+        "rules",
+        "CREATE TABLE rules (id INTEGER PRIMARY KEY, grammar TEXT, name TEXT)",
+    ),
+    (
+        "nodes",
+        "CREATE TABLE nodes (id INTEGER PRIMARY KEY, trace_id INTEGER, \
+         parent_id INTEGER, rule_id INTEGER, depth INTEGER, state TEXT, \
+         partial_match INTEGER, start INTEGER, end INTEGER, log_line INTEGER)",
+    ),
+];
+
+pub(crate) fn write_sqlite(path: &Path, traces: &[Trace]) -> Result<(), Box<dyn Error>> {
+    // the tables would be there already
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let mut db = Connection::open(path)?;
+    let tx = db.transaction()?;
+    for (_, sql) in SCHEMA {
+        tx.execute(sql, [])?;
+    }
+    {
+        let mut insert_trace =
+            tx.prepare("INSERT INTO traces VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)")?;
+        let mut insert_rule = tx.prepare("INSERT INTO rules VALUES (?1, ?2, ?3)")?;
+        let mut insert_node =
+            tx.prepare("INSERT INTO nodes VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)")?;
+        let mut rule_ids: HashMap<(Option<String>, String), usize> = HashMap::new();
+        let mut next_node = 1;
+
+        for (n, trace) in traces.iter().enumerate() {
+            let trace_id = n + 1;
+            let input = &trace.input;
+            insert_trace.execute(params![
+                trace_id as i64,
+                (trace.index + 1) as i64,
+                trace.root.rule.name,
+                trace.source,
+                trace.grammar,
+                trace.test.as_ref().map(|t| &t.name),
+                input,
+                succeeded(trace),
+                trace.error.as_ref().map(|e| e.loc.pos(input) as i64),
+                trace.error.as_ref().map(|e| &e.expected),
+            ])?;
+
+            let loaded;
+            let children = match &trace.spilled {
+                Some(tree) => {
+                    loaded = tree.load_all()?;
+                    &loaded
+                }
+                None => &trace.root.children,
+            };
+            let mut add = |node: &Node, place: Place| {
+                let key = (trace.grammar.clone(), node.rule.name.clone());
+                let rule_id = match rule_ids.get(&key) {
+                    Some(&id) => id,
+                    None => {
+                        let id = rule_ids.len() + 1;
+                        insert_rule.execute(params![id as i64, trace.grammar, node.rule.name])?;
+                        rule_ids.insert(key, id);
+                        id
+                    }
+                };
+                let start = node.rule.loc.pos(input);
+                let end = match (&node.state, node.rule.next_loc) {
+                    (State::Success, Some(next)) => Some(next.pos(input).max(start)),
+                    _ => None,
+                };
+                let state = match node.state {
+                    State::Success => "success",
+                    State::Failure => "failure",
+                    State::Unknown => "unknown",
+                };
+                insert_node.execute(params![
+                    place.id as i64,
+                    trace_id as i64,
+                    place.parent.map(|id| id as i64),
+                    rule_id as i64,
+                    place.depth as i64,
+                    state,
+                    node.partial_match,
+                    start as i64,
+                    end.map(|end| end as i64),
+                    node.log_line as i64,
+                ])?;
+                Ok(())
+            };
+            insert_nodes(children, None, 1, &mut next_node, &mut add)?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Where a node goes in the `nodes` table.
+struct Place {
+    id: usize,
+    parent: Option<usize>,
+    depth: usize,
+}
+
+/// Hands out pre-order ids, parents before their children.
+fn insert_nodes(
+    nodes: &[Node],
+    parent: Option<usize>,
+    depth: usize,
+    next_id: &mut usize,
+    f: &mut dyn FnMut(&Node, Place) -> rusqlite::Result<()>,
+) -> rusqlite::Result<()> {
+    for node in nodes {
+        let id = *next_id;
+        *next_id += 1;
+        f(node, Place { id, parent, depth })?;
+        insert_nodes(&node.children, Some(id), depth + 1, next_id, f)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gen::{generate, GenArgs},
+        input::tests::{read, TestDir},
+    };
+
+    #[test]
+    fn large_traces_read_back() {
+        let dir = TestDir::new("large_traces_read_back");
+        let args = GenArgs {
+            nodes: 10_000,
+            depth: 12,
+            seed: 1,
+            traces: 3,
+            malformed: false,
+        };
+        let traces = read(&dir, &generate(&args), &[]).unwrap();
+        let path = dir.join("trace.db");
+        // written twice, the second time over the first
+        write_sqlite(&path, &traces[..1]).unwrap();
+        write_sqlite(&path, &traces).unwrap();
+
+        let db = Connection::open(&path).unwrap();
+        let count = |sql: &str| -> usize {
+            db.query_row(sql, [], |row| row.get::<_, i64>(0)).unwrap() as usize
+        };
+        assert_eq!(count("SELECT count(*) FROM traces"), args.traces);
+        assert_eq!(
+            count("SELECT count(*) FROM nodes"),
+            args.traces * args.nodes
+        );
+        // every parent exists, and is one level up
+        assert_eq!(
+            count(
+                "SELECT count(*) FROM nodes AS child JOIN nodes AS parent \
+                 ON parent.id = child.parent_id AND parent.depth = child.depth - 1"
+            ),
+            count("SELECT count(*) FROM nodes WHERE parent_id IS NOT NULL")
+        );
+        let input: String = db
+            .query_row("SELECT input FROM traces WHERE id = 3", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(input, traces[2].input);
+    }
+}
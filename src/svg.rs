@@ -0,0 +1,251 @@
+//! Static SVG rendering of trace trees, see `--format svg`, for design docs
+//! and anywhere else an interactive report won't do.
+//!
+//! Trees are laid out top-down: each subtree gets a horizontal band as wide
+//! as its children need (or as its own box, if wider), and parents are
+//! centered over their band. `--hide` and `--flatten` apply, and are the
+//! way to keep large trees legible.
+
+use crate::{escape_html, input::Trace, Args, Node, State};
+use std::{error::Error, io::Write};
+
+const CHAR_WIDTH: f64 = 7.2;
+const PADDING: f64 = 8.0;
+const BOX_HEIGHT: f64 = 34.0;
+const ROW_HEIGHT: f64 = 64.0;
+const GAP: f64 = 10.0;
+const MARGIN: f64 = 20.0;
+/// room for a trace's title, above its tree
+const TITLE_HEIGHT: f64 = 30.0;
+/// chars of input shown under each rule name
+const SNIPPET: usize = 16;
+
+/// A node as drawn, once hidden and flattened nodes are out of the way.
+struct Laid {
+    rule: String,
+    snippet: String,
+    title: String,
+    class: &'static str,
+    width: f64,
+    /// width of the band the node's subtree takes
+    band: f64,
+    /// center of the node's box
+    x: f64,
+    depth: usize,
+    children: Vec<Laid>,
+}
+
+pub(crate) fn write_svg(
+    f: &mut dyn Write,
+    args: &Args,
+    traces: &[Trace],
+) -> Result<(), Box<dyn Error>> {
+    let mut trees = vec![];
+    for trace in traces {
+        let children = match &trace.spilled {
+            Some(tree) => tree
+                .load_all()?
+                .iter()
+                .filter(|child| !args.should_hide(trace, child))
+                .map(|child| lay(args, trace, child))
+                .collect(),
+            None => lay_children(args, trace, &trace.root),
+        };
+        let mut laid = Laid {
+            rule: trace.root.rule.name.clone(),
+            snippet: String::new(),
+            title: trace.root.rule.name.clone(),
+            class: "root",
+            width: trace.root.rule.name.chars().count() as f64 * CHAR_WIDTH + 2.0 * PADDING,
+            band: 0.0,
+            x: 0.0,
+            depth: 0,
+            children,
+        };
+        measure(&mut laid);
+        place(&mut laid, MARGIN, 0);
+        let title = match &trace.source {
+            Some(source) => format!("{} — {}", trace.root.rule.name, source),
+            None => trace.root.rule.name.clone(),
+        };
+        trees.push((title, laid));
+    }
+
+    let width = trees.iter().map(|(_, laid)| laid.band).fold(0.0, f64::max) + 2.0 * MARGIN;
+    let heights: Vec<f64> = trees
+        .iter()
+        .map(|(_, laid)| TITLE_HEIGHT + (depth(laid) as f64 + 1.0) * ROW_HEIGHT)
+        .collect();
+    let height = heights.iter().sum::<f64>() + MARGIN;
+
+    writeln!(
+        f,
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.0} {h:.0}" font-family="'Source Code Pro', monospace" font-size="12">
+<!-- generated by pegviz {version} -->
+<style>
+  rect {{ stroke-width: 1.5; }}
+  .success rect {{ fill: #17382c; stroke: #27966d; }}
+  .failure rect {{ fill: #3a1d1d; stroke: #942c2c; }}
+  .partial-match rect {{ fill: #3a2f17; stroke: #ba8925; }}
+  .unknown rect {{ fill: #2a2a2a; stroke: #666; }}
+  .root rect {{ fill: #222; stroke: #888; }}
+  text {{ fill: #ddd; text-anchor: middle; }}
+  text.snippet {{ fill: #999; font-size: 10px; }}
+  text.trace-title {{ text-anchor: start; font-size: 14px; }}
+  path {{ fill: none; stroke: #555; }}
+</style>
+<rect width="100%" height="100%" fill="#111" stroke="none"/>"##,
+        w = width,
+        h = height,
+        version = env!("CARGO_PKG_VERSION"),
+    )?;
+    let mut top = MARGIN;
+    for ((title, laid), height) in trees.iter().zip(&heights) {
+        writeln!(f, r#"<g transform="translate(0 {:.0})">"#, top)?;
+        writeln!(
+            f,
+            r#"<text class="trace-title" x="{:.0}" y="14">{}</text>"#,
+            MARGIN,
+            escape_html(title)
+        )?;
+        write_edges(f, laid)?;
+        write_boxes(f, laid)?;
+        writeln!(f, "</g>")?;
+        top += height;
+    }
+    writeln!(f, "</svg>")?;
+    Ok(())
+}
+
+/// Builds what's drawn for `node`, with `--hide` and `--flatten` applied.
+fn lay(args: &Args, trace: &Trace, node: &Node) -> Laid {
+    if args.should_flatten(trace, node, node.children.len()) {
+        return lay(args, trace, &node.children[0]);
+    }
+    let input = &trace.input;
+    let start = node.rule.loc.pos(input);
+    let (snippet, class) = match node.state {
+        State::Success => {
+            let end = node.rule.next_loc.map_or(start, |loc| loc.pos(input));
+            (snippet(&input[start..end.max(start)]), "success")
+        }
+        State::Failure if node.partial_match => (snippet(&input[start..]), "partial-match"),
+        State::Failure => (snippet(&input[start..]), "failure"),
+        State::Unknown => (snippet(&input[start..]), "unknown"),
+    };
+    let title = match node.rule.next_loc {
+        Some(next) => format!(
+            "{} {}–{}",
+            node.rule.name,
+            args.display_loc(input, node.rule.loc),
+            args.display_loc(input, next)
+        ),
+        None => format!(
+            "{} {}",
+            node.rule.name,
+            args.display_loc(input, node.rule.loc)
+        ),
+    };
+    let chars = node.rule.name.chars().count().max(snippet.chars().count());
+    Laid {
+        rule: node.rule.name.clone(),
+        snippet,
+        title,
+        class,
+        width: chars as f64 * CHAR_WIDTH + 2.0 * PADDING,
+        band: 0.0,
+        x: 0.0,
+        depth: 0,
+        children: lay_children(args, trace, node),
+    }
+}
+
+fn lay_children(args: &Args, trace: &Trace, node: &Node) -> Vec<Laid> {
+    node.children
+        .iter()
+        .filter(|child| !args.should_hide(trace, child))
+        .map(|child| lay(args, trace, child))
+        .collect()
+}
+
+/// Up to `SNIPPET` chars of `text`, quoted and escaped like a Rust string.
+fn snippet(text: &str) -> String {
+    let mut res: String = text.chars().take(SNIPPET).collect();
+    let more = res.len() < text.len();
+    res = format!("{:?}", res);
+    if more {
+        res.push('…');
+    }
+    res
+}
+
+fn measure(laid: &mut Laid) {
+    let mut children = 0.0;
+    for child in &mut laid.children {
+        measure(child);
+        children += child.band;
+    }
+    if !laid.children.is_empty() {
+        children += GAP * (laid.children.len() - 1) as f64;
+    }
+    laid.band = laid.width.max(children);
+}
+
+fn place(laid: &mut Laid, left: f64, depth: usize) {
+    laid.x = left + laid.band / 2.0;
+    laid.depth = depth;
+    let children: f64 = laid.children.iter().map(|c| c.band).sum::<f64>()
+        + GAP * laid.children.len().saturating_sub(1) as f64;
+    let mut left = left + (laid.band - children) / 2.0;
+    for child in &mut laid.children {
+        place(child, left, depth + 1);
+        left += child.band + GAP;
+    }
+}
+
+fn depth(laid: &Laid) -> usize {
+    laid.children.iter().map(depth).max().map_or(0, |d| d + 1)
+}
+
+fn top(laid: &Laid) -> f64 {
+    TITLE_HEIGHT + laid.depth as f64 * ROW_HEIGHT
+}
+
+fn write_edges(f: &mut dyn Write, laid: &Laid) -> Result<(), Box<dyn Error>> {
+    let y0 = top(laid) + BOX_HEIGHT;
+    for child in &laid.children {
+        let y1 = top(child);
+        let mid = (y0 + y1) / 2.0;
+        writeln!(
+            f,
+            r#"<path d="M{:.1} {:.1} C{:.1} {:.1} {:.1} {:.1} {:.1} {:.1}"/>"#,
+            laid.x, y0, laid.x, mid, child.x, mid, child.x, y1
+        )?;
+        write_edges(f, child)?;
+    }
+    Ok(())
+}
+
+fn write_boxes(f: &mut dyn Write, laid: &Laid) -> Result<(), Box<dyn Error>> {
+    let y = top(laid);
+    writeln!(
+        f,
+        r#"<g class="{}"><title>{}</title><rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.0}" rx="3"/><text x="{:.1}" y="{:.1}">{}</text><text class="snippet" x="{:.1}" y="{:.1}">{}</text></g>"#,
+        laid.class,
+        escape_html(&laid.title),
+        laid.x - laid.width / 2.0,
+        y,
+        laid.width,
+        BOX_HEIGHT,
+        laid.x,
+        y + 14.0,
+        escape_html(&laid.rule),
+        laid.x,
+        y + 27.0,
+        escape_html(&laid.snippet)
+    )?;
+    for child in &laid.children {
+        write_boxes(f, child)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,228 @@
+//! Plain-text trees, see `--format text`, for terminals where there's no
+//! browser to open a report in.
+
+use crate::{input::Trace, Args, Node, Note, State};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, IsTerminal, Write},
+    str::FromStr,
+};
+
+/// chars of input shown after each node
+const SNIPPET: usize = 24;
+
+/// Whether to color text output, see `--color`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Color {
+    /// when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err(format!(
+                "unknown color mode {:?}, expected auto, always or never",
+                s
+            )),
+        }
+    }
+}
+
+impl Color {
+    /// Whether to use colors when writing to stdout (`to_stdout`), or to a
+    /// file.
+    pub(crate) fn enabled(self, to_stdout: bool) -> bool {
+        match self {
+            Color::Auto => to_stdout && io::stdout().is_terminal(),
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+/// ANSI escapes, or nothing when colors are off.
+struct Paint(bool);
+
+impl Paint {
+    fn paint<'a>(&self, code: &'static str, text: &'a str) -> Painted<'a> {
+        Painted {
+            code: if self.0 { Some(code) } else { None },
+            text,
+        }
+    }
+}
+
+struct Painted<'a> {
+    code: Option<&'static str>,
+    text: &'a str,
+}
+
+impl fmt::Display for Painted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "\x1b[{}m{}\x1b[0m", code, self.text),
+            None => f.write_str(self.text),
+        }
+    }
+}
+
+const GREEN: &str = "32";
+const RED: &str = "31";
+const YELLOW: &str = "33";
+const DIM: &str = "2";
+const BOLD: &str = "1";
+
+pub(crate) fn write_text(
+    f: &mut dyn Write,
+    args: &Args,
+    traces: &[Trace],
+    color: bool,
+) -> Result<(), Box<dyn Error>> {
+    let paint = Paint(color);
+    for (i, trace) in traces.iter().enumerate() {
+        if i > 0 {
+            writeln!(f)?;
+        }
+        let mut title = trace.root.rule.name.clone();
+        if let Some(source) = &trace.source {
+            title.push_str(" — ");
+            title.push_str(source);
+        }
+        writeln!(f, "{}", paint.paint(BOLD, &title))?;
+        if let Some(error) = &trace.error {
+            let message = format!(
+                "error at {}: expected {}",
+                args.display_loc(&trace.input, error.loc),
+                error.expected
+            );
+            writeln!(f, "{}", paint.paint(RED, &message))?;
+        }
+        let notes = &trace.root.notes;
+        match &trace.spilled {
+            Some(tree) => write_children(f, args, trace, &paint, &tree.load_all()?, notes, "")?,
+            None => write_children(f, args, trace, &paint, &trace.root.children, notes, "")?,
+        }
+    }
+    Ok(())
+}
+
+fn write_children(
+    f: &mut dyn Write,
+    args: &Args,
+    trace: &Trace,
+    paint: &Paint,
+    children: &[Node],
+    // the parent's
+    notes: &[Note],
+    prefix: &str,
+) -> Result<(), Box<dyn Error>> {
+    let visible: Vec<(usize, &Node)> = children
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| !args.should_hide(trace, child))
+        .collect();
+    let note_prefix = |before: usize| {
+        if visible.iter().any(|&(i, _)| i >= before) {
+            format!("{}│  ", prefix)
+        } else {
+            format!("{}   ", prefix)
+        }
+    };
+    let mut n = 0;
+    for (i, child) in children.iter().enumerate() {
+        for note in notes.iter().filter(|note| note.before == i) {
+            let text = format!("# {}", note.text);
+            writeln!(f, "{}{}", note_prefix(i), paint.paint(DIM, &text))?;
+        }
+        if args.should_hide(trace, child) {
+            continue;
+        }
+        n += 1;
+        let last = n == visible.len();
+        let (branch, indent) = if last {
+            ("└─ ", "   ")
+        } else {
+            ("├─ ", "│  ")
+        };
+        // a flattened node is replaced by its only child
+        let mut shown = child;
+        while args.should_flatten(trace, shown, shown.children.len()) {
+            shown = &shown.children[0];
+        }
+        write!(f, "{}{}", prefix, branch)?;
+        write_node(f, args, trace, paint, shown)?;
+        let prefix = format!("{}{}", prefix, indent);
+        write_children(
+            f,
+            args,
+            trace,
+            paint,
+            &shown.children,
+            &shown.notes,
+            &prefix,
+        )?;
+    }
+    for note in notes.iter().filter(|note| note.before >= children.len()) {
+        let text = format!("# {}", note.text);
+        writeln!(f, "{}   {}", prefix, paint.paint(DIM, &text))?;
+    }
+    Ok(())
+}
+
+fn write_node(
+    f: &mut dyn Write,
+    args: &Args,
+    trace: &Trace,
+    paint: &Paint,
+    node: &Node,
+) -> Result<(), Box<dyn Error>> {
+    let input = &trace.input;
+    let start = node.rule.loc.pos(input);
+    let (mark, code) = match node.state {
+        State::Success => ("✓", GREEN),
+        State::Failure if node.partial_match => ("✗", YELLOW),
+        State::Failure => ("✗", RED),
+        State::Unknown => ("?", DIM),
+    };
+    let (positions, text) = match (&node.state, node.rule.next_loc) {
+        (State::Success, Some(next)) => {
+            let end = next.pos(input).max(start);
+            (
+                format!(
+                    "{}–{}",
+                    args.display_loc(input, node.rule.loc),
+                    args.display_loc(input, next)
+                ),
+                &input[start..end],
+            )
+        }
+        _ => (
+            args.display_loc(input, node.rule.loc).to_string(),
+            &input[start..],
+        ),
+    };
+    let mut snippet: String = text.chars().take(SNIPPET).collect();
+    let more = snippet.len() < text.len();
+    snippet = format!("{:?}", snippet);
+    if more {
+        snippet.push('…');
+    }
+    writeln!(
+        f,
+        "{} {} {} {}",
+        paint.paint(code, mark),
+        paint.paint(code, &node.rule.name),
+        paint.paint(DIM, &positions),
+        snippet
+    )?;
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use std::{cmp::Ordering, fmt};
+
+/// A wall-clock timestamp found at the start of a log line, e.g.
+/// `2020-05-01T10:00:00.123Z [PEG_INPUT_START]` or env_logger's
+/// `[2020-05-01T10:00:00Z INFO mycrate] [PEG_INPUT_START]`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timestamp {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    nanos: u32,
+    /// offset from UTC in minutes, `None` if the timestamp didn't specify one
+    offset: Option<i32>,
+}
+
+impl Timestamp {
+    /// The current time, in UTC.
+    pub(crate) fn now() -> Timestamp {
+        let elapsed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let secs = elapsed.as_secs() as i64;
+        // civil_from_days, from the same place as `unix_secs`
+        let z = secs.div_euclid(86400) + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        let time = secs.rem_euclid(86400) as u32;
+        Timestamp {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time / 60 % 60,
+            second: time % 60,
+            nanos: 0,
+            offset: Some(0),
+        }
+    }
+
+    /// Seconds since the unix epoch, treating timestamps without an offset as UTC.
+    fn unix_secs(&self) -> i64 {
+        // days_from_civil, see http://howardhinnant.github.io/date_algorithms.html
+        let y = if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let era = if y >= 0 { y } else { y - 399 } / 400;
+        let yoe = y - era * 400;
+        let m = self.month as i64;
+        let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        days * 86400 + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+            - self.offset.unwrap_or(0) as i64 * 60
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+        if self.nanos > 0 {
+            write!(f, ".{:03}", self.nanos / 1_000_000)?;
+        }
+        match self.offset {
+            Some(0) => write!(f, "Z"),
+            Some(offset) => write!(
+                f,
+                "{}{:02}:{:02}",
+                if offset < 0 { '-' } else { '+' },
+                offset.abs() / 60,
+                offset.abs() % 60
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+impl PartialEq for Timestamp {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Timestamp {}
+
+impl Ord for Timestamp {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.unix_secs(), self.nanos).cmp(&(other.unix_secs(), other.nanos))
+    }
+}
+
+impl PartialOrd for Timestamp {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Splits a leading timestamp off `line`, returning the timestamp (if any)
+/// and the rest of the line.
+pub(crate) fn split(line: &str) -> (Option<Timestamp>, &str) {
+    match line_prefix::timestamped(line) {
+        Ok(res) => res,
+        Err(_) => (None, line),
+    }
+}
+
+peg::parser! {
+    grammar line_prefix() for str {
+        pub(crate) rule timestamped() -> (Option<Timestamp>, &'input str)
+            = ts:(t:prefix() " "+ { t })? rest:$([_]*) { (ts, rest) }
+
+        rule prefix() -> Timestamp
+            = "[" t:timestamp() (!"]" [_])* "]" { t }
+            / timestamp()
+
+        rule timestamp() -> Timestamp
+            = year:digits(4) "-" month:digits(2) "-" day:digits(2) ("T" / " ")
+              hour:digits(2) ":" minute:digits(2) ":" second:digits(2)
+              nanos:fraction()?
+              offset:offset()? {
+                Timestamp {
+                    year: year as i64,
+                    month: month as u32,
+                    day: day as u32,
+                    hour: hour as u32,
+                    minute: minute as u32,
+                    second: second as u32,
+                    nanos: nanos.unwrap_or(0),
+                    offset,
+                }
+            }
+
+        rule fraction() -> u32
+            = ("." / ",") digits:$(['0'..='9']+) {
+                // right-pad (or truncate) to nanosecond precision
+                format!("{:0<9.9}", digits).parse().unwrap()
+            }
+
+        rule offset() -> i32
+            = "Z" { 0 }
+            / sign:$("+" / "-") hours:digits(2) ":"? minutes:digits(2) {
+                let offset = (hours * 60 + minutes) as i32;
+                if sign == "-" { -offset } else { offset }
+            }
+
+        rule digits(n: usize) -> u64
+            = digits:$(['0'..='9']*<{n}>) { digits.parse().unwrap() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Timestamp {
+        split(line).0.unwrap()
+    }
+
+    #[test]
+    fn prefixes() {
+        let (ts, rest) = split("2020-05-01T10:00:00.123Z [PEG_INPUT_START]");
+        assert_eq!(ts.unwrap().to_string(), "2020-05-01 10:00:00.123Z");
+        assert_eq!(rest, "[PEG_INPUT_START]");
+
+        // env_logger
+        let (ts, rest) = split("[2020-05-01T10:00:00Z INFO mycrate] [PEG_TRACE_START]");
+        assert_eq!(ts.unwrap().to_string(), "2020-05-01 10:00:00Z");
+        assert_eq!(rest, "[PEG_TRACE_START]");
+
+        assert_eq!(
+            parse("2020-05-01 10:00:00,5+0530 x").to_string(),
+            "2020-05-01 10:00:00.500+05:30"
+        );
+        assert_eq!(
+            parse("2020-05-01 10:00:00 x").to_string(),
+            "2020-05-01 10:00:00"
+        );
+
+        for line in &[
+            "[PEG_INPUT_START]",
+            "2020-05-01 [PEG_INPUT_START]",
+            "",
+            "2020-05-01T10:00:00Z",
+        ] {
+            assert_eq!(split(line), (None, *line));
+        }
+    }
+
+    #[test]
+    fn ordering_follows_offsets() {
+        assert_eq!(
+            parse("2020-05-01T10:00:00+02:00 x"),
+            parse("2020-05-01T08:00:00Z x")
+        );
+        // across a day, and a year
+        assert!(parse("2020-12-31T23:30:00-01:00 x") > parse("2021-01-01T00:15:00Z x"));
+        assert!(
+            parse("2020-05-01T10:00:00.000000002Z x") > parse("2020-05-01T10:00:00.000000001Z x")
+        );
+        // offsets left out are taken as UTC
+        assert_eq!(
+            parse("2020-05-01T10:00:00 x"),
+            parse("2020-05-01T10:00:00Z x")
+        );
+    }
+
+    #[test]
+    fn now_round_trips() {
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let now = Timestamp::now();
+        assert!((now.unix_secs() - secs).abs() <= 1);
+        assert_eq!(parse(&format!("{} x", now)), now);
+        assert_eq!(parse("1970-01-01T00:00:00Z x").unix_secs(), 0);
+        assert_eq!(parse("2000-03-01T00:00:00Z x").unix_secs(), 951_868_800);
+    }
+}
@@ -0,0 +1,105 @@
+//! Token streams, for grammars that parse the tokens of a lexer rather than
+//! text. The lexer's tokens are logged after the source, one per line with
+//! the span of source they came from:
+//!
+//! ```text
+//! [PEG_INPUT_START]
+//! let x = 1;
+//! [PEG_TOKENS]
+//! Let 0..3
+//! Ident("x") 4..5
+//! Eq 6..7
+//! Int(1) 8..9
+//! Semi 9..10
+//! [PEG_TRACE_START]
+//! ```
+//!
+//! Spans are byte offsets or `line:column` pairs. The trace's locations are
+//! then indices into the tokens, and are turned into where those tokens are
+//! in the source, so snippets show the source text.
+
+use crate::{Line, Location};
+
+pub(crate) struct Token {
+    pub(crate) name: String,
+    /// byte offsets of the token in the input
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+/// A token line, `Name start..end`: the span is whatever comes after the
+/// last space, token names may have spaces in them.
+pub(crate) fn parse(line: &str, input: &str) -> Result<Token, String> {
+    let line = line.trim();
+    let bad = || {
+        format!(
+            "expected a token name and its span, like `Ident 4..5`, got {:?}",
+            line
+        )
+    };
+    let (name, span) = line.rsplit_once(char::is_whitespace).ok_or_else(bad)?;
+    let (start, end) = span.split_once("..").ok_or_else(bad)?;
+    let pos = |s: &str| -> Result<usize, String> {
+        let loc = match s.split_once(':') {
+            Some((line, column)) => Location::LineCol {
+                line: line.parse().map_err(|_| bad())?,
+                column: column.parse().map_err(|_| bad())?,
+            },
+            None => Location::Offset(s.parse().map_err(|_| bad())?),
+        };
+        Ok(loc.pos(input))
+    };
+    let (start, end) = (pos(start)?, pos(end)?);
+    if end < start {
+        return Err(format!("token {:?} ends before it starts", name.trim()));
+    }
+    Ok(Token {
+        name: name.trim().into(),
+        start,
+        end,
+    })
+}
+
+/// Where the token at `index` starts in the input, the end of the last
+/// token for the end of the stream.
+pub(crate) fn start(tokens: &[Token], index: usize) -> usize {
+    match tokens.get(index) {
+        Some(token) => token.start,
+        None => tokens.last().map_or(0, |token| token.end),
+    }
+}
+
+/// Where a match of the tokens from `from` up to `to` ends in the input: at
+/// the end of its last token, leaving out whatever the lexer skipped after
+/// it.
+fn end(tokens: &[Token], from: usize, to: usize) -> usize {
+    if to > from && to <= tokens.len() {
+        tokens[to - 1].end
+    } else {
+        start(tokens, to)
+    }
+}
+
+/// Turns the token indices in `line` into byte offsets.
+pub(crate) fn convert_line(tokens: &[Token], line: &mut Line) {
+    let rule = match line {
+        Line::Attempt(rule) | Line::Failure(rule) | Line::Success(rule) => rule,
+        Line::Cache(Some(rule)) => rule,
+        _ => return,
+    };
+    let from = match rule.loc {
+        Location::Offset(from) => from,
+        _ => return,
+    };
+    rule.loc = Location::Offset(start(tokens, from));
+    if let Some(Location::Offset(to)) = rule.next_loc {
+        rule.next_loc = Some(Location::Offset(end(tokens, from, to)));
+    }
+}
+
+/// The tokens lying within `start..end` of the input.
+pub(crate) fn within(tokens: &[Token], start: usize, end: usize) -> &[Token] {
+    let first = tokens.partition_point(|token| token.start < start);
+    let last = first + tokens[first..].partition_point(|token| token.end <= end);
+    &tokens[first..last]
+}
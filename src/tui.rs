@@ -0,0 +1,568 @@
+//! Terminal trace browser, see `--tui`: the tree on the left, the selected
+//! node's input on the right.
+//!
+//! There's no terminal library to lean on, so this drives the terminal
+//! directly: `stty` for raw mode, ANSI escapes for drawing. Keys and drawing
+//! go through `/dev/tty`, since stdin is usually the log being read. That's
+//! Unix only, `--tui` is rejected elsewhere.
+
+use crate::{input::Trace, Args, Node, State};
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    process::{Command, Stdio},
+};
+
+/// A node of the tree being browsed, in an arena.
+struct Item<'a> {
+    node: &'a Node,
+    trace: usize,
+    depth: usize,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+struct Browser<'a> {
+    args: &'a Args,
+    traces: &'a [Trace],
+    items: Vec<Item<'a>>,
+    expanded: Vec<bool>,
+    /// indices into `items`, as currently shown
+    rows: Vec<usize>,
+    cursor: usize,
+    /// first row on screen
+    top: usize,
+    search: String,
+    /// typing a search
+    prompt: Option<String>,
+    /// shown in the status line until the next key
+    message: Option<String>,
+}
+
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Escape,
+    Backspace,
+    /// Ctrl-C, which raw mode turns into a key so the terminal gets restored
+    Interrupt,
+    Char(char),
+}
+
+pub(crate) fn run(args: &Args, traces: &[Trace]) -> Result<(), Box<dyn Error>> {
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|_| "pegviz: --tui needs a terminal")?;
+
+    // spilled trees are read back in full, there's no browsing them lazily
+    let mut loaded = vec![];
+    for trace in traces {
+        loaded.push(match &trace.spilled {
+            Some(tree) => Some(tree.load_all()?),
+            None => None,
+        });
+    }
+
+    let mut browser = Browser {
+        args,
+        traces,
+        items: vec![],
+        expanded: vec![],
+        rows: vec![],
+        cursor: 0,
+        top: 0,
+        search: String::new(),
+        prompt: None,
+        message: None,
+    };
+    for (i, trace) in traces.iter().enumerate() {
+        let children = loaded[i].as_deref().unwrap_or(&trace.root.children);
+        browser.add(&trace.root, children, i, 0, None);
+    }
+    browser.expanded = vec![false; browser.items.len()];
+    // traces start out showing their top-level rules
+    for i in 0..browser.items.len() {
+        browser.expanded[i] = browser.items[i].parent.is_none();
+    }
+    browser.update_rows();
+
+    let raw = RawMode::enter()?;
+    write!(tty, "\x1b[?1049h\x1b[?25l")?;
+    let res = browser.event_loop(&mut tty);
+    write!(tty, "\x1b[?25h\x1b[?1049l")?;
+    drop(raw);
+    res
+}
+
+impl<'a> Browser<'a> {
+    /// Adds `node` to the arena, which stands for `children` (its own, or the
+    /// ones read back from disk).
+    fn add(
+        &mut self,
+        node: &'a Node,
+        children: &'a [Node],
+        trace: usize,
+        depth: usize,
+        parent: Option<usize>,
+    ) -> usize {
+        let index = self.items.len();
+        self.items.push(Item {
+            node,
+            trace,
+            depth,
+            parent,
+            children: vec![],
+        });
+        let t = &self.traces[trace];
+        for child in children {
+            if self.args.should_hide(t, child) {
+                continue;
+            }
+            let mut shown = child;
+            while self.args.should_flatten(t, shown, shown.children.len()) {
+                shown = &shown.children[0];
+            }
+            let child = self.add(shown, &shown.children, trace, depth + 1, Some(index));
+            self.items[index].children.push(child);
+        }
+        index
+    }
+
+    fn update_rows(&mut self) {
+        fn visit(browser: &Browser, item: usize, rows: &mut Vec<usize>) {
+            rows.push(item);
+            if browser.expanded[item] {
+                for &child in &browser.items[item].children {
+                    visit(browser, child, rows);
+                }
+            }
+        }
+
+        let selected = self.rows.get(self.cursor).copied();
+        let mut rows = vec![];
+        for item in 0..self.items.len() {
+            if self.items[item].parent.is_none() {
+                visit(self, item, &mut rows);
+            }
+        }
+        self.rows = rows;
+        if let Some(selected) = selected {
+            self.select(selected);
+        }
+    }
+
+    /// Moves the cursor to `item`, which must be shown.
+    fn select(&mut self, item: usize) {
+        if let Some(row) = self.rows.iter().position(|&r| r == item) {
+            self.cursor = row;
+        }
+    }
+
+    fn selected(&self) -> usize {
+        self.rows[self.cursor]
+    }
+
+    fn event_loop(&mut self, tty: &mut File) -> Result<(), Box<dyn Error>> {
+        let mut drawn = None;
+        loop {
+            // reads time out, so a resized terminal gets redrawn without
+            // waiting for a key
+            let size = terminal_size();
+            if drawn != Some(size) {
+                self.draw(tty, size.0, size.1)?;
+                drawn = Some(size);
+            }
+            let keys = read_keys(tty)?;
+            if !keys.is_empty() {
+                drawn = None;
+            }
+            for key in keys {
+                if !self.handle(key, size.0) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Acts on a key press, returns false to quit.
+    fn handle(&mut self, key: Key, height: usize) -> bool {
+        self.message = None;
+        if let Key::Interrupt = key {
+            return false;
+        }
+        if let Some(prompt) = &mut self.prompt {
+            match key {
+                Key::Enter => {
+                    self.search = self.prompt.take().unwrap();
+                    self.find(true);
+                }
+                Key::Escape => self.prompt = None,
+                Key::Backspace => {
+                    prompt.pop();
+                }
+                Key::Char(c) => prompt.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        let page = height.saturating_sub(2).max(1);
+        let last = self.rows.len() - 1;
+        match key {
+            Key::Char('q') | Key::Escape => return false,
+            Key::Up | Key::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Down | Key::Char('j') => self.cursor = (self.cursor + 1).min(last),
+            Key::PageUp => self.cursor = self.cursor.saturating_sub(page),
+            Key::PageDown => self.cursor = (self.cursor + page).min(last),
+            Key::Home | Key::Char('g') => self.cursor = 0,
+            Key::End | Key::Char('G') => self.cursor = last,
+            Key::Right | Key::Char('l') => {
+                let item = self.selected();
+                if self.expanded[item] {
+                    if let Some(&first) = self.items[item].children.first() {
+                        self.select(first);
+                    }
+                } else {
+                    self.expanded[item] = true;
+                    self.update_rows();
+                }
+            }
+            Key::Left | Key::Char('h') => {
+                let item = self.selected();
+                if self.expanded[item] && !self.items[item].children.is_empty() {
+                    self.expanded[item] = false;
+                    self.update_rows();
+                } else if let Some(parent) = self.items[item].parent {
+                    self.select(parent);
+                }
+            }
+            Key::Enter | Key::Char(' ') => {
+                let item = self.selected();
+                self.expanded[item] = !self.expanded[item];
+                self.update_rows();
+            }
+            Key::Char('/') => self.prompt = Some(String::new()),
+            Key::Char('n') => self.find(true),
+            Key::Char('N') => self.find(false),
+            _ => {}
+        }
+        true
+    }
+
+    /// Goes to the next (or previous) node whose rule name contains the
+    /// search, among all nodes, expanding its ancestors.
+    fn find(&mut self, forward: bool) {
+        if self.search.is_empty() {
+            return;
+        }
+        let n = self.items.len();
+        let from = self.selected();
+        let found = (1..=n)
+            .map(|k| {
+                if forward {
+                    (from + k) % n
+                } else {
+                    (from + n - k) % n
+                }
+            })
+            .find(|&i| self.items[i].node.rule.name.contains(&self.search));
+        let found = match found {
+            Some(found) => found,
+            None => {
+                self.message = Some(format!("no rule matching {:?}", self.search));
+                return;
+            }
+        };
+        let mut ancestor = self.items[found].parent;
+        while let Some(a) = ancestor {
+            self.expanded[a] = true;
+            ancestor = self.items[a].parent;
+        }
+        self.update_rows();
+        self.select(found);
+    }
+
+    fn draw(&mut self, tty: &mut File, height: usize, width: usize) -> Result<(), Box<dyn Error>> {
+        let tree_height = height.saturating_sub(1).max(1);
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + tree_height {
+            self.top = self.cursor + 1 - tree_height;
+        }
+        let left_width = (width * 3 / 5).max(20).min(width);
+        let right_width = width.saturating_sub(left_width + 1);
+        let detail = self.detail(right_width, tree_height);
+
+        let mut screen = String::from("\x1b[H");
+        for row in 0..tree_height {
+            let (plain, painted) = match self.rows.get(self.top + row) {
+                Some(&item) => self.tree_row(item, self.top + row == self.cursor, left_width),
+                None => (0, String::new()),
+            };
+            screen.push_str(&painted);
+            screen.push_str(&" ".repeat(left_width.saturating_sub(plain)));
+            if right_width > 0 {
+                screen.push_str("\x1b[2m│\x1b[0m");
+                if let Some((_, line)) = detail.get(row) {
+                    screen.push_str(line);
+                }
+            }
+            screen.push_str("\x1b[K\r\n");
+        }
+
+        let status = match (&self.prompt, &self.message) {
+            (Some(prompt), _) => format!("/{}", prompt),
+            (None, Some(message)) => message.clone(),
+            (None, None) => {
+                let item = &self.items[self.selected()];
+                format!(
+                    "{} — {}/{} — ←→ collapse/expand, / search, n/N next/previous, q quit",
+                    self.traces[item.trace].root.rule.name,
+                    self.cursor + 1,
+                    self.rows.len()
+                )
+            }
+        };
+        let status: String = status.chars().take(width).collect();
+        screen.push_str(&format!("\x1b[7m{}\x1b[K\x1b[0m", status));
+        tty.write_all(screen.as_bytes())?;
+        tty.flush()?;
+        Ok(())
+    }
+
+    /// A row of the tree pane, and how many columns it takes.
+    fn tree_row(&self, item: usize, selected: bool, width: usize) -> (usize, String) {
+        let it = &self.items[item];
+        let trace = &self.traces[it.trace];
+        let node = it.node;
+        let fold = match (it.children.is_empty(), self.expanded[item]) {
+            (true, _) => ' ',
+            (false, true) => '▾',
+            (false, false) => '▸',
+        };
+        let (mark, color) = state_mark(node);
+        let loc = if it.parent.is_none() {
+            String::new()
+        } else {
+            format!(" {}", self.args.display_loc(&trace.input, node.rule.loc))
+        };
+        let head = format!("{}{} ", "  ".repeat(it.depth), fold);
+        let mut plain: Vec<char> = format!("{}{} {}{}", head, mark, node.rule.name, loc)
+            .chars()
+            .collect();
+        plain.truncate(width);
+        let len = plain.len();
+
+        // paint the parts that fit
+        let mut painted = String::new();
+        if selected {
+            painted.push_str("\x1b[7m");
+        }
+        for (i, c) in plain.into_iter().enumerate() {
+            let at = i - head.chars().count().min(i);
+            if i == head.chars().count() {
+                painted.push_str(&format!("\x1b[{}m", color));
+            } else if at == 2 + node.rule.name.chars().count() {
+                painted.push_str("\x1b[39;2m");
+            }
+            painted.push(c);
+        }
+        painted.push_str("\x1b[0m");
+        (len, painted)
+    }
+
+    /// The detail pane's lines, for the selected node.
+    fn detail(&self, width: usize, height: usize) -> Vec<(usize, String)> {
+        if width == 0 {
+            return vec![];
+        }
+        let item = &self.items[self.selected()];
+        let trace = &self.traces[item.trace];
+        let node = item.node;
+        let input = &trace.input;
+        let mut lines = vec![];
+        let mut push = |text: String, style: &str| {
+            let text: String = text.chars().take(width).collect();
+            let len = text.chars().count();
+            lines.push((len, format!("{}{}\x1b[0m", style, text)));
+        };
+
+        push(format!(" {}", node.rule.name), "\x1b[1m");
+        let state = match node.state {
+            State::Success => "success",
+            State::Failure if node.partial_match => "failure (partial match)",
+            State::Failure => "failure",
+            State::Unknown => "unknown (never finished)",
+        };
+        push(format!(" {}", state), "");
+        let start = node.rule.loc.pos(input);
+        let end = match (&node.state, node.rule.next_loc) {
+            (State::Success, Some(next)) => next.pos(input).max(start),
+            _ => start,
+        };
+        if item.parent.is_some() {
+            let at = match node.rule.next_loc {
+                Some(next) => format!(
+                    " at {}–{}",
+                    self.args.display_loc(input, node.rule.loc),
+                    self.args.display_loc(input, next)
+                ),
+                None => format!(" at {}", self.args.display_loc(input, node.rule.loc)),
+            };
+            push(at, "\x1b[2m");
+            push(format!(" log line {}", node.log_line), "\x1b[2m");
+        }
+        push(String::new(), "");
+
+        // the input around the match, with the match highlighted and the
+        // position of a failure underlined
+        let first_line = input[..start].rfind('\n').map_or(0, |i| i + 1);
+        let mut line = String::from(" ");
+        let mut len = 1;
+        for (i, c) in input[first_line..].char_indices() {
+            let i = first_line + i;
+            if lines.len() >= height {
+                break;
+            }
+            if c == '\n' || len >= width {
+                lines.push((len, std::mem::take(&mut line)));
+                line.push(' ');
+                len = 1;
+                if c == '\n' {
+                    continue;
+                }
+            }
+            let style = if i >= start && i < end {
+                "\x1b[7m"
+            } else if i == start && end == start {
+                "\x1b[4;31m"
+            } else {
+                ""
+            };
+            if style.is_empty() {
+                line.push(if c == '\t' { ' ' } else { c });
+            } else {
+                line.push_str(&format!("{}{}\x1b[0m", style, c));
+            }
+            len += 1;
+        }
+        if len > 1 && lines.len() < height {
+            lines.push((len, line));
+        }
+        lines
+    }
+}
+
+fn state_mark(node: &Node) -> (char, &'static str) {
+    match node.state {
+        State::Success => ('✓', "32"),
+        State::Failure if node.partial_match => ('✗', "33"),
+        State::Failure => ('✗', "31"),
+        State::Unknown => ('?', "2"),
+    }
+}
+
+/// Reads what's been typed, several keys at once when pasting or typing
+/// fast, or nothing once the read times out. Sequences that aren't bound to
+/// anything are skipped.
+fn read_keys(tty: &mut File) -> Result<Vec<Key>, Box<dyn Error>> {
+    let mut buf = [0; 64];
+    let n = tty.read(&mut buf)?;
+    let mut bytes = &buf[..n];
+    let mut keys = vec![];
+    while let Some(&first) = bytes.first() {
+        let (key, len) = match bytes {
+            [0x1b, b'[' | b'O', b'A', ..] => (Some(Key::Up), 3),
+            [0x1b, b'[' | b'O', b'B', ..] => (Some(Key::Down), 3),
+            [0x1b, b'[' | b'O', b'C', ..] => (Some(Key::Right), 3),
+            [0x1b, b'[' | b'O', b'D', ..] => (Some(Key::Left), 3),
+            [0x1b, b'[' | b'O', b'H', ..] => (Some(Key::Home), 3),
+            [0x1b, b'[' | b'O', b'F', ..] => (Some(Key::End), 3),
+            [0x1b, b'[', b'1', b'~', ..] => (Some(Key::Home), 4),
+            [0x1b, b'[', b'4', b'~', ..] => (Some(Key::End), 4),
+            [0x1b, b'[', b'5', b'~', ..] => (Some(Key::PageUp), 4),
+            [0x1b, b'[', b'6', b'~', ..] => (Some(Key::PageDown), 4),
+            // some other sequence: up to its final byte
+            [0x1b, b'[', rest @ ..] => {
+                let end = rest.iter().position(|b| (0x40..0x7f).contains(b));
+                (None, end.map_or(bytes.len(), |end| end + 3))
+            }
+            [0x1b, ..] => (Some(Key::Escape), 1),
+            [b'\r' | b'\n', ..] => (Some(Key::Enter), 1),
+            [0x7f | 0x08, ..] => (Some(Key::Backspace), 1),
+            [0x03, ..] => (Some(Key::Interrupt), 1),
+            _ => {
+                let len = match first {
+                    0xf0..=0xff => 4,
+                    0xe0..=0xef => 3,
+                    0xc0..=0xdf => 2,
+                    _ => 1,
+                }
+                .min(bytes.len());
+                let c = std::str::from_utf8(&bytes[..len])
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                    .filter(|c| !c.is_control());
+                (c.map(Key::Char), len)
+            }
+        };
+        keys.extend(key);
+        bytes = &bytes[len..];
+    }
+    Ok(keys)
+}
+
+/// Rows and columns of the terminal, from `stty size`.
+fn terminal_size() -> (usize, usize) {
+    let out = stty(&["size"]).unwrap_or_default();
+    let mut words = out.split_whitespace().map(|w| w.parse().ok());
+    match (words.next().flatten(), words.next().flatten()) {
+        (Some(rows), Some(cols)) if rows > 0 && cols > 0 => (rows, cols),
+        _ => (24, 80),
+    }
+}
+
+fn stty(args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let out = Command::new("stty")
+        .args(args)
+        .stdin(File::open("/dev/tty")?)
+        .stderr(Stdio::null())
+        .output()?;
+    if !out.status.success() {
+        return Err("pegviz: stty failed".into());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Puts the terminal in raw mode until dropped.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enter() -> Result<RawMode, Box<dyn Error>> {
+        let saved = stty(&["-g"])?.trim().to_string();
+        // Ctrl-C comes through as a key (see `Key::Interrupt`), and reads
+        // give up after 0.2s
+        stty(&[
+            "-icanon", "-echo", "-isig", "-ixon", "-icrnl", "min", "0", "time", "2",
+        ])?;
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]).ok();
+    }
+}
@@ -0,0 +1,178 @@
+//! zstd-compressed logs, decompressed with `ruzstd` as they're read.
+//! Dictionaries aren't supported, nothing archives logs with one.
+
+use ruzstd::decoding::{
+    errors::{FrameDecoderError, ReadFrameHeaderError},
+    BlockDecodingStrategy, FrameDecoder,
+};
+use std::io::{self, BufRead, Read};
+
+/// A frame with a 1 KiB window and a single empty block.
+const EMPTY_FRAME: [u8; 9] = [0x28, 0xb5, 0x2f, 0xfd, 0x00, 0x00, 0x01, 0x00, 0x00];
+
+/// Decompresses the frames `source` holds, one after the other as
+/// `cat a.zst b.zst` produces, skipping skippable frames.
+pub(crate) struct Decoder<R> {
+    source: R,
+    frame: FrameDecoder,
+    /// whether `frame` is in the middle of a frame
+    in_frame: bool,
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub(crate) fn new(source: R) -> Self {
+        // `ruzstd` only limits the window size (to 100 MiB) when a decoder
+        // is reset, not when it reads its first frame: start it off with an
+        // empty one so a hostile header can't make it allocate much more
+        let mut frame = FrameDecoder::new();
+        frame.init(&EMPTY_FRAME[..]).unwrap();
+        Decoder {
+            source,
+            frame,
+            in_frame: false,
+        }
+    }
+
+    /// Starts decoding the next frame, returns `false` at the end of
+    /// `source`.
+    fn next_frame(&mut self) -> io::Result<bool> {
+        loop {
+            if self.source.fill_buf()?.is_empty() {
+                return Ok(false);
+            }
+            match self.frame.reset(&mut self.source) {
+                Ok(()) => return Ok(true),
+                Err(FrameDecoderError::ReadFrameHeaderError(ReadFrameHeaderError::SkipFrame {
+                    length,
+                    ..
+                })) => {
+                    let length = length as u64;
+                    let skipped = io::copy(&mut (&mut self.source).take(length), &mut io::sink())?;
+                    if skipped < length {
+                        return Err(invalid("truncated skippable zstd frame"));
+                    }
+                }
+                Err(e) => return Err(invalid(e)),
+            }
+        }
+    }
+
+    /// Checks the content checksum of the frame that just ended, if it had
+    /// one.
+    fn check_frame(&self) -> io::Result<()> {
+        match (
+            self.frame.get_checksum_from_data(),
+            self.frame.get_calculated_checksum(),
+        ) {
+            (Some(expected), Some(actual)) if expected != actual => {
+                Err(invalid("zstd checksum mismatch"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if !self.in_frame {
+                if !self.next_frame()? {
+                    return Ok(0);
+                }
+                self.in_frame = true;
+            }
+            // decoding may stop short of what was asked for, see
+            // `ruzstd::decoding::StreamingDecoder`
+            while self.frame.can_collect() < buf.len() && !self.frame.is_finished() {
+                let needed = buf.len() - self.frame.can_collect();
+                self.frame
+                    .decode_blocks(&mut self.source, BlockDecodingStrategy::UptoBytes(needed))
+                    .map_err(invalid)?;
+            }
+            let n = self.frame.read(buf)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            // finished, and all collected
+            self.check_frame()?;
+            self.in_frame = false;
+        }
+    }
+}
+
+fn invalid<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+
+    fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = vec![];
+        Decoder::new(data).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn log() -> Vec<u8> {
+        let mut log = vec![];
+        for i in 0..5000 {
+            log.extend_from_slice(
+                format!("[PEG_TRACE] Attempting to match rule `expr` at 1:{}\n", i).as_bytes(),
+            );
+        }
+        log
+    }
+
+    #[test]
+    fn round_trip() {
+        let log = log();
+        let compressed = compress_to_vec(&log[..], CompressionLevel::Fastest);
+        assert!(compressed.len() < log.len() / 4);
+        assert_eq!(decompress(&compressed).unwrap(), log);
+        assert_eq!(decompress(&[]).unwrap(), b"");
+    }
+
+    #[test]
+    fn concatenated_and_skippable_frames() {
+        let mut data = compress_to_vec(&b"first\n"[..], CompressionLevel::Fastest);
+        // skippable frame, 3 bytes of user data
+        data.extend_from_slice(&[0x50, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3]);
+        data.extend(compress_to_vec(&b"second\n"[..], CompressionLevel::Fastest));
+        assert_eq!(decompress(&data).unwrap(), b"first\nsecond\n");
+    }
+
+    #[test]
+    fn corrupt_input_is_an_error() {
+        let compressed = compress_to_vec(&log()[..], CompressionLevel::Fastest);
+
+        // truncated frames
+        for len in [1, 4, 5, compressed.len() / 2, compressed.len() - 1] {
+            assert!(decompress(&compressed[..len]).is_err(), "length {}", len);
+        }
+        // truncated skippable frame
+        assert!(decompress(&[0x50, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1]).is_err());
+        // not zstd at all
+        assert!(decompress(b"[PEG_INPUT_START]\n").is_err());
+
+        // a window way past what's allowed (2 TiB), in front of an empty
+        // last block that would otherwise be fine
+        let big_window = [0x28, 0xb5, 0x2f, 0xfd, 0x00, 0xf8, 0x01, 0x00, 0x00];
+        assert!(decompress(&big_window).is_err());
+        assert_eq!(decompress(&EMPTY_FRAME).unwrap(), b"");
+
+        // flipped bits anywhere must not panic, and mostly error out
+        for i in (4..compressed.len()).step_by(compressed.len() / 50) {
+            let mut corrupt = compressed.clone();
+            corrupt[i] ^= 0x5a;
+            decompress(&corrupt).ok();
+        }
+    }
+}